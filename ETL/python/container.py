@@ -1,37 +1,123 @@
 import argparse
+import ast
 from RestrictedPython import compile_restricted, Eval, Guards
 import json
+import sys
+import types
 
-def main(script: str, input: str):
-  byte_code = compile_restricted(
+class StderrPrintCollector:
+  """Sends the script's print calls to stderr, which the runner collects as logs."""
+  def __init__(self, _getattr_=None):
+    self._getattr_ = _getattr_
+
+  def write(self, text):
+    sys.stderr.write(text)
+
+  def __call__(self):
+    return ""
+
+  def _call_print(self, *objects, **kwargs):
+    if kwargs.get("file", None) is None:
+      kwargs["file"] = self
+    else:
+      self._getattr_(kwargs["file"], "write")
+    print(*objects, **kwargs)
+
+def is_module_allowed(name: str, allowed_modules: list):
+  return any(name == module or name.startswith(module + ".") for module in allowed_modules)
+
+def disallowed_import_error(name: str, allowed_modules: list):
+  allowed = ", ".join(allowed_modules) if allowed_modules else "none"
+  return ImportError(f"Import of module '{name}' is not allowed (allowed modules: {allowed})")
+
+def check_imports(script: str, allowed_modules: list):
+  """Rejects the script before it runs if it imports a module outside of the allow-list."""
+  for node in ast.walk(ast.parse(script)):
+    if isinstance(node, ast.Import):
+      names = [alias.name for alias in node.names]
+    elif isinstance(node, ast.ImportFrom):
+      names = ["." * node.level + (node.module or "")]
+    else:
+      continue
+    for name in names:
+      if not is_module_allowed(name, allowed_modules):
+        raise disallowed_import_error(name, allowed_modules)
+
+def check_module(value, allowed_modules: list):
+  """Refuses a module object outside of the allow-list, such as `os` reached as `requests.utils.os`."""
+  if isinstance(value, types.ModuleType) and not is_module_allowed(value.__name__, allowed_modules):
+    raise disallowed_import_error(value.__name__, allowed_modules)
+  return value
+
+def guarded_import(allowed_modules: list):
+  def _import(name, globals=None, locals=None, fromlist=(), level=0):
+    if level != 0 or not is_module_allowed(name, allowed_modules):
+      raise disallowed_import_error("." * level + name, allowed_modules)
+    module = __import__(name, globals, locals, fromlist, level)
+    # `from requests.utils import os` reads `os` off the module without going through `_getattr_`.
+    for attribute in fromlist or ():
+      check_module(getattr(module, attribute, None), allowed_modules)
+    return module
+  return _import
+
+def guarded_getattr(allowed_modules: list):
+  def _getattr_(ob, name, *args, **kwargs):
+    return check_module(Guards.safer_getattr(ob, name, *args, **kwargs), allowed_modules)
+  return _getattr_
+
+def compile_script(script: str, allowed_modules: list):
+  check_imports(script, allowed_modules)
+  return compile_restricted(
       script,
       "<string>",
       "exec"
   )
+
+def main(script: str, input: str, cursor: str, allowed_modules: list):
+  byte_code = compile_script(script, allowed_modules)
   def get_item(ob, key):
     return ob[key]
-  
+
   restricted_globals = {
-    **Guards.safe_builtins,
+    "__builtins__": {
+      **Guards.safe_builtins,
+      "__import__": guarded_import(allowed_modules),
+    },
     "_getiter_": Eval.default_guarded_getiter,
     "_iter_unpack_sequence_": Guards.guarded_iter_unpack_sequence,
-    "_getattr_": Guards.safer_getattr,
+    "_getattr_": guarded_getattr(allowed_modules),
     "_getitem_": get_item,
+    "_print_": StderrPrintCollector,
     "input": json.loads(input),
+    "cursor": json.loads(cursor),
     "result": None
   }
-  
+
   exec(byte_code, restricted_globals)
-  print(json.dumps(restricted_globals["result"]))
-    
-    
-  
-  
+  print(json.dumps({
+    "result": restricted_globals["result"],
+    "cursor": restricted_globals["cursor"]
+  }))
+
+
+
+
 if __name__ == "__main__":
   parser=argparse.ArgumentParser()
 
   parser.add_argument("--script", help="Script to run", type=str)
-  parser.add_argument("--input", help="Input as json string", type=str)
-  
+  parser.add_argument("--input", help="Input as json string", type=str, default="{}")
+  parser.add_argument("--cursor", help="Cursor returned by the previous run as json string", type=str, default="null")
+  parser.add_argument("--allowed-modules", help="Importable modules as json list", type=str, default="[]")
+  parser.add_argument("--validate", help="Only check that the script compiles and imports allowed modules", action="store_true")
+
   args=parser.parse_args()
-  main(args.script, args.input)
\ No newline at end of file
+  allowed_modules = json.loads(args.allowed_modules)
+  if args.validate:
+    try:
+      compile_script(args.script, allowed_modules)
+    except (ImportError, SyntaxError) as e:
+      sys.stderr.write(f"{type(e).__name__}: {e}\n")
+      sys.exit(1)
+  else:
+    main(args.script, args.input, args.cursor, allowed_modules)
@@ -0,0 +1,66 @@
+import contextlib
+import io
+import json
+import logging
+import os
+import unittest
+
+from container import guarded_getattr, guarded_import, main
+
+# `logging` imports `os` and `sys`, so both can be reached from it when only `logging` is allowed.
+ALLOWED_MODULES = ["logging", "json"]
+
+def run(script: str):
+  stdout = io.StringIO()
+  with contextlib.redirect_stdout(stdout):
+    main(script, "{}", "null", ALLOWED_MODULES)
+  return json.loads(stdout.getvalue())["result"]
+
+class GuardedGetattrTest(unittest.TestCase):
+  def setUp(self):
+    self.getattr = guarded_getattr(ALLOWED_MODULES)
+
+  def test_refuses_modules_outside_of_the_allow_list(self):
+    with self.assertRaises(ImportError):
+      self.getattr(logging, "os")
+
+  def test_returns_allowed_submodules(self):
+    self.assertIs(self.getattr(json, "decoder"), json.decoder)
+
+  def test_returns_other_attributes(self):
+    self.assertIs(self.getattr(logging, "getLogger"), logging.getLogger)
+    self.assertEqual(self.getattr(os, "sep"), os.sep)
+
+class GuardedImportTest(unittest.TestCase):
+  def setUp(self):
+    self.import_ = guarded_import(ALLOWED_MODULES)
+
+  def test_refuses_modules_outside_of_the_allow_list(self):
+    with self.assertRaises(ImportError):
+      self.import_("os")
+
+  def test_refuses_imported_names_that_are_disallowed_modules(self):
+    with self.assertRaises(ImportError):
+      self.import_("logging", fromlist=("os",))
+
+  def test_imports_allowed_names(self):
+    self.assertIs(self.import_("logging", fromlist=("getLogger",)), logging)
+
+class ScriptTest(unittest.TestCase):
+  def test_blocks_attribute_access_to_disallowed_modules(self):
+    with self.assertRaises(ImportError):
+      run("import logging\nresult = logging.os.getcwd()")
+
+  def test_blocks_nested_attribute_access_to_disallowed_modules(self):
+    with self.assertRaises(ImportError):
+      run("import logging.handlers\nresult = logging.handlers.os.getcwd()")
+
+  def test_blocks_from_imports_of_disallowed_modules(self):
+    with self.assertRaises(ImportError):
+      run("from logging import os\nresult = os.getcwd()")
+
+  def test_runs_scripts_using_allowed_modules(self):
+    self.assertEqual(run("import json\nresult = json.loads('[1]')"), [1])
+
+if __name__ == "__main__":
+  unittest.main()
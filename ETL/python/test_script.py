@@ -2,7 +2,6 @@ import requests
 import concurrent.futures
 
 
-input = globals()["input"]
 response = requests.get(input["url"], headers={"X-CLIENT-CERT": input["SECRET"], "Content-Type": "application/json"})
 references = response.json()["data"]["asset_references"]
 with concurrent.futures.ThreadPoolExecutor(max_workers=5) as executor:
@@ -10,5 +9,4 @@ with concurrent.futures.ThreadPoolExecutor(max_workers=5) as executor:
     results = [future.result() for future in concurrent.futures.as_completed(futures)]
     assets = [result.json()["data"] for result in results]
 
-result = assets
-
+result = assets
\ No newline at end of file
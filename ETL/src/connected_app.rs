@@ -1,4 +1,5 @@
 pub mod connected_app_model;
 pub mod connected_app_core;
+pub mod connected_app_limiter;
 pub mod connected_app_repository;
 pub mod connected_app_web_api;
\ No newline at end of file
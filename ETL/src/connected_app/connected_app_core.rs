@@ -1,22 +1,236 @@
 use crate::connected_app::{
     connected_app_model::ConnectedApp, connected_app_repository::{ConnectedAppRepository, CreateConnectedAppParams},
 };
-use crate::shared::errors::Error;
+use crate::connected_app::connected_app_limiter::{ConnectedAppLimiter, ConnectedAppPermit};
+use crate::connected_app::connected_app_model::{
+    ConnectedAppApiKey, ConnectedAppDetails, ConnectedAppUsage, CreateConnectedAppApiKeyParams,
+    CreatedConnectedAppApiKey,
+};
+use crate::connected_app::connected_app_repository::{
+    ConnectedAppFilter, UpdateConnectedAppParams,
+};
+use crate::shared::bus::{Commands, Publish, TopicIds};
+use crate::shared::errors::{Error, FieldErrors};
+use crate::shared::merge_struct::Merge;
+use crate::shared::pagination::{Page, PageRequest};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Characters of a key kept as its prefix: `heutl_` and 8 random hex digits.
+const API_KEY_PREFIX_LENGTH: usize = 14;
 
 pub struct ConnectedAppCore<'a> {
     pub connected_app_repository: Box<dyn ConnectedAppRepository + 'a>,
+    pub connected_app_limiter: ConnectedAppLimiter,
+    pub publish: Publish,
+}
+
+fn validate_limits(
+    rate_limit_per_minute: Option<u32>,
+    max_concurrent_calls: Option<u32>,
+) -> Result<(), Error> {
+    if rate_limit_per_minute == Some(0) {
+        return Err(Error::BadRequestError(
+            "rate_limit_per_minute must be greater than 0".to_string(),
+        ));
+    }
+    if max_concurrent_calls == Some(0) {
+        return Err(Error::BadRequestError(
+            "max_concurrent_calls must be greater than 0".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn generate_api_key() -> String {
+    format!("heutl_{}", hex::encode(rand::random::<[u8; 32]>()))
 }
 
 impl<'a> ConnectedAppCore<'a> {
     pub async fn create_connected_app(&self, params: &CreateConnectedAppParams) -> Result<ConnectedApp, Error> {
-        return self.connected_app_repository.create_connected_app(params).await;
+        let mut field_errors = FieldErrors::default();
+        field_errors.check_id(&params.id);
+        if params.name.trim().is_empty() {
+            field_errors.add("name", "Can't be empty".to_string());
+        }
+        field_errors.result()?;
+        validate_limits(params.rate_limit_per_minute, params.max_concurrent_calls)?;
+        let id = params
+            .id
+            .clone()
+            .unwrap_or_else(|| Uuid::now_v7().to_string());
+        let connected_app = self
+            .connected_app_repository
+            .create_connected_app(&id, params)
+            .await?;
+        (self.publish)(
+            Commands::ConnectedAppCreated {
+                connected_app: connected_app.clone(),
+            },
+            Some(TopicIds::ConnectedAppCreated),
+        );
+        Ok(connected_app)
     }
 
-    pub async fn get_connected_app(&self, id: &String) -> Result<ConnectedApp, Error> {
+    pub async fn get_connected_app(&self, id: &str) -> Result<ConnectedApp, Error> {
         return self.connected_app_repository.get_connected_app(id).await;
     }
 
+    pub async fn get_connected_app_details(
+        &self,
+        id: &str,
+    ) -> Result<ConnectedAppDetails, Error> {
+        return self
+            .connected_app_repository
+            .get_connected_app_details(id)
+            .await;
+    }
+
     pub async fn get_all_connected_apps(&self) -> Result<Vec<ConnectedApp>, Error> {
         return self.connected_app_repository.get_all_connected_apps().await;
     }
+
+    pub async fn get_connected_apps(
+        &self,
+        filter: &ConnectedAppFilter,
+        page: &PageRequest,
+    ) -> Result<Page<ConnectedAppDetails>, Error> {
+        return self
+            .connected_app_repository
+            .get_connected_apps(filter, page)
+            .await;
+    }
+
+    pub async fn update_connected_app(
+        &self,
+        id: &str,
+        params: &UpdateConnectedAppParams,
+    ) -> Result<ConnectedApp, Error> {
+        let connected_app = self.get_connected_app(id).await?.merge(params.clone());
+        validate_limits(
+            connected_app.rate_limit_per_minute,
+            connected_app.max_concurrent_calls,
+        )?;
+        self.connected_app_repository
+            .update_connected_app(&connected_app)
+            .await?;
+        (self.publish)(
+            Commands::ConnectedAppUpdated {
+                connected_app: connected_app.clone(),
+            },
+            Some(TopicIds::ConnectedAppUpdated),
+        );
+        Ok(connected_app)
+    }
+
+    /// Waits until the app limits allow one more poll or delivery. The permit is given back
+    /// when dropped.
+    pub async fn acquire_call_permit(
+        &self,
+        connected_app_id: &str,
+    ) -> Result<ConnectedAppPermit, Error> {
+        let connected_app = self.get_connected_app(connected_app_id).await?;
+        return Ok(self.connected_app_limiter.acquire(&connected_app).await);
+    }
+
+    pub async fn get_all_connected_app_usages(&self) -> Result<Vec<ConnectedAppUsage>, Error> {
+        let connected_apps = self.get_all_connected_apps().await?;
+        Ok(connected_apps
+            .into_iter()
+            .map(|connected_app| {
+                let (queued_calls, in_flight_calls) =
+                    self.connected_app_limiter.usage(&connected_app.id);
+                ConnectedAppUsage {
+                    connected_app_id: connected_app.id,
+                    connected_app_name: connected_app.name,
+                    rate_limit_per_minute: connected_app.rate_limit_per_minute,
+                    max_concurrent_calls: connected_app.max_concurrent_calls,
+                    queued_calls,
+                    in_flight_calls,
+                }
+            })
+            .collect())
+    }
+
+    pub async fn create_api_key(
+        &self,
+        connected_app_id: &str,
+        params: &CreateConnectedAppApiKeyParams,
+    ) -> Result<CreatedConnectedAppApiKey, Error> {
+        self.get_connected_app(connected_app_id).await?;
+        let key = generate_api_key();
+        let api_key = ConnectedAppApiKey {
+            id: Uuid::now_v7().to_string(),
+            connected_app_id: connected_app_id.to_string(),
+            prefix: key[..API_KEY_PREFIX_LENGTH].to_string(),
+            role: params.role,
+            created_at: Utc::now().timestamp(),
+            revoked_at: None,
+        };
+        self.connected_app_repository
+            .create_api_key(&api_key, &hash_api_key(&key))
+            .await?;
+        (self.publish)(
+            Commands::ConnectedAppApiKeyCreated {
+                api_key: api_key.clone(),
+            },
+            Some(TopicIds::ConnectedAppApiKeyCreated),
+        );
+        Ok(CreatedConnectedAppApiKey { api_key, key })
+    }
+
+    pub async fn get_api_keys(
+        &self,
+        connected_app_id: &str,
+    ) -> Result<Vec<ConnectedAppApiKey>, Error> {
+        self.get_connected_app(connected_app_id).await?;
+        return self
+            .connected_app_repository
+            .get_api_keys(connected_app_id)
+            .await;
+    }
+
+    pub async fn revoke_api_key(
+        &self,
+        connected_app_id: &str,
+        api_key_id: &str,
+    ) -> Result<(), Error> {
+        let revoked = self
+            .connected_app_repository
+            .revoke_api_key(connected_app_id, api_key_id, Utc::now().timestamp())
+            .await?;
+        if revoked == 0 {
+            return Err(Error::NotFoundError(format!(
+                "No active API key {} for connected app {}",
+                api_key_id, connected_app_id
+            )));
+        }
+        (self.publish)(
+            Commands::ConnectedAppApiKeyRevoked {
+                connected_app_id: connected_app_id.to_string(),
+                api_key_id: api_key_id.to_string(),
+            },
+            Some(TopicIds::ConnectedAppApiKeyRevoked),
+        );
+        Ok(())
+    }
+
+    /// Key matching this one, unless it is unknown or revoked.
+    pub async fn authenticate(&self, key: &str) -> Result<ConnectedAppApiKey, Error> {
+        return match self
+            .connected_app_repository
+            .get_active_api_key(&hash_api_key(key))
+            .await
+        {
+            Err(Error::NotFoundError(_)) => {
+                Err(Error::UnauthorizedError("Invalid API key".to_string()))
+            }
+            result => result,
+        };
+    }
 }
@@ -0,0 +1,313 @@
+use crate::connected_app::connected_app_model::ConnectedApp;
+use crate::shared::metrics::set_connected_app_calls;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+struct CallCounters {
+    connected_app_id: String,
+    queued: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+impl CallCounters {
+    fn new(connected_app_id: &str) -> Self {
+        Self {
+            connected_app_id: connected_app_id.to_string(),
+            queued: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn publish(&self) {
+        set_connected_app_calls(
+            &self.connected_app_id,
+            self.queued.load(Ordering::Relaxed),
+            self.in_flight.load(Ordering::Relaxed),
+        );
+    }
+}
+
+/// Permits of an app without concurrency cap.
+const UNLIMITED_PERMITS: usize = Semaphore::MAX_PERMITS;
+
+fn permits(max_concurrent_calls: Option<u32>) -> usize {
+    max_concurrent_calls.map_or(UNLIMITED_PERMITS, |max_concurrent_calls| {
+        max_concurrent_calls as usize
+    })
+}
+
+struct ThrottleState {
+    rate_limit_per_minute: Option<u32>,
+    max_concurrent_calls: Option<u32>,
+    /// Earliest start of the next call, calls being spread evenly over the minute.
+    next_call_at: Instant,
+    /// Permits still held by calls that exceed a lowered cap, forgotten as they are given back.
+    excess_permits: usize,
+}
+
+/// Limits of a connected app. They are updated in place, so that the calls already holding a
+/// permit keep counting against a changed cap.
+struct Throttle {
+    state: Mutex<ThrottleState>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl Throttle {
+    fn new(connected_app: &ConnectedApp) -> Self {
+        Self {
+            state: Mutex::new(ThrottleState {
+                rate_limit_per_minute: connected_app.rate_limit_per_minute,
+                max_concurrent_calls: connected_app.max_concurrent_calls,
+                next_call_at: Instant::now(),
+                excess_permits: 0,
+            }),
+            semaphore: Arc::new(Semaphore::new(permits(connected_app.max_concurrent_calls))),
+        }
+    }
+
+    fn update(&self, connected_app: &ConnectedApp) {
+        let mut state = self.state.lock().unwrap();
+        state.rate_limit_per_minute = connected_app.rate_limit_per_minute;
+        if state.max_concurrent_calls == connected_app.max_concurrent_calls {
+            return;
+        }
+        let current_permits = permits(state.max_concurrent_calls);
+        let new_permits = permits(connected_app.max_concurrent_calls);
+        if new_permits > current_permits {
+            let added_permits = new_permits - current_permits;
+            let kept_permits = added_permits.min(state.excess_permits);
+            state.excess_permits -= kept_permits;
+            self.semaphore.add_permits(added_permits - kept_permits);
+        } else {
+            let removed_permits = current_permits - new_permits;
+            let forgotten_permits = self.semaphore.forget_permits(removed_permits);
+            state.excess_permits += removed_permits - forgotten_permits;
+        }
+        state.max_concurrent_calls = connected_app.max_concurrent_calls;
+    }
+
+    /// Start of the next call allowed by the rate limit, `None` without limit.
+    fn reserve_call(&self) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        let rate_limit_per_minute = state.rate_limit_per_minute?;
+        let call_at = state.next_call_at.max(Instant::now());
+        state.next_call_at = call_at + Duration::from_secs(60) / rate_limit_per_minute;
+        Some(call_at)
+    }
+
+    fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut state = self.state.lock().unwrap();
+        if state.excess_permits > 0 {
+            state.excess_permits -= 1;
+            permit.forget();
+        }
+    }
+}
+
+/// Counts a call as queued until it is dropped, including when the waiting future is.
+struct QueuedCall(Arc<CallCounters>);
+
+impl Drop for QueuedCall {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::Relaxed);
+        self.0.publish();
+    }
+}
+
+/// Right to call a connected app, held for the whole poll or delivery.
+pub struct ConnectedAppPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    throttle: Arc<Throttle>,
+    counters: Arc<CallCounters>,
+}
+
+impl Drop for ConnectedAppPermit {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.throttle.release(permit);
+        }
+        self.counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.counters.publish();
+    }
+}
+
+/// Makes the polls and deliveries of this instance wait for the rate limit and concurrency cap
+/// of the connected app they target. Limit changes apply to the calls started afterwards, the
+/// calls still running counting against a lowered cap.
+///
+/// Limits are enforced per process: every instance sharing the database lets the app take its
+/// full rate and cap.
+#[derive(Default)]
+pub struct ConnectedAppLimiter {
+    throttles: Mutex<HashMap<String, Arc<Throttle>>>,
+    counters: Mutex<HashMap<String, Arc<CallCounters>>>,
+}
+
+impl ConnectedAppLimiter {
+    pub async fn acquire(&self, connected_app: &ConnectedApp) -> ConnectedAppPermit {
+        let throttle = self.throttle(connected_app);
+        let counters = self.counters(&connected_app.id);
+        counters.queued.fetch_add(1, Ordering::Relaxed);
+        counters.publish();
+        let queued_call = QueuedCall(Arc::clone(&counters));
+
+        // Calls waiting for their slot don't hold a permit other calls could run with.
+        if let Some(call_at) = throttle.reserve_call() {
+            tokio::time::sleep_until(call_at).await;
+        }
+        // The semaphore is never closed, so acquiring only fails if the throttle is dropped.
+        let permit = Arc::clone(&throttle.semaphore).acquire_owned().await.ok();
+
+        drop(queued_call);
+        counters.in_flight.fetch_add(1, Ordering::Relaxed);
+        counters.publish();
+        ConnectedAppPermit {
+            permit,
+            throttle,
+            counters,
+        }
+    }
+
+    /// Calls of the app waiting for a permit and calls holding one.
+    pub fn usage(&self, connected_app_id: &str) -> (usize, usize) {
+        return match self.counters.lock().unwrap().get(connected_app_id) {
+            Some(counters) => (
+                counters.queued.load(Ordering::Relaxed),
+                counters.in_flight.load(Ordering::Relaxed),
+            ),
+            None => (0, 0),
+        };
+    }
+
+    fn throttle(&self, connected_app: &ConnectedApp) -> Arc<Throttle> {
+        let mut throttles = self.throttles.lock().unwrap();
+        if let Some(throttle) = throttles.get(&connected_app.id) {
+            throttle.update(connected_app);
+            return Arc::clone(throttle);
+        }
+        let throttle = Arc::new(Throttle::new(connected_app));
+        throttles.insert(connected_app.id.clone(), Arc::clone(&throttle));
+        throttle
+    }
+
+    fn counters(&self, connected_app_id: &str) -> Arc<CallCounters> {
+        return Arc::clone(
+            self.counters
+                .lock()
+                .unwrap()
+                .entry(connected_app_id.to_string())
+                .or_insert_with(|| Arc::new(CallCounters::new(connected_app_id))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Long enough for a call that can go to be through.
+    const SETTLE: Duration = Duration::from_millis(50);
+
+    fn connected_app(
+        rate_limit_per_minute: Option<u32>,
+        max_concurrent_calls: Option<u32>,
+    ) -> ConnectedApp {
+        ConnectedApp {
+            id: "connected-app".to_string(),
+            name: "Connected app".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            rate_limit_per_minute,
+            max_concurrent_calls,
+        }
+    }
+
+    /// Starts acquiring a permit, giving it time to go through.
+    async fn start_call(
+        limiter: &Arc<ConnectedAppLimiter>,
+        connected_app: ConnectedApp,
+    ) -> tokio::task::JoinHandle<ConnectedAppPermit> {
+        let limiter = Arc::clone(limiter);
+        let call = tokio::spawn(async move { limiter.acquire(&connected_app).await });
+        tokio::time::sleep(SETTLE).await;
+        call
+    }
+
+    #[tokio::test]
+    async fn calls_past_the_cap_wait_for_a_permit() {
+        let limiter = Arc::new(ConnectedAppLimiter::default());
+        let first = limiter.acquire(&connected_app(None, Some(2))).await;
+        let _second = limiter.acquire(&connected_app(None, Some(2))).await;
+
+        let third = start_call(&limiter, connected_app(None, Some(2))).await;
+        assert!(!third.is_finished());
+        assert_eq!(limiter.usage("connected-app"), (1, 2));
+
+        drop(first);
+        let _third = third.await.unwrap();
+        assert_eq!(limiter.usage("connected-app"), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn lowering_the_cap_counts_the_running_calls() {
+        let limiter = Arc::new(ConnectedAppLimiter::default());
+        let mut running = vec![];
+        for _ in 0..3 {
+            running.push(limiter.acquire(&connected_app(None, Some(3))).await);
+        }
+
+        let call = start_call(&limiter, connected_app(None, Some(1))).await;
+        running.pop();
+        running.pop();
+        tokio::time::sleep(SETTLE).await;
+        assert!(!call.is_finished());
+
+        running.pop();
+        let _call = call.await.unwrap();
+        let next_call = start_call(&limiter, connected_app(None, Some(1))).await;
+        assert!(!next_call.is_finished());
+    }
+
+    #[tokio::test]
+    async fn raising_the_cap_lets_waiting_calls_through() {
+        let limiter = Arc::new(ConnectedAppLimiter::default());
+        let _running = limiter.acquire(&connected_app(None, Some(1))).await;
+        let call = start_call(&limiter, connected_app(None, Some(1))).await;
+        assert!(!call.is_finished());
+
+        let _raised = limiter.acquire(&connected_app(None, Some(3))).await;
+        let _call = call.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn capping_an_unlimited_app_counts_the_running_calls() {
+        let limiter = Arc::new(ConnectedAppLimiter::default());
+        let running = limiter.acquire(&connected_app(None, None)).await;
+
+        let call = start_call(&limiter, connected_app(None, Some(1))).await;
+        assert!(!call.is_finished());
+
+        drop(running);
+        let _call = call.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn calls_waiting_for_their_rate_limit_slot_hold_no_permit() {
+        let limiter = Arc::new(ConnectedAppLimiter::default());
+        // One call every 200ms.
+        let app = connected_app(Some(300), Some(1));
+        drop(limiter.acquire(&app).await);
+
+        let call = start_call(&limiter, app.clone()).await;
+        assert!(!call.is_finished());
+        let throttle = limiter.throttle(&app);
+        assert_eq!(throttle.semaphore.available_permits(), 1);
+
+        let _call = call.await.unwrap();
+        assert_eq!(throttle.semaphore.available_permits(), 0);
+    }
+}
@@ -1,10 +1,127 @@
 use serde::{Deserialize, Serialize};
 
+use crate::connected_app::connected_app_repository::UpdateConnectedAppParams;
+use crate::shared::merge_struct::Merge;
+use crate::shared::pagination::{Paginated, SortField, SortValue};
+use chrono::Utc;
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, Eq, ToSchema)]
 pub struct ConnectedApp {
     pub id: String,
     pub name: String,
     pub created_at: i64,
     pub updated_at: i64,
-}
\ No newline at end of file
+    /// Polls and deliveries started per minute across all the sharings and subscriptions of
+    /// the app, `None` for no limit. Each HEUTL instance applies it on its own.
+    pub rate_limit_per_minute: Option<u32>,
+    /// Polls and deliveries running at once for the app, `None` for no limit. Each HEUTL
+    /// instance applies it on its own.
+    pub max_concurrent_calls: Option<u32>,
+}
+
+impl Paginated for ConnectedApp {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn sort_value(&self, sort: SortField) -> SortValue {
+        match sort {
+            SortField::CreatedAt => SortValue::Integer(self.created_at),
+            SortField::UpdatedAt => SortValue::Integer(self.updated_at),
+            SortField::Name => SortValue::Text(self.name.clone()),
+        }
+    }
+}
+
+/// Connected app with the number of entity sharings it owns and of entity subscriptions
+/// delivering to it.
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, ToSchema)]
+pub struct ConnectedAppDetails {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub connected_app: ConnectedApp,
+    pub entity_sharing_count: i64,
+    pub entity_subscription_count: i64,
+}
+
+impl Paginated for ConnectedAppDetails {
+    fn id(&self) -> &str {
+        self.connected_app.id()
+    }
+
+    fn sort_value(&self, sort: SortField) -> SortValue {
+        self.connected_app.sort_value(sort)
+    }
+}
+
+/// Calls of this instance to a connected app, waiting on or running within its limits.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ConnectedAppUsage {
+    pub connected_app_id: String,
+    pub connected_app_name: String,
+    pub rate_limit_per_minute: Option<u32>,
+    pub max_concurrent_calls: Option<u32>,
+    pub queued_calls: usize,
+    pub in_flight_calls: usize,
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum Role {
+    /// Manages every resource.
+    Admin,
+    /// Manages the connected app the key was issued to, with its sharings and subscriptions.
+    #[default]
+    Owner,
+    /// Reads every resource and changes none.
+    Auditor,
+}
+
+/// Key a connected app authenticates with, sent as `Authorization: Bearer <key>`. Only a hash
+/// of the key is stored, an app rotates its key by creating a new one and revoking the old one.
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, ToSchema)]
+pub struct ConnectedAppApiKey {
+    pub id: String,
+    pub connected_app_id: String,
+    /// First characters of the key, to tell the keys of an app apart.
+    pub prefix: String,
+    pub role: Role,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+/// Newly created key, the only time the key itself is returned.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct CreatedConnectedAppApiKey {
+    #[serde(flatten)]
+    pub api_key: ConnectedAppApiKey,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct CreateConnectedAppApiKeyParams {
+    /// Only admins issue admin and auditor keys. Defaults to `owner`.
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl Merge<UpdateConnectedAppParams> for ConnectedApp {
+    fn merge(self, other: UpdateConnectedAppParams) -> Self {
+        let mut merged = self.clone();
+        if let Some(name) = other.name {
+            merged.name = name;
+        }
+        if let Some(rate_limit_per_minute) = other.rate_limit_per_minute {
+            merged.rate_limit_per_minute = rate_limit_per_minute;
+        }
+        if let Some(max_concurrent_calls) = other.max_concurrent_calls {
+            merged.max_concurrent_calls = max_concurrent_calls;
+        }
+        merged.updated_at = Utc::now().timestamp();
+        merged
+    }
+}
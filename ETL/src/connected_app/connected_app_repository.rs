@@ -1,19 +1,79 @@
-use crate::connected_app::connected_app_model::ConnectedApp;
+use crate::connected_app::connected_app_model::{
+    ConnectedApp, ConnectedAppApiKey, ConnectedAppDetails,
+};
 use crate::shared::errors::Error;
+use crate::shared::merge_struct::deserialize_nullable;
+use crate::shared::pagination::{Page, PageRequest};
 use async_trait::async_trait;   
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 pub mod connected_app_sqlite_repository;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
 pub struct CreateConnectedAppParams {
-    pub id: String,
+    /// Generated as a UUIDv7 when left out.
+    #[serde(default)]
+    pub id: Option<String>,
     pub name: String,
+    #[serde(default)]
+    pub rate_limit_per_minute: Option<u32>,
+    #[serde(default)]
+    pub max_concurrent_calls: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
+pub struct UpdateConnectedAppParams {
+    pub name: Option<String>,
+    /// `null` lifts the limit, leaving the field out keeps the current one.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<u32>)]
+    pub rate_limit_per_minute: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<u32>)]
+    pub max_concurrent_calls: Option<Option<u32>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConnectedAppFilter {
+    /// Part of the name, case insensitive.
+    pub name: Option<String>,
+    /// Unix timestamp the apps were last updated at or after.
+    pub updated_since: Option<i64>,
 }
 
 #[async_trait]
 pub trait ConnectedAppRepository: Send + Sync {
-    async fn create_connected_app(&self, params: &CreateConnectedAppParams) -> Result<ConnectedApp, Error>;
-    async fn get_connected_app(&self, id: &String) -> Result<ConnectedApp, Error>;
+    async fn create_connected_app(
+        &self,
+        id: &str,
+        params: &CreateConnectedAppParams,
+    ) -> Result<ConnectedApp, Error>;
+    async fn get_connected_app(&self, id: &str) -> Result<ConnectedApp, Error>;
+    async fn get_connected_app_details(&self, id: &str) -> Result<ConnectedAppDetails, Error>;
     async fn get_all_connected_apps(&self) -> Result<Vec<ConnectedApp>, Error>;
+    async fn get_connected_apps(
+        &self,
+        filter: &ConnectedAppFilter,
+        page: &PageRequest,
+    ) -> Result<Page<ConnectedAppDetails>, Error>;
+    async fn update_connected_app(&self, connected_app: &ConnectedApp) -> Result<u64, Error>;
+    async fn create_api_key(
+        &self,
+        api_key: &ConnectedAppApiKey,
+        key_hash: &str,
+    ) -> Result<(), Error>;
+    async fn get_api_keys(
+        &self,
+        connected_app_id: &str,
+    ) -> Result<Vec<ConnectedAppApiKey>, Error>;
+    /// Key with this hash, unless it was revoked.
+    async fn get_active_api_key(&self, key_hash: &str) -> Result<ConnectedAppApiKey, Error>;
+    async fn revoke_api_key(
+        &self,
+        connected_app_id: &str,
+        api_key_id: &str,
+        revoked_at: i64,
+    ) -> Result<u64, Error>;
 }
 
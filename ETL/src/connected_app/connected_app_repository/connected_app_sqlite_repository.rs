@@ -1,11 +1,21 @@
-use crate::connected_app::connected_app_model::ConnectedApp;
+use crate::connected_app::connected_app_model::{
+    ConnectedApp, ConnectedAppApiKey, ConnectedAppDetails,
+};
 use crate::connected_app::connected_app_repository::{
-    ConnectedAppRepository, CreateConnectedAppParams,
+    ConnectedAppFilter, ConnectedAppRepository, CreateConnectedAppParams,
 };
 use crate::shared::errors::Error;
+use crate::shared::pagination::{Page, PageRequest};
 use async_trait::async_trait;
 use chrono::Utc;
-use sqlx::sqlite::SqlitePool;
+use sqlx::QueryBuilder;
+use sqlx::sqlite::{Sqlite, SqlitePool};
+
+const CONNECTED_APP_DETAILS_SELECT: &str = "SELECT *,
+        (SELECT COUNT(*) FROM entity_sharings WHERE connected_app_id = connected_apps.id) AS entity_sharing_count,
+        (SELECT COUNT(*) FROM entity_subscriptions WHERE connected_app_id = connected_apps.id) AS entity_subscription_count
+        FROM connected_apps";
+
 pub struct ConnectedAppSQLiteRepository<'a> {
     pub pool: &'a SqlitePool,
 }
@@ -14,28 +24,34 @@ pub struct ConnectedAppSQLiteRepository<'a> {
 impl<'a> ConnectedAppRepository for ConnectedAppSQLiteRepository<'a> {
     async fn create_connected_app(
         &self,
+        id: &str,
         params: &CreateConnectedAppParams,
     ) -> Result<ConnectedApp, Error> {
         let connected_app = ConnectedApp {
-            id: params.id.clone(),
+            id: id.to_string(),
             name: params.name.clone(),
             created_at: Utc::now().timestamp(),
             updated_at: Utc::now().timestamp(),
+            rate_limit_per_minute: params.rate_limit_per_minute,
+            max_concurrent_calls: params.max_concurrent_calls,
         };
         sqlx::query(
-            "INSERT INTO connected_apps (id, name, created_at, updated_at) VALUES ($1, $2, $3, $4)",
+            "INSERT INTO connected_apps (id, name, created_at, updated_at, rate_limit_per_minute, max_concurrent_calls)
+        VALUES ($1, $2, $3, $4, $5, $6)",
         )
         .bind(&connected_app.id)
         .bind(&connected_app.name)
-        .bind(&connected_app.created_at)
-        .bind(&connected_app.updated_at)
+        .bind(connected_app.created_at)
+        .bind(connected_app.updated_at)
+        .bind(connected_app.rate_limit_per_minute)
+        .bind(connected_app.max_concurrent_calls)
         .execute(self.pool)
         .await?;
 
         Ok(connected_app)
     }
 
-    async fn get_connected_app(&self, id: &String) -> Result<ConnectedApp, Error> {
+    async fn get_connected_app(&self, id: &str) -> Result<ConnectedApp, Error> {
         let connected_app: ConnectedApp = sqlx::query_as(
             "SELECT * FROM connected_apps WHERE id = $1 LIMIT 1",
         )
@@ -45,6 +61,17 @@ impl<'a> ConnectedAppRepository for ConnectedAppSQLiteRepository<'a> {
         Ok(connected_app)
     }
 
+    async fn get_connected_app_details(&self, id: &str) -> Result<ConnectedAppDetails, Error> {
+        let connected_app: ConnectedAppDetails = sqlx::query_as(&format!(
+            "{} WHERE id = $1 LIMIT 1",
+            CONNECTED_APP_DETAILS_SELECT
+        ))
+        .bind(id)
+        .fetch_one(self.pool)
+        .await?;
+        Ok(connected_app)
+    }
+
     async fn get_all_connected_apps(&self) -> Result<Vec<ConnectedApp>, Error> {
         let connected_apps: Vec<ConnectedApp> = sqlx::query_as(
             "SELECT * FROM connected_apps",
@@ -53,4 +80,105 @@ impl<'a> ConnectedAppRepository for ConnectedAppSQLiteRepository<'a> {
         .await?;
         Ok(connected_apps)
     }
+
+    async fn get_connected_apps(
+        &self,
+        filter: &ConnectedAppFilter,
+        page: &PageRequest,
+    ) -> Result<Page<ConnectedAppDetails>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(CONNECTED_APP_DETAILS_SELECT);
+        query.push(" WHERE 1 = 1");
+        if let Some(name) = &filter.name {
+            query
+                .push(" AND instr(lower(name), lower(")
+                .push_bind(name)
+                .push(")) > 0");
+        }
+        if let Some(updated_since) = filter.updated_since {
+            query.push(" AND updated_at >= ").push_bind(updated_since);
+        }
+        page.push_to(&mut query);
+        let connected_apps: Vec<ConnectedAppDetails> =
+            query.build_query_as().fetch_all(self.pool).await?;
+        Ok(page.page_of(connected_apps))
+    }
+
+    async fn update_connected_app(&self, connected_app: &ConnectedApp) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE connected_apps SET name = $1, updated_at = $2, rate_limit_per_minute = $3,
+        max_concurrent_calls = $4 WHERE id = $5",
+        )
+        .bind(&connected_app.name)
+        .bind(connected_app.updated_at)
+        .bind(connected_app.rate_limit_per_minute)
+        .bind(connected_app.max_concurrent_calls)
+        .bind(&connected_app.id)
+        .execute(self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn create_api_key(
+        &self,
+        api_key: &ConnectedAppApiKey,
+        key_hash: &str,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO connected_app_api_keys (id, connected_app_id, key_hash, prefix, role, created_at,
+        revoked_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&api_key.id)
+        .bind(&api_key.connected_app_id)
+        .bind(key_hash)
+        .bind(&api_key.prefix)
+        .bind(api_key.role)
+        .bind(api_key.created_at)
+        .bind(api_key.revoked_at)
+        .execute(self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_api_keys(
+        &self,
+        connected_app_id: &str,
+    ) -> Result<Vec<ConnectedAppApiKey>, Error> {
+        let api_keys: Vec<ConnectedAppApiKey> = sqlx::query_as(
+            "SELECT id, connected_app_id, prefix, role, created_at, revoked_at FROM connected_app_api_keys
+        WHERE connected_app_id = $1 ORDER BY created_at",
+        )
+        .bind(connected_app_id)
+        .fetch_all(self.pool)
+        .await?;
+        Ok(api_keys)
+    }
+
+    async fn get_active_api_key(&self, key_hash: &str) -> Result<ConnectedAppApiKey, Error> {
+        let api_key: ConnectedAppApiKey = sqlx::query_as(
+            "SELECT id, connected_app_id, prefix, role, created_at, revoked_at FROM connected_app_api_keys
+        WHERE key_hash = $1 AND revoked_at IS NULL LIMIT 1",
+        )
+        .bind(key_hash)
+        .fetch_one(self.pool)
+        .await?;
+        Ok(api_key)
+    }
+
+    async fn revoke_api_key(
+        &self,
+        connected_app_id: &str,
+        api_key_id: &str,
+        revoked_at: i64,
+    ) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE connected_app_api_keys SET revoked_at = $1
+        WHERE id = $2 AND connected_app_id = $3 AND revoked_at IS NULL",
+        )
+        .bind(revoked_at)
+        .bind(api_key_id)
+        .bind(connected_app_id)
+        .execute(self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
 }
@@ -1,27 +1,277 @@
-use axum::{Json, debug_handler, extract::State, response::IntoResponse};
+use axum::{
+    Json, debug_handler,
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
 use reqwest::StatusCode;
 use crate::services::web_api::WebAppCores;
-use crate::connected_app::connected_app_repository::CreateConnectedAppParams;
+use crate::connected_app::connected_app_repository::{
+    ConnectedAppFilter, CreateConnectedAppParams, UpdateConnectedAppParams,
+};
+use crate::connected_app::connected_app_model::{
+    ConnectedApp, ConnectedAppApiKey, ConnectedAppDetails, ConnectedAppUsage,
+    CreateConnectedAppApiKeyParams, CreatedConnectedAppApiKey, Role,
+};
+use crate::services::auth::Caller;
+use crate::services::policy::{
+    require_admin, require_api_key_manager, require_connected_app_owner,
+    require_connected_app_reader,
+};
+use crate::shared::errors::{Error, ErrorBody};
+use crate::shared::pagination::{Page, PageQuery};
 
+#[utoipa::path(
+    get,
+    path = "/connected-apps",
+    tag = "connected-apps",
+    params(ConnectedAppFilter, PageQuery),
+    responses(
+        (
+            status = 200,
+            description = "Page of connected apps",
+            body = Page<ConnectedAppDetails>,
+        ),
+        (status = 400, description = "Invalid page", body = ErrorBody),
+    )
+)]
 #[debug_handler]
-pub async fn get_connected_apps(State(web_app_cores): State<WebAppCores>) -> impl IntoResponse {
+pub async fn get_connected_apps(
+    State(web_app_cores): State<WebAppCores>,
+    Query(filter): Query<ConnectedAppFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<impl IntoResponse, Error> {
     let connected_apps = web_app_cores
         .app_core
-        .get_all_connected_apps()
-        .await
-        .unwrap();
-    return (StatusCode::OK, Json(connected_apps));
+        .get_connected_apps(&filter, &page.page_request()?)
+        .await?;
+    Ok((StatusCode::OK, Json(connected_apps)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/connected-apps/{connected_app_id}",
+    tag = "connected-apps",
+    params(
+        ("connected_app_id" = String, Path, description = "Id of the connected app"),
+    ),
+    responses(
+        (status = 200, description = "Connected app", body = ConnectedAppDetails),
+        (status = 404, description = "Unknown connected app", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_connected_app(
+    State(web_app_cores): State<WebAppCores>,
+    Path(connected_app_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let connected_app = web_app_cores
+        .app_core
+        .get_connected_app_details(&connected_app_id)
+        .await?;
+    Ok((StatusCode::OK, Json(connected_app)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/connected-apps",
+    tag = "connected-apps",
+    request_body = CreateConnectedAppParams,
+    responses(
+        (status = 201, description = "Created connected app", body = ConnectedApp),
+        (status = 400, description = "Invalid limits", body = ErrorBody),
+        (status = 409, description = "Id already taken", body = ErrorBody),
+        (status = 422, description = "Invalid fields, listed in `fields`", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
 #[debug_handler]
 pub async fn create_connected_app(
     State(web_app_cores): State<WebAppCores>,
+    caller: Caller,
     Json(data): Json<CreateConnectedAppParams>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Error> {
+    require_admin(&caller, "create connected apps")?;
+    let connected_app = web_app_cores.app_core.create_connected_app(&data).await?;
+    Ok((StatusCode::CREATED, Json(connected_app)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/connected-apps/{connected_app_id}",
+    tag = "connected-apps",
+    params(
+        ("connected_app_id" = String, Path, description = "Id of the connected app"),
+    ),
+    request_body = UpdateConnectedAppParams,
+    responses(
+        (status = 200, description = "Updated connected app", body = ConnectedApp),
+        (status = 400, description = "Invalid limits", body = ErrorBody),
+        (status = 404, description = "Unknown connected app", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn update_connected_app(
+    State(web_app_cores): State<WebAppCores>,
+    Path(connected_app_id): Path<String>,
+    caller: Caller,
+    Json(data): Json<UpdateConnectedAppParams>,
+) -> Result<impl IntoResponse, Error> {
+    require_connected_app_owner(&caller, &connected_app_id, "update this connected app")?;
     let connected_app = web_app_cores
         .app_core
-        .create_connected_app(&data)
-        .await
-        .unwrap();
-    return (StatusCode::CREATED, Json(connected_app));
+        .update_connected_app(&connected_app_id, &data)
+        .await?;
+    Ok((StatusCode::OK, Json(connected_app)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/connected-app-usages",
+    tag = "connected-apps",
+    responses(
+        (
+            status = 200,
+            description = "Limits and current calls of every connected app",
+            body = Vec<ConnectedAppUsage>,
+        ),
+    )
+)]
+#[debug_handler]
+pub async fn get_connected_app_usages(
+    State(web_app_cores): State<WebAppCores>,
+) -> Result<impl IntoResponse, Error> {
+    let connected_app_usages = web_app_cores
+        .app_core
+        .get_all_connected_app_usages()
+        .await?;
+    Ok((StatusCode::OK, Json(connected_app_usages)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/connected-apps/{connected_app_id}/api-keys",
+    tag = "connected-apps",
+    params(
+        ("connected_app_id" = String, Path, description = "Id of the connected app"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Keys of the app, revoked ones included. Owners see the owner keys only.",
+            body = Vec<ConnectedAppApiKey>,
+        ),
+        (status = 403, description = "Keys of another app", body = ErrorBody),
+        (status = 404, description = "Unknown connected app", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_connected_app_api_keys(
+    State(web_app_cores): State<WebAppCores>,
+    Path(connected_app_id): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_connected_app_reader(
+        &caller,
+        &connected_app_id,
+        "list the API keys of this connected app",
+    )?;
+    let mut api_keys = web_app_cores
+        .app_core
+        .get_api_keys(&connected_app_id)
+        .await?;
+    if caller.role == Role::Owner {
+        api_keys.retain(|api_key| api_key.role == Role::Owner);
+    }
+    Ok((StatusCode::OK, Json(api_keys)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/connected-apps/{connected_app_id}/api-keys",
+    tag = "connected-apps",
+    params(
+        ("connected_app_id" = String, Path, description = "Id of the connected app"),
+    ),
+    request_body(content = Option<CreateConnectedAppApiKeyParams>),
+    responses(
+        (
+            status = 201,
+            description = "Created key, shown only once",
+            body = CreatedConnectedAppApiKey,
+        ),
+        (status = 403, description = "Keys of another app, or not an owner key", body = ErrorBody),
+        (status = 404, description = "Unknown connected app", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn create_connected_app_api_key(
+    State(web_app_cores): State<WebAppCores>,
+    Path(connected_app_id): Path<String>,
+    caller: Caller,
+    data: Option<Json<CreateConnectedAppApiKeyParams>>,
+) -> Result<impl IntoResponse, Error> {
+    let params = data.map(|Json(params)| params).unwrap_or_default();
+    require_api_key_manager(
+        &caller,
+        &connected_app_id,
+        params.role,
+        "issue API keys of this role for this connected app",
+    )?;
+    let api_key = web_app_cores
+        .app_core
+        .create_api_key(&connected_app_id, &params)
+        .await?;
+    Ok((StatusCode::CREATED, Json(api_key)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/connected-apps/{connected_app_id}/api-keys/{api_key_id}",
+    tag = "connected-apps",
+    params(
+        ("connected_app_id" = String, Path, description = "Id of the connected app"),
+        ("api_key_id" = String, Path, description = "Id of the key"),
+    ),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 403, description = "Keys of another app, or not an owner key", body = ErrorBody),
+        (status = 404, description = "Unknown or already revoked key", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn revoke_connected_app_api_key(
+    State(web_app_cores): State<WebAppCores>,
+    Path((connected_app_id, api_key_id)): Path<(String, String)>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    // Checked before the lookup so that other callers can't tell which keys exist.
+    require_connected_app_owner(
+        &caller,
+        &connected_app_id,
+        "revoke API keys of this connected app",
+    )?;
+    let api_key = web_app_cores
+        .app_core
+        .get_api_keys(&connected_app_id)
+        .await?
+        .into_iter()
+        .find(|api_key| api_key.id == api_key_id)
+        .ok_or_else(|| {
+            Error::NotFoundError(format!(
+                "No API key {} for connected app {}",
+                api_key_id, connected_app_id
+            ))
+        })?;
+    require_api_key_manager(
+        &caller,
+        &connected_app_id,
+        api_key.role,
+        "revoke API keys of this role for this connected app",
+    )?;
+    web_app_cores
+        .app_core
+        .revoke_api_key(&connected_app_id, &api_key_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -2,4 +2,6 @@ pub mod entity_sharing_model;
 pub mod entity_sharing_core;
 pub mod entity_sharing_repository;
 pub mod entity_polling_handler;
+pub mod entity_polling_scheduler;
+pub mod entity_polling_schedule;
 pub mod entity_sharing_web_api;
\ No newline at end of file
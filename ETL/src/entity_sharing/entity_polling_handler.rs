@@ -1,157 +1,216 @@
-use crate::entity_sharing::entity_sharing_model::EntitySharing;
+use crate::entity_sharing::entity_polling_scheduler::{EntityPollingScheduler, PollFence};
+use crate::entity_sharing::entity_sharing_model::{
+    EntitySharing, EntitySharingRun, EntitySharingRunTrigger,
+};
 use crate::entity_subscription::entity_subscription_core::EntitySubscriptionCore;
 use crate::shared::bus::{Commands, TopicIds};
 use crate::shared::errors::Error;
-use crate::shared::python_runner::run_python_script_output_json;
+use crate::shared::json_schema_validator::validate_entity_list;
+use crate::shared::metrics::record_poll_run;
+use crate::shared::script_runtime::spawn_script_output_json;
+use chrono::Utc;
 use futures::future::join_all;
 use pubsub_bus::BusEvent;
 use pubsub_bus::Subscriber;
-use serde_json::json;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
-};
-use std::thread;
-use std::thread::JoinHandle;
-use std::time::Duration;
-use tokio;
-use tokio::sync::broadcast;
+use serde_json::{Value, json};
+use std::time::Instant;
+use tracing::{Instrument, error, info, info_span, instrument};
 
+/// Reconciles the running pollers whenever a sharing change is published on the bus.
 pub struct EntityPollingHandler {
-    handles: Vec<JoinHandle<()>>,
-    should_stop: Arc<AtomicBool>,
-    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
-    channel: (
-        broadcast::Sender<EntitySharing>,
-        broadcast::Receiver<EntitySharing>,
-    ),
+    entity_polling_scheduler: EntityPollingScheduler,
 }
 
 impl Subscriber<Commands, TopicIds> for EntityPollingHandler {
     fn on_event(&mut self, event: &BusEvent<Commands, TopicIds>) {
         match event.get_content() {
-            Commands::EntitySharingCreated { entity_sharing } => {
-                let entity_sharing = entity_sharing.clone();
-                let entity_subscription_core = Arc::clone(&self.entity_subscription_core);
-                let should_stop = Arc::clone(&self.should_stop);
-
-                let handle = setup_new_entity_sharing_polling(
-                    entity_sharing,
-                    entity_subscription_core,
-                    should_stop,
-                    self.channel.0.subscribe()
-                );
-
-                self.handles.push(handle);
-            }
-            Commands::EntitySharingUpdated { entity_sharing } => {
-                self.channel.0.send(entity_sharing.clone()).unwrap();
+            Commands::EntitySharingCreated { entity_sharing }
+            | Commands::EntitySharingUpdated { entity_sharing } => {
+                self.entity_polling_scheduler
+                    .reconcile(Some(entity_sharing.id.clone()));
             }
             _ => {}
         }
     }
 
     fn is_subscribed_to(&self, topic_id: &TopicIds) -> bool {
-        match topic_id {
-            TopicIds::EntitySharingCreated => true,
-            TopicIds::EntitySharingUpdated => true,
-            _ => false,
-        }
+        matches!(
+            topic_id,
+            TopicIds::EntitySharingCreated | TopicIds::EntitySharingUpdated
+        )
     }
 }
 
 impl EntityPollingHandler {
-    pub fn new(
-        entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
-        should_stop: Arc<AtomicBool>,
-    ) -> Self {
+    pub fn new(entity_polling_scheduler: EntityPollingScheduler) -> Self {
         Self {
-            handles: vec![],
-            entity_subscription_core,
-            should_stop,
-            channel: broadcast::channel(16),
+            entity_polling_scheduler,
         }
     }
 }
 
-fn setup_new_entity_sharing_polling(
-    entity_sharing: EntitySharing,
-    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
-    should_stop: Arc<AtomicBool>,
-    receiver: broadcast::Receiver<EntitySharing>,
-) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        if let Err(e) = rt.block_on(run_entity_sharing_polling(
-            entity_sharing,
-            entity_subscription_core,
-            &should_stop,
-            receiver,
-        )) {
-            eprintln!("Error in entity sharing polling: {:?}", e);
-        }
-    })
+/// Runs the sharing script once, notifies its subscriptions of the result and persists the
+/// cursor the script returned, unless the guardrail holds the result. Every call with a script
+/// is recorded as a run of the sharing, with the id `run_id`.
+/// Nothing is delivered nor saved once `fence` finds the lease lost.
+#[instrument(
+    name = "poll",
+    skip_all,
+    fields(entity_sharing_id = %entity_sharing.id, ?trigger, %run_id)
+)]
+pub async fn poll_entity_sharing(
+    entity_sharing: &mut EntitySharing,
+    entity_subscription_core: &EntitySubscriptionCore<'static>,
+    trigger: EntitySharingRunTrigger,
+    run_id: String,
+    fence: &PollFence,
+) -> Result<(), Error> {
+    let Some(python_script) = entity_sharing.python_script.clone() else {
+        return Ok(());
+    };
+
+    let started = Instant::now();
+    let mut run = EntitySharingRun {
+        id: run_id,
+        entity_sharing_id: entity_sharing.id.clone(),
+        trigger,
+        started_at: Utc::now().timestamp(),
+        ended_at: 0,
+        duration_ms: 0,
+        entity_count: None,
+        valid: None,
+        validation_errors: vec![],
+        subscriptions_notified: 0,
+        subscriptions_failed: 0,
+        error: None,
+        held_delivery_id: None,
+    };
+    let result = run_poll_cycle(
+        entity_sharing,
+        &python_script,
+        entity_subscription_core,
+        &mut run,
+        fence,
+    )
+    .await;
+    run.ended_at = Utc::now().timestamp();
+    run.duration_ms = started.elapsed().as_millis() as i64;
+    run.error = result.as_ref().err().map(|e| format!("{:?}", e));
+    record_poll_run(
+        &entity_sharing.id,
+        result.is_ok(),
+        started.elapsed(),
+        run.entity_count,
+    );
+    if let Err(e) = entity_subscription_core
+        .entity_sharing_core
+        .create_entity_sharing_run(&run)
+        .await
+    {
+        error!(error = ?e, "Error saving entity sharing run");
+    }
+    info!(
+        duration_ms = run.duration_ms,
+        entity_count = run.entity_count,
+        subscriptions_notified = run.subscriptions_notified,
+        subscriptions_failed = run.subscriptions_failed,
+        error = run.error,
+        "Entity sharing polled"
+    );
+    return result;
 }
 
-async fn run_entity_sharing_polling(
-    mut entity_sharing: EntitySharing,
-    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
-    should_stop: &Arc<AtomicBool>,
-    mut receiver: broadcast::Receiver<EntitySharing>,
+async fn run_poll_cycle(
+    entity_sharing: &mut EntitySharing,
+    python_script: &str,
+    entity_subscription_core: &EntitySubscriptionCore<'static>,
+    run: &mut EntitySharingRun,
+    fence: &PollFence,
 ) -> Result<(), Error> {
-    if entity_sharing.polling_infos.is_none() {
+    let entity_subscriptions = entity_subscription_core
+        .get_all_entity_subscriptions_for_entity_sharing(&entity_sharing.id)
+        .await?;
+    // An approved held delivery moves the cursor outside of the poller.
+    entity_sharing.polling_cursor = entity_subscription_core
+        .entity_sharing_core
+        .get_entity_sharing(&entity_sharing.id)
+        .await?
+        .polling_cursor;
+    let input = entity_sharing
+        .polling_infos
+        .as_ref()
+        .and_then(|polling_infos| polling_infos.input.clone())
+        .unwrap_or(json!({}));
+    let permit = entity_subscription_core
+        .entity_sharing_core
+        .connected_app_core
+        .acquire_call_permit(&entity_sharing.connected_app_id)
+        .await?;
+    let script_run = spawn_script_output_json(
+        entity_sharing.script_runtime,
+        python_script,
+        &input,
+        &entity_sharing.polling_cursor,
+        &entity_sharing.allowed_modules,
+    )
+    .instrument(info_span!("run_script", runtime = ?entity_sharing.script_runtime));
+    let output = tokio::select! {
+        output = script_run => output?,
+        _ = fence.lost() => return Err(fence.lost_error(&entity_sharing.id)),
+    };
+    drop(permit);
+
+    let entity_count = match &output.result {
+        Value::Array(entities) => entities.len() as i64,
+        Value::Null => 0,
+        _ => 1,
+    };
+    run.entity_count = Some(entity_count);
+    // Entities are delivered regardless, the outcome of the check is only recorded.
+    run.validation_errors = info_span!("validate", entity_count).in_scope(|| {
+        validate_entity_list(
+            &entity_sharing.json_schema,
+            entity_sharing.is_array,
+            &output.result,
+        )
+        .unwrap_or_else(|e| vec![format!("{:?}", e)])
+    });
+    run.valid = Some(run.validation_errors.is_empty());
+
+    let entity_sharing_core = &entity_subscription_core.entity_sharing_core;
+    fence.check(entity_sharing_core, &entity_sharing.id).await?;
+    let held_delivery = entity_subscription_core
+        .entity_sharing_core
+        .apply_entity_count_guardrail(
+            entity_sharing,
+            &run.id,
+            &output.result,
+            entity_count,
+            &output.cursor,
+        )
+        .await?;
+    if let Some(held_delivery) = held_delivery {
+        // The held list keeps the cursor until it is approved.
+        run.held_delivery_id = Some(held_delivery.id);
         return Ok(());
     }
-
-    println!(
-        "Starting entity sharing polling thread: {:?}",
-        entity_sharing.name
-    );
-    while !should_stop.load(Ordering::Relaxed) {
-        if !receiver.is_empty() {
-            if let Ok(new_entity_sharing) = receiver.recv().await {
-                if new_entity_sharing.id.eq(&entity_sharing.id) {
-                    entity_sharing = new_entity_sharing;
-                }
-            }
-        }
-        
-        let entity_subscriptions = entity_subscription_core
-            .get_all_entity_subscriptions_for_entity_sharing(&entity_sharing.id)
+    let results = join_all(entity_subscriptions.into_iter().map(async |sub| {
+        entity_subscription_core
+            .notify_subscription_of_new_entity_list(&sub, &output.result)
             .await
-            .unwrap();
-        if let Some(polling_infos) = &entity_sharing.polling_infos {
-            if let Some(python_script) = &entity_sharing.python_script {
-                //TODO: set the input of the python script
-                let result = match run_python_script_output_json(python_script, &json!({})) {
-                    Ok(result) => result,
-                    Err(e) => {
-                        eprintln!(
-                            "Error polling entity sharing: {:?} - {:?}",
-                            entity_sharing.name, e
-                        );
-                        tokio::time::sleep(Duration::from_millis(10000)).await;
-                        continue;
-                    }
-                };
-                join_all(entity_subscriptions.into_iter().map(async |sub| {
-                    entity_subscription_core
-                        .notify_subscription_of_new_entity_list(&sub, &result)
-                        .await
-                }))
-                .await;
-            }
-            tokio::time::sleep(Duration::from_millis(polling_infos.polling_interval)).await;
-        }
+    }))
+    .await;
+    run.subscriptions_notified = results.iter().filter(|result| result.is_ok()).count() as i64;
+    run.subscriptions_failed = results.len() as i64 - run.subscriptions_notified;
 
-        if should_stop.load(Ordering::Relaxed) {
-            break;
-        }
+    if output.cursor != entity_sharing.polling_cursor {
+        // Deliveries may have taken long enough for the lease to go.
+        fence.check(entity_sharing_core, &entity_sharing.id).await?;
+        entity_subscription_core
+            .entity_sharing_core
+            .update_entity_sharing_polling_cursor(&entity_sharing.id, &output.cursor)
+            .await?;
+        entity_sharing.polling_cursor = output.cursor;
     }
-
-    println!(
-        "Stopping entity sharing polling thread: {}",
-        entity_sharing.id
-    );
     Ok(())
 }
@@ -0,0 +1,395 @@
+use crate::shared::errors::Error;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IntervalMode {
+    /// Waits `polling_interval` after a poll ends, so the period grows with the script run time.
+    #[default]
+    FixedDelay,
+    /// Starts a poll every `polling_interval`, right away if the previous one overran.
+    FixedRate,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+/// When a sharing is polled: either every `polling_interval` milliseconds, or on a `cron`
+/// expression evaluated in `timezone`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(untagged)]
+pub enum PollingSchedule {
+    Interval {
+        polling_interval: u64,
+        #[serde(default)]
+        interval_mode: IntervalMode,
+    },
+    Cron {
+        /// Either a standard 5 fields expression, e.g. `0 2 * * 1-5` or `0 2 * * Mon-Fri` for
+        /// every weekday at 02:00, or one starting with seconds. Days of the week are numbered
+        /// from 0 or 7 for Sunday in the first form, and from 1 for Sunday in the second one.
+        cron: String,
+        /// IANA time zone name, e.g. `Europe/Paris`.
+        #[serde(default = "default_timezone")]
+        timezone: String,
+    },
+}
+
+const DAY_NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+fn parse_day_number(day: &str) -> Option<usize> {
+    day.parse::<usize>().ok().filter(|day| *day <= 7)
+}
+
+/// Rewrites a standard day of the week field, numbered from 0 for Sunday, with the day names
+/// the cron crate reads, as it numbers them from 1 for Sunday. Numeric ranges and steps are
+/// expanded, which also lets `5-7` wrap around to Sunday.
+fn convert_days_of_week(field: &str) -> String {
+    field
+        .split(',')
+        .map(|item| {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse::<usize>().ok().filter(|step| *step > 0)),
+                None => (item, Some(1)),
+            };
+            let bounds = match range {
+                "*" if item.contains('/') => Some((0, 6)),
+                _ => match range.split_once('-') {
+                    Some((first, last)) => parse_day_number(first).zip(parse_day_number(last)),
+                    None if item.contains('/') => parse_day_number(range).map(|first| (first, 7)),
+                    None => parse_day_number(range).map(|day| (day, day)),
+                },
+            };
+            match (bounds, step) {
+                (Some((first, last)), Some(step)) if first <= last => (first..=last)
+                    .step_by(step)
+                    .map(|day| DAY_NAMES[day % 7])
+                    .collect::<Vec<_>>()
+                    .join(","),
+                // Names, `*` and `?` mean the same to the crate, anything else is reported by it.
+                _ => item.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_cron(cron: &str, timezone: &str) -> Result<(Schedule, Tz), Error> {
+    let fields: Vec<&str> = cron.split_whitespace().collect();
+    let expression = match fields.as_slice() {
+        [minutes, hours, days_of_month, months, days_of_week] => format!(
+            "0 {} {} {} {} {}",
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            convert_days_of_week(days_of_week)
+        ),
+        _ => cron.to_string(),
+    };
+    let schedule = Schedule::from_str(&expression)
+        .map_err(|e| Error::BadRequestError(format!("Invalid cron expression {}: {}", cron, e)))?;
+    let timezone = Tz::from_str(timezone)
+        .map_err(|e| Error::BadRequestError(format!("Invalid time zone {}: {}", timezone, e)))?;
+    Ok((schedule, timezone))
+}
+
+impl PollingSchedule {
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            PollingSchedule::Interval {
+                polling_interval: 0,
+                ..
+            } => Err(Error::BadRequestError(
+                "polling_interval must be greater than 0".to_string(),
+            )),
+            PollingSchedule::Interval { .. } => Ok(()),
+            PollingSchedule::Cron { cron, timezone } => parse_cron(cron, timezone).map(|_| ()),
+        }
+    }
+
+    fn next_cron_delay(
+        cron: &str,
+        timezone: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Duration, Error> {
+        let (schedule, timezone) = parse_cron(cron, timezone)?;
+        let next =
+            schedule
+                .after(&now.with_timezone(&timezone))
+                .next()
+                .ok_or(Error::BadRequestError(format!(
+                    "Cron expression {} has no upcoming occurrence",
+                    cron
+                )))?;
+        Ok((next.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default())
+    }
+
+    /// Delay before the first poll: interval schedules poll right away, cron ones wait for
+    /// their next occurrence.
+    pub fn first_poll_delay(&self, now: DateTime<Utc>) -> Result<Duration, Error> {
+        match self {
+            PollingSchedule::Interval { .. } => Ok(Duration::ZERO),
+            PollingSchedule::Cron { cron, timezone } => Self::next_cron_delay(cron, timezone, now),
+        }
+    }
+
+    /// Delay before the next poll, given when the last one started.
+    pub fn next_poll_delay(
+        &self,
+        last_poll_started_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<Duration, Error> {
+        match self {
+            PollingSchedule::Interval {
+                polling_interval,
+                interval_mode: IntervalMode::FixedDelay,
+            } => Ok(Duration::from_millis(*polling_interval)),
+            PollingSchedule::Interval {
+                polling_interval,
+                interval_mode: IntervalMode::FixedRate,
+            } => {
+                let elapsed = (now - last_poll_started_at).to_std().unwrap_or_default();
+                Ok(Duration::from_millis(*polling_interval).saturating_sub(elapsed))
+            }
+            PollingSchedule::Cron { cron, timezone } => Self::next_cron_delay(cron, timezone, now),
+        }
+    }
+}
+
+fn default_initial_backoff() -> u64 {
+    10000
+}
+
+fn default_max_backoff() -> u64 {
+    600000
+}
+
+fn default_backoff_multiplier() -> u32 {
+    2
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_open_duration() -> u64 {
+    1800000
+}
+
+/// How a sharing is retried after failed polls. Durations are in milliseconds.
+///
+/// Each consecutive failure multiplies the delay before the next attempt by
+/// `backoff_multiplier`, up to `max_backoff`. After `failure_threshold` consecutive failures the
+/// circuit opens: a single attempt is made every `circuit_open_duration` until one succeeds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+pub struct PollingRetryPolicy {
+    #[serde(default = "default_initial_backoff")]
+    pub initial_backoff: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    #[serde(default = "default_backoff_multiplier")]
+    pub backoff_multiplier: u32,
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "default_circuit_open_duration")]
+    pub circuit_open_duration: u64,
+}
+
+impl Default for PollingRetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+            backoff_multiplier: default_backoff_multiplier(),
+            failure_threshold: default_failure_threshold(),
+            circuit_open_duration: default_circuit_open_duration(),
+        }
+    }
+}
+
+impl PollingRetryPolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.backoff_multiplier == 0 {
+            return Err(Error::BadRequestError(
+                "backoff_multiplier must be at least 1".to_string(),
+            ));
+        }
+        if self.failure_threshold == 0 {
+            return Err(Error::BadRequestError(
+                "failure_threshold must be at least 1".to_string(),
+            ));
+        }
+        if self.initial_backoff > self.max_backoff {
+            return Err(Error::BadRequestError(
+                "initial_backoff must not exceed max_backoff".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_circuit_open(&self, consecutive_failures: u32) -> bool {
+        consecutive_failures >= self.failure_threshold
+    }
+
+    /// Delay between the last failed poll and the next attempt.
+    pub fn retry_delay(&self, consecutive_failures: u32) -> Duration {
+        if self.is_circuit_open(consecutive_failures) {
+            return Duration::from_millis(self.circuit_open_duration);
+        }
+        let factor =
+            (self.backoff_multiplier as u64).saturating_pow(consecutive_failures.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff
+                .saturating_mul(factor)
+                .min(self.max_backoff),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn cron(cron: &str, timezone: &str) -> PollingSchedule {
+        PollingSchedule::Cron {
+            cron: cron.to_string(),
+            timezone: timezone.to_string(),
+        }
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    /// Next occurrences of the schedule from `now`, in UTC.
+    fn next_polls(
+        schedule: &PollingSchedule,
+        mut now: DateTime<Utc>,
+        count: usize,
+    ) -> Vec<DateTime<Utc>> {
+        (0..count)
+            .map(|_| {
+                let delay = schedule.first_poll_delay(now).unwrap();
+                now += chrono::Duration::from_std(delay).unwrap();
+                let poll = now;
+                now += chrono::Duration::seconds(1);
+                poll
+            })
+            .collect()
+    }
+
+    // 2025-01-04 is a Saturday.
+
+    #[test]
+    fn standard_numeric_days_count_from_sunday() {
+        let polls = next_polls(&cron("0 2 * * 1-5", "UTC"), utc(2025, 1, 4, 12, 0), 6);
+        assert_eq!(
+            polls,
+            vec![
+                utc(2025, 1, 6, 2, 0),
+                utc(2025, 1, 7, 2, 0),
+                utc(2025, 1, 8, 2, 0),
+                utc(2025, 1, 9, 2, 0),
+                utc(2025, 1, 10, 2, 0),
+                utc(2025, 1, 13, 2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn standard_sunday_is_0_or_7() {
+        for days_of_week in ["0", "7"] {
+            let schedule = cron(&format!("30 8 * * {}", days_of_week), "UTC");
+            schedule.validate().unwrap();
+            assert_eq!(
+                next_polls(&schedule, utc(2025, 1, 4, 12, 0), 2),
+                vec![utc(2025, 1, 5, 8, 30), utc(2025, 1, 12, 8, 30)]
+            );
+        }
+    }
+
+    #[test]
+    fn standard_ranges_wrap_to_sunday_and_take_steps() {
+        assert_eq!(
+            next_polls(&cron("0 0 * * 5-7", "UTC"), utc(2025, 1, 2, 12, 0), 4),
+            vec![
+                utc(2025, 1, 3, 0, 0),
+                utc(2025, 1, 4, 0, 0),
+                utc(2025, 1, 5, 0, 0),
+                utc(2025, 1, 10, 0, 0),
+            ]
+        );
+        assert_eq!(convert_days_of_week("*/2"), "Sun,Tue,Thu,Sat");
+        assert_eq!(convert_days_of_week("1-5/2,0"), "Mon,Wed,Fri,Sun");
+        assert_eq!(convert_days_of_week("Mon-Fri"), "Mon-Fri");
+        assert_eq!(convert_days_of_week("*"), "*");
+    }
+
+    #[test]
+    fn standard_named_days() {
+        assert_eq!(
+            next_polls(&cron("0 2 * * Mon-Fri", "UTC"), utc(2025, 1, 4, 12, 0), 1),
+            vec![utc(2025, 1, 6, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn seconds_form_uses_the_cron_crate_numbering() {
+        assert_eq!(
+            next_polls(&cron("0 0 2 * * 2-6", "UTC"), utc(2025, 1, 4, 12, 0), 1),
+            vec![utc(2025, 1, 6, 2, 0)]
+        );
+        assert_eq!(
+            next_polls(
+                &cron("15 0 2 * * Mon-Fri", "UTC"),
+                utc(2025, 1, 4, 12, 0),
+                1
+            ),
+            vec![Utc.with_ymd_and_hms(2025, 1, 6, 2, 0, 15).unwrap()]
+        );
+    }
+
+    #[test]
+    fn cron_runs_in_the_time_zone() {
+        // Paris is UTC+1 in winter and UTC+2 in summer.
+        let schedule = cron("0 2 * * *", "Europe/Paris");
+        assert_eq!(
+            next_polls(&schedule, utc(2025, 1, 4, 12, 0), 1),
+            vec![utc(2025, 1, 5, 1, 0)]
+        );
+        assert_eq!(
+            next_polls(&schedule, utc(2025, 7, 4, 12, 0), 1),
+            vec![utc(2025, 7, 5, 0, 0)]
+        );
+        // The day of the week is the one of the time zone: Monday 01:00 in Tokyo is Sunday in UTC.
+        assert_eq!(
+            next_polls(&cron("0 1 * * 1", "Asia/Tokyo"), utc(2025, 1, 4, 12, 0), 1),
+            vec![utc(2025, 1, 5, 16, 0)]
+        );
+    }
+
+    #[test]
+    fn invalid_schedules_are_rejected() {
+        assert!(cron("0 2 * *", "UTC").validate().is_err());
+        assert!(cron("0 2 * * 8", "UTC").validate().is_err());
+        assert!(cron("0 2 * * *", "Mars/Olympus").validate().is_err());
+        let interval = |polling_interval| PollingSchedule::Interval {
+            polling_interval,
+            interval_mode: IntervalMode::FixedRate,
+        };
+        assert!(interval(0).validate().is_err());
+        interval(1000).validate().unwrap();
+    }
+}
@@ -0,0 +1,1085 @@
+use crate::entity_sharing::entity_polling_handler::poll_entity_sharing;
+use crate::entity_sharing::entity_polling_schedule::{PollingRetryPolicy, PollingSchedule};
+use crate::entity_sharing::entity_sharing_core::EntitySharingCore;
+use crate::entity_sharing::entity_sharing_model::{
+    EntitySharing, EntitySharingHealth, EntitySharingHealthStatus, EntitySharingRunTrigger,
+};
+use crate::entity_subscription::entity_subscription_core::EntitySubscriptionCore;
+use crate::shared::config::Config;
+use crate::shared::errors::Error;
+use crate::shared::shutdown::ShutdownCoordinator;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, error, info, info_span, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Delay before retrying when the next poll of a sharing can't be scheduled.
+const SCHEDULE_ERROR_DELAY: Duration = Duration::from_millis(10000);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PollerStatus {
+    /// Sleeping until `next_poll_at`.
+    Waiting,
+    /// Running the sharing script or notifying its subscriptions.
+    Polling,
+    Paused,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct PollerState {
+    pub entity_sharing_id: String,
+    /// Instance holding the sharing lease, the one running this poller.
+    pub instance_id: String,
+    pub entity_sharing_name: String,
+    pub status: PollerStatus,
+    pub next_poll_at: Option<i64>,
+    pub last_poll_started_at: Option<i64>,
+    pub last_poll_ended_at: Option<i64>,
+    pub health: EntitySharingHealth,
+}
+
+/// Poll requested through the API, outside of the sharing schedule.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct TriggeredPoll {
+    pub entity_sharing_id: String,
+    /// Id of the run the poll is recorded as once it is over, see the runs of the sharing.
+    pub run_id: String,
+}
+
+/// Manual poll that later triggers of the same sharing are coalesced into until it is closed.
+/// A poller closes it once the poll starts, its next poll can't overlap with this one. A poll
+/// running on its own task closes it once over, nothing else keeps the two from overlapping.
+#[derive(Clone)]
+struct ManualPoll {
+    run_id: String,
+    closed: Arc<AtomicBool>,
+}
+
+impl ManualPoll {
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+struct PollerControl {
+    entity_sharing: EntitySharing,
+    paused: bool,
+}
+
+struct LeaseSettings {
+    instance_id: String,
+    lease_duration: Duration,
+}
+
+struct Poller {
+    state: Arc<Mutex<PollerState>>,
+    control: watch::Sender<PollerControl>,
+    manual_polls: mpsc::UnboundedSender<ManualPoll>,
+    /// Cancelled once the lease is lost, which ends the poller without finishing its poll.
+    lease_lost: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+/// Guards what a poll writes, the deliveries and the cursor, against another instance polling
+/// the same sharing by checking the lease again right before.
+pub struct PollFence {
+    instance_id: String,
+    /// Whether this instance must hold the lease. A manual poll of a sharing no poller runs only
+    /// needs no other instance to hold it.
+    leased: bool,
+    lease_lost: CancellationToken,
+}
+
+impl PollFence {
+    fn new(instance_id: &str, leased: bool, lease_lost: CancellationToken) -> Self {
+        Self {
+            instance_id: instance_id.to_string(),
+            leased,
+            lease_lost,
+        }
+    }
+
+    /// Resolves once the scheduler finds the lease lost.
+    pub async fn lost(&self) {
+        self.lease_lost.cancelled().await
+    }
+
+    pub fn lost_error(&self, entity_sharing_id: &str) -> Error {
+        Error::ConflictError(format!(
+            "Instance {} lost the polling lease of entity sharing {}",
+            self.instance_id, entity_sharing_id
+        ))
+    }
+
+    pub async fn check(
+        &self,
+        entity_sharing_core: &EntitySharingCore<'_>,
+        entity_sharing_id: &str,
+    ) -> Result<(), Error> {
+        let lease = entity_sharing_core
+            .get_entity_sharing_lease(entity_sharing_id)
+            .await?;
+        let now = Utc::now().timestamp();
+        let live_lease = lease.filter(|lease| lease.expires_at > now);
+        let held = match &live_lease {
+            Some(lease) => lease.instance_id == self.instance_id,
+            None => !self.leased,
+        };
+        if !held || self.lease_lost.is_cancelled() {
+            return Err(self.lost_error(entity_sharing_id));
+        }
+        Ok(())
+    }
+}
+
+enum SchedulerCommand {
+    /// Brings the running pollers in line with the polling sharings stored in the database,
+    /// `changed_entity_sharing_id` being the sharing whose change triggered it, if any.
+    Reconcile {
+        changed_entity_sharing_id: Option<String>,
+    },
+    GetPollers {
+        reply: oneshot::Sender<Vec<PollerState>>,
+    },
+    GetPoller {
+        entity_sharing_id: String,
+        reply: oneshot::Sender<Result<PollerState, Error>>,
+    },
+    Pause {
+        entity_sharing_id: String,
+        reply: oneshot::Sender<Result<PollerState, Error>>,
+    },
+    Resume {
+        entity_sharing_id: String,
+        reply: oneshot::Sender<Result<PollerState, Error>>,
+    },
+    Cancel {
+        entity_sharing_id: String,
+        reply: oneshot::Sender<Result<PollerState, Error>>,
+    },
+    TriggerPoll {
+        entity_sharing_id: String,
+        reply: oneshot::Sender<Result<TriggeredPoll, Error>>,
+    },
+}
+
+/// Handle to the task that owns every sharing poller. Pollers are tokio tasks of the main
+/// runtime, so the number of polling sharings doesn't cost threads.
+///
+/// When several instances share the database, each one only polls the sharings it holds a
+/// lease on. Leases are renewed on every heartbeat and spread evenly across the live instances,
+/// the leases of an instance that stops heartbeating are taken over once they expire. A poller
+/// whose lease is lost stops right away, and each poll checks the lease again before delivering
+/// and before saving the cursor, see `PollFence`. Pausing, resuming and cancelling only apply to
+/// the pollers of the instance receiving the request.
+#[derive(Clone)]
+pub struct EntityPollingScheduler {
+    sender: mpsc::UnboundedSender<SchedulerCommand>,
+}
+
+impl EntityPollingScheduler {
+    /// Spawns the scheduler task, must be called from within the tokio runtime.
+    pub fn start(
+        entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
+        shutdown: ShutdownCoordinator,
+        config: &Config,
+    ) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let lease_settings = LeaseSettings {
+            instance_id: config.instance_id.clone(),
+            lease_duration: config.poller_lease_duration,
+        };
+        shutdown.spawn(run_scheduler(
+            receiver,
+            lease_settings,
+            entity_subscription_core,
+            shutdown.clone(),
+        ));
+        Self { sender }
+    }
+
+    pub fn reconcile(&self, changed_entity_sharing_id: Option<String>) {
+        if self
+            .sender
+            .send(SchedulerCommand::Reconcile {
+                changed_entity_sharing_id,
+            })
+            .is_err()
+        {
+            error!("Entity polling scheduler is not running");
+        }
+    }
+
+    pub async fn get_pollers(&self) -> Result<Vec<PollerState>, Error> {
+        return self
+            .request(|reply| SchedulerCommand::GetPollers { reply })
+            .await;
+    }
+
+    pub async fn get_poller(&self, entity_sharing_id: &str) -> Result<PollerState, Error> {
+        let entity_sharing_id = entity_sharing_id.to_string();
+        return self
+            .request(|reply| SchedulerCommand::GetPoller {
+                entity_sharing_id,
+                reply,
+            })
+            .await?;
+    }
+
+    pub async fn pause(&self, entity_sharing_id: &str) -> Result<PollerState, Error> {
+        let entity_sharing_id = entity_sharing_id.to_string();
+        return self
+            .request(|reply| SchedulerCommand::Pause {
+                entity_sharing_id,
+                reply,
+            })
+            .await?;
+    }
+
+    pub async fn resume(&self, entity_sharing_id: &str) -> Result<PollerState, Error> {
+        let entity_sharing_id = entity_sharing_id.to_string();
+        return self
+            .request(|reply| SchedulerCommand::Resume {
+                entity_sharing_id,
+                reply,
+            })
+            .await?;
+    }
+
+    /// Stops polling a sharing until it is updated again.
+    pub async fn cancel(&self, entity_sharing_id: &str) -> Result<PollerState, Error> {
+        let entity_sharing_id = entity_sharing_id.to_string();
+        return self
+            .request(|reply| SchedulerCommand::Cancel {
+                entity_sharing_id,
+                reply,
+            })
+            .await?;
+    }
+
+    /// Polls a sharing right away without moving its next scheduled poll. A trigger arriving
+    /// while another one is still pending, or running outside of a poller, gets its run back.
+    pub async fn trigger_poll(&self, entity_sharing_id: &str) -> Result<TriggeredPoll, Error> {
+        let entity_sharing_id = entity_sharing_id.to_string();
+        return self
+            .request(|reply| SchedulerCommand::TriggerPoll {
+                entity_sharing_id,
+                reply,
+            })
+            .await?;
+    }
+
+    /// Checks the scheduler task is still running and answering requests.
+    pub async fn check_alive(&self) -> Result<(), Error> {
+        return self.get_pollers().await.map(|_| ());
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> SchedulerCommand,
+    ) -> Result<T, Error> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(command(reply))
+            .map_err(|_| Error::SchedulerError("Scheduler is not running".to_string()))?;
+        response
+            .await
+            .map_err(|_| Error::SchedulerError("Scheduler dropped the request".to_string()))
+    }
+}
+
+fn poller_not_found(entity_sharing_id: &str) -> Error {
+    Error::NotFoundError(format!(
+        "No poller for entity sharing {}",
+        entity_sharing_id
+    ))
+}
+
+async fn run_scheduler(
+    mut receiver: mpsc::UnboundedReceiver<SchedulerCommand>,
+    lease_settings: LeaseSettings,
+    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
+    shutdown: ShutdownCoordinator,
+) {
+    let mut pollers: HashMap<String, Poller> = HashMap::new();
+    // Sharings this instance holds the lease on, polled unless they were cancelled.
+    let mut leased: HashSet<String> = HashSet::new();
+    let mut leases_expire_at = Utc::now();
+    // Sharings whose poller was cancelled through the API, with the definition they had then.
+    // They stay leased and are left alone until they change.
+    let mut cancelled: HashMap<String, EntitySharing> = HashMap::new();
+    let mut pending_manual_polls: HashMap<String, ManualPoll> = HashMap::new();
+    // Reconciling also renews the leases, so it runs a few times per lease duration.
+    let mut heartbeat =
+        tokio::time::interval((lease_settings.lease_duration / 3).max(Duration::from_secs(1)));
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // Pollers see the shutdown on their own and end once their current poll is over.
+    while let Some(command) = tokio::select! {
+        command = receiver.recv() => command,
+        _ = heartbeat.tick() => Some(SchedulerCommand::Reconcile {
+            changed_entity_sharing_id: None,
+        }),
+        _ = shutdown.cancelled() => None,
+    } {
+        pollers.retain(|_, poller| !poller.task.is_finished());
+        match command {
+            SchedulerCommand::Reconcile {
+                changed_entity_sharing_id,
+            } => {
+                if let Some(entity_sharing_id) = &changed_entity_sharing_id {
+                    cancelled.remove(entity_sharing_id);
+                }
+                match reconcile_pollers(
+                    &mut pollers,
+                    &mut leased,
+                    &mut cancelled,
+                    &lease_settings,
+                    &entity_subscription_core,
+                    &shutdown,
+                )
+                .await
+                {
+                    Ok(expires_at) => leases_expire_at = expires_at,
+                    Err(e) => {
+                        error!(error = ?e, "Error reconciling entity sharing pollers");
+                        // Other instances are free to take over the sharings once the leases
+                        // couldn't be renewed in time.
+                        if Utc::now() >= leases_expire_at && !leased.is_empty() {
+                            warn!("Polling leases expired, stopping all pollers");
+                            for id in leased.drain() {
+                                stop_poller(&mut pollers, &id);
+                            }
+                        }
+                    }
+                }
+            }
+            SchedulerCommand::GetPollers { reply } => {
+                let mut states: Vec<PollerState> = pollers
+                    .values()
+                    .map(|poller| poller.state.lock().unwrap().clone())
+                    .collect();
+                states.sort_by(|a, b| a.entity_sharing_id.cmp(&b.entity_sharing_id));
+                let _ = reply.send(states);
+            }
+            SchedulerCommand::GetPoller {
+                entity_sharing_id,
+                reply,
+            } => {
+                let state = pollers
+                    .get(&entity_sharing_id)
+                    .map(|poller| poller.state.lock().unwrap().clone())
+                    .ok_or(poller_not_found(&entity_sharing_id));
+                let _ = reply.send(state);
+            }
+            SchedulerCommand::Pause {
+                entity_sharing_id,
+                reply,
+            } => {
+                let _ = reply.send(set_paused(&pollers, &entity_sharing_id, true));
+            }
+            SchedulerCommand::Resume {
+                entity_sharing_id,
+                reply,
+            } => {
+                let _ = reply.send(set_paused(&pollers, &entity_sharing_id, false));
+            }
+            SchedulerCommand::Cancel {
+                entity_sharing_id,
+                reply,
+            } => {
+                // Dropping the control channel ends the poller once its current poll is done.
+                let state = pollers
+                    .remove(&entity_sharing_id)
+                    .map(|poller| {
+                        let entity_sharing = poller.control.borrow().entity_sharing.clone();
+                        cancelled.insert(entity_sharing_id.clone(), entity_sharing);
+                        poller.state.lock().unwrap().clone()
+                    })
+                    .ok_or(poller_not_found(&entity_sharing_id));
+                let _ = reply.send(state);
+            }
+            SchedulerCommand::TriggerPoll {
+                entity_sharing_id,
+                reply,
+            } => {
+                pending_manual_polls.retain(|_, manual_poll| !manual_poll.is_closed());
+                let manual_poll = match pending_manual_polls.get(&entity_sharing_id) {
+                    Some(manual_poll) => Ok(manual_poll.clone()),
+                    None => {
+                        trigger_poll(
+                            &pollers,
+                            &entity_sharing_id,
+                            &lease_settings.instance_id,
+                            &entity_subscription_core,
+                            &shutdown,
+                        )
+                        .await
+                    }
+                };
+                let triggered_poll = manual_poll.map(|manual_poll| {
+                    let run_id = manual_poll.run_id.clone();
+                    pending_manual_polls.insert(entity_sharing_id.clone(), manual_poll);
+                    TriggeredPoll {
+                        entity_sharing_id,
+                        run_id,
+                    }
+                });
+                let _ = reply.send(triggered_poll);
+            }
+        }
+    }
+}
+
+/// Stops the poller of a sharing whose lease is lost, its poll in progress included.
+fn stop_poller(pollers: &mut HashMap<String, Poller>, entity_sharing_id: &str) {
+    if let Some(poller) = pollers.remove(entity_sharing_id) {
+        poller.lease_lost.cancel();
+    }
+}
+
+/// Same sharing definition, the cursor and health being tracked by the pollers themselves.
+fn same_definition(entity_sharing: &EntitySharing, other: &EntitySharing) -> bool {
+    EntitySharing {
+        polling_cursor: other.polling_cursor.clone(),
+        health: other.health.clone(),
+        ..entity_sharing.clone()
+    } == *other
+}
+
+/// Renews the leases of this instance, takes or gives back leases to keep its fair share of
+/// the polling sharings, then starts, updates or stops pollers to match. Returns when the
+/// renewed leases expire.
+async fn reconcile_pollers(
+    pollers: &mut HashMap<String, Poller>,
+    leased: &mut HashSet<String>,
+    cancelled: &mut HashMap<String, EntitySharing>,
+    lease_settings: &LeaseSettings,
+    entity_subscription_core: &Arc<EntitySubscriptionCore<'static>>,
+    shutdown: &ShutdownCoordinator,
+) -> Result<DateTime<Utc>, Error> {
+    let entity_sharing_core = &entity_subscription_core.entity_sharing_core;
+    let instance_id = &lease_settings.instance_id;
+    let now = Utc::now();
+    let expires_at = now + lease_settings.lease_duration;
+    entity_sharing_core
+        .heartbeat_polling_instance(instance_id, expires_at.timestamp())
+        .await?;
+    let live_instances = entity_sharing_core
+        .count_live_polling_instances(now.timestamp())
+        .await?
+        .max(1);
+
+    let mut desired: HashMap<String, EntitySharing> = entity_sharing_core
+        .get_all_polling_entity_sharings()
+        .await?
+        .into_iter()
+        .map(|entity_sharing| (entity_sharing.id.clone(), entity_sharing))
+        .collect();
+    cancelled.retain(|id, entity_sharing| {
+        desired
+            .get(id)
+            .is_some_and(|desired| same_definition(desired, entity_sharing))
+    });
+    let fair_share = desired.len().div_ceil(live_instances as usize);
+
+    for id in leased.clone() {
+        let renewed = desired.contains_key(&id)
+            && entity_sharing_core
+                .acquire_entity_sharing_lease(
+                    &id,
+                    instance_id,
+                    now.timestamp(),
+                    expires_at.timestamp(),
+                )
+                .await?;
+        if !renewed {
+            if desired.contains_key(&id) {
+                warn!(entity_sharing_id = %id, "Lost the polling lease of entity sharing");
+                stop_poller(pollers, &id);
+            } else {
+                entity_sharing_core
+                    .release_entity_sharing_lease(&id, instance_id)
+                    .await?;
+                pollers.remove(&id);
+            }
+            leased.remove(&id);
+        }
+    }
+
+    // Leases past the fair share go back to the other instances. Sharings in the middle of a
+    // poll are kept until the next heartbeat so that no two instances poll them at once.
+    let extra_leases: Vec<String> = leased
+        .iter()
+        .filter(|id| !cancelled.contains_key(*id))
+        .filter(|id| {
+            pollers
+                .get(*id)
+                .is_none_or(|poller| poller.state.lock().unwrap().status != PollerStatus::Polling)
+        })
+        .take(leased.len().saturating_sub(fair_share))
+        .cloned()
+        .collect();
+    for id in extra_leases {
+        pollers.remove(&id);
+        leased.remove(&id);
+        entity_sharing_core
+            .release_entity_sharing_lease(&id, instance_id)
+            .await?;
+    }
+
+    for id in desired.keys() {
+        if leased.len() >= fair_share {
+            break;
+        }
+        if !leased.contains(id)
+            && entity_sharing_core
+                .acquire_entity_sharing_lease(
+                    id,
+                    instance_id,
+                    now.timestamp(),
+                    expires_at.timestamp(),
+                )
+                .await?
+        {
+            leased.insert(id.clone());
+        }
+    }
+
+    pollers.retain(|id, _| leased.contains(id) && !cancelled.contains_key(id));
+    for id in leased.iter() {
+        if cancelled.contains_key(id) {
+            continue;
+        }
+        let Some(entity_sharing) = desired.remove(id) else {
+            continue;
+        };
+        match pollers.get(id) {
+            Some(poller) => {
+                // Pollers track their own cursor and health, so only definition changes are
+                // handed over.
+                poller.control.send_if_modified(|control| {
+                    let unchanged = same_definition(&entity_sharing, &control.entity_sharing);
+                    if !unchanged {
+                        control.entity_sharing = entity_sharing;
+                    }
+                    !unchanged
+                });
+            }
+            None => {
+                let poller = spawn_poller(
+                    entity_sharing,
+                    instance_id,
+                    Arc::clone(entity_subscription_core),
+                    shutdown.clone(),
+                );
+                pollers.insert(id.clone(), poller);
+            }
+        }
+    }
+    Ok(expires_at)
+}
+
+/// Hands a new manual poll to the sharing poller, or runs it on its own task if the sharing
+/// isn't being polled. Sharings leased to another live instance are left to that instance.
+async fn trigger_poll(
+    pollers: &HashMap<String, Poller>,
+    entity_sharing_id: &str,
+    instance_id: &str,
+    entity_subscription_core: &Arc<EntitySubscriptionCore<'static>>,
+    shutdown: &ShutdownCoordinator,
+) -> Result<ManualPoll, Error> {
+    let entity_sharing = entity_subscription_core
+        .entity_sharing_core
+        .get_entity_sharing(entity_sharing_id)
+        .await?;
+    if entity_sharing.python_script.is_none() {
+        return Err(Error::BadRequestError(format!(
+            "Entity sharing {} has no script to poll",
+            entity_sharing_id
+        )));
+    }
+    if !pollers.contains_key(entity_sharing_id) {
+        let lease = entity_subscription_core
+            .entity_sharing_core
+            .get_entity_sharing_lease(entity_sharing_id)
+            .await?;
+        if let Some(lease) = lease.filter(|lease| {
+            lease.instance_id != *instance_id && lease.expires_at > Utc::now().timestamp()
+        }) {
+            return Err(Error::ConflictError(format!(
+                "Entity sharing {} is polled by instance {}",
+                entity_sharing_id, lease.instance_id
+            )));
+        }
+    }
+
+    let manual_poll = ManualPoll {
+        run_id: Uuid::now_v7().to_string(),
+        closed: Arc::new(AtomicBool::new(false)),
+    };
+    let handed_to_poller = pollers
+        .get(entity_sharing_id)
+        .is_some_and(|poller| poller.manual_polls.send(manual_poll.clone()).is_ok());
+    if !handed_to_poller {
+        let manual_poll = manual_poll.clone();
+        let entity_subscription_core = Arc::clone(entity_subscription_core);
+        let fence = PollFence::new(instance_id, false, CancellationToken::new());
+        shutdown.spawn(async move {
+            let mut entity_sharing = entity_sharing;
+            let error = poll_entity_sharing(
+                &mut entity_sharing,
+                &entity_subscription_core,
+                EntitySharingRunTrigger::Manual,
+                manual_poll.run_id.clone(),
+                &fence,
+            )
+            .await
+            .err()
+            .map(|e| format!("{:?}", e));
+            let mut health = entity_sharing.health.clone();
+            health.record_poll(error, &retry_policy(&entity_sharing));
+            save_health(&entity_sharing, &health, &entity_subscription_core).await;
+            manual_poll.close();
+        });
+    }
+    Ok(manual_poll)
+}
+
+fn set_paused(
+    pollers: &HashMap<String, Poller>,
+    entity_sharing_id: &str,
+    paused: bool,
+) -> Result<PollerState, Error> {
+    let poller = pollers
+        .get(entity_sharing_id)
+        .ok_or(poller_not_found(entity_sharing_id))?;
+    poller
+        .control
+        .send_modify(|control| control.paused = paused);
+    let mut state = poller.state.lock().unwrap();
+    // A running poll finishes first, the poller reports its own status once it is done.
+    match (paused, state.status) {
+        (true, PollerStatus::Waiting) => {
+            state.status = PollerStatus::Paused;
+            state.next_poll_at = None;
+        }
+        (false, PollerStatus::Paused) => state.status = PollerStatus::Waiting,
+        _ => {}
+    }
+    Ok(state.clone())
+}
+
+fn spawn_poller(
+    entity_sharing: EntitySharing,
+    instance_id: &str,
+    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
+    shutdown: ShutdownCoordinator,
+) -> Poller {
+    let state = Arc::new(Mutex::new(PollerState {
+        entity_sharing_id: entity_sharing.id.clone(),
+        instance_id: instance_id.to_string(),
+        entity_sharing_name: entity_sharing.name.clone(),
+        status: PollerStatus::Waiting,
+        next_poll_at: None,
+        last_poll_started_at: None,
+        last_poll_ended_at: None,
+        health: entity_sharing.health.clone(),
+    }));
+    let (control, receiver) = watch::channel(PollerControl {
+        entity_sharing,
+        paused: false,
+    });
+    let (manual_polls, manual_poll_receiver) = mpsc::unbounded_channel();
+    let lease_lost = CancellationToken::new();
+    let fence = PollFence::new(instance_id, true, lease_lost.clone());
+    let span = info_span!(
+        "poller",
+        entity_sharing_id = %receiver.borrow().entity_sharing.id,
+    );
+    let task = shutdown.spawn(
+        run_poller(
+            receiver,
+            manual_poll_receiver,
+            Arc::clone(&state),
+            entity_subscription_core,
+            fence,
+            shutdown.clone(),
+        )
+        .instrument(span),
+    );
+    Poller {
+        state,
+        control,
+        manual_polls,
+        lease_lost,
+        task,
+    }
+}
+
+fn retry_policy(entity_sharing: &EntitySharing) -> PollingRetryPolicy {
+    entity_sharing
+        .polling_infos
+        .as_ref()
+        .map(|polling_infos| polling_infos.retry_policy.clone())
+        .unwrap_or_default()
+}
+
+async fn save_health(
+    entity_sharing: &EntitySharing,
+    health: &EntitySharingHealth,
+    entity_subscription_core: &EntitySubscriptionCore<'static>,
+) {
+    if let Err(e) = entity_subscription_core
+        .entity_sharing_core
+        .update_entity_sharing_health(&entity_sharing.id, health)
+        .await
+    {
+        error!(error = ?e, "Error saving entity sharing health");
+    }
+}
+
+/// Updates the health of the sharing in the poller state and in the database, if it changed.
+async fn set_health(
+    state: &Mutex<PollerState>,
+    health: EntitySharingHealth,
+    entity_sharing: &EntitySharing,
+    entity_subscription_core: &EntitySubscriptionCore<'static>,
+) {
+    {
+        let mut state = state.lock().unwrap();
+        if state.health == health {
+            return;
+        }
+        state.health = health.clone();
+    }
+    save_health(entity_sharing, &health, entity_subscription_core).await;
+}
+
+/// Polls the sharing once, on schedule or for the manual poll, if any, keeping the poller state
+/// and the sharing health up to date.
+async fn run_poll(
+    state: &Mutex<PollerState>,
+    manual_poll: Option<&ManualPoll>,
+    entity_sharing: &mut EntitySharing,
+    entity_subscription_core: &EntitySubscriptionCore<'static>,
+    fence: &PollFence,
+) {
+    {
+        let mut state = state.lock().unwrap();
+        state.status = PollerStatus::Polling;
+        state.next_poll_at = None;
+        state.last_poll_started_at = Some(Utc::now().timestamp());
+    }
+    let (trigger, run_id) = match manual_poll {
+        Some(manual_poll) => {
+            manual_poll.close();
+            (EntitySharingRunTrigger::Manual, manual_poll.run_id.clone())
+        }
+        None => (
+            EntitySharingRunTrigger::Scheduled,
+            Uuid::now_v7().to_string(),
+        ),
+    };
+    let error = match poll_entity_sharing(
+        entity_sharing,
+        entity_subscription_core,
+        trigger,
+        run_id,
+        fence,
+    )
+    .await
+    {
+        Ok(_) => None,
+        Err(e) => {
+            warn!(error = ?e, "Error polling entity sharing");
+            Some(format!("{:?}", e))
+        }
+    };
+
+    let mut health = {
+        let mut state = state.lock().unwrap();
+        state.last_poll_ended_at = Some(Utc::now().timestamp());
+        state.health.clone()
+    };
+    health.record_poll(error, &retry_policy(entity_sharing));
+    set_health(state, health, entity_sharing, entity_subscription_core).await;
+}
+
+async fn run_poller(
+    mut control: watch::Receiver<PollerControl>,
+    mut manual_polls: mpsc::UnboundedReceiver<ManualPoll>,
+    state: Arc<Mutex<PollerState>>,
+    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
+    fence: PollFence,
+    shutdown: ShutdownCoordinator,
+) {
+    let mut polling_cursor: Option<Value> = control.borrow().entity_sharing.polling_cursor.clone();
+    let mut last_poll_started_at: Option<DateTime<Utc>> = None;
+    let mut next_poll: Option<(PollingSchedule, PollingRetryPolicy, DateTime<Utc>)> = None;
+
+    info!(
+        entity_sharing_name = %control.borrow().entity_sharing.name,
+        "Starting entity sharing poller"
+    );
+    while !shutdown.is_shutting_down() && !fence.lease_lost.is_cancelled() {
+        let PollerControl {
+            mut entity_sharing,
+            paused,
+        } = control.borrow_and_update().clone();
+        let Some(polling_infos) = entity_sharing.polling_infos.clone() else {
+            break;
+        };
+        state.lock().unwrap().entity_sharing_name = entity_sharing.name.clone();
+        entity_sharing.polling_cursor = polling_cursor.clone();
+
+        let mut health = state.lock().unwrap().health.clone();
+        if paused {
+            {
+                let mut state = state.lock().unwrap();
+                state.status = PollerStatus::Paused;
+                state.next_poll_at = None;
+            }
+            health.status = EntitySharingHealthStatus::Paused;
+            set_health(&state, health, &entity_sharing, &entity_subscription_core).await;
+            next_poll = None;
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = fence.lost() => break,
+                changed = control.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                Some(manual_poll) = manual_polls.recv() => {
+                    run_poll(
+                        &state,
+                        Some(&manual_poll),
+                        &mut entity_sharing,
+                        &entity_subscription_core,
+                        &fence,
+                    )
+                    .await;
+                    polling_cursor = entity_sharing.polling_cursor;
+                }
+            }
+            continue;
+        }
+
+        health.refresh_status(&polling_infos.retry_policy);
+        set_health(
+            &state,
+            health.clone(),
+            &entity_sharing,
+            &entity_subscription_core,
+        )
+        .await;
+
+        // The deadline is kept across definition updates and manual polls unless the schedule,
+        // the retry policy or the failure count changed.
+        let last_failure_at = health
+            .last_failure_at
+            .and_then(|last_failure_at| DateTime::from_timestamp(last_failure_at, 0));
+        let next_poll_at = match &next_poll {
+            Some((schedule, retry_policy, at))
+                if *schedule == polling_infos.schedule
+                    && *retry_policy == polling_infos.retry_policy =>
+            {
+                *at
+            }
+            _ => match last_failure_at {
+                Some(last_failure_at) if health.consecutive_failures > 0 => {
+                    last_failure_at
+                        + polling_infos
+                            .retry_policy
+                            .retry_delay(health.consecutive_failures)
+                }
+                _ => {
+                    let now = Utc::now();
+                    let delay = match last_poll_started_at {
+                        None => polling_infos.schedule.first_poll_delay(now),
+                        Some(started_at) => polling_infos.schedule.next_poll_delay(started_at, now),
+                    };
+                    let delay = delay.unwrap_or_else(|e| {
+                        error!(error = ?e, "Error scheduling next poll of entity sharing");
+                        SCHEDULE_ERROR_DELAY
+                    });
+                    now + delay
+                }
+            },
+        };
+        next_poll = Some((
+            polling_infos.schedule.clone(),
+            polling_infos.retry_policy.clone(),
+            next_poll_at,
+        ));
+        {
+            let mut state = state.lock().unwrap();
+            state.status = PollerStatus::Waiting;
+            state.next_poll_at = Some(next_poll_at.timestamp());
+        }
+
+        let delay = (next_poll_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = fence.lost() => break,
+            _ = tokio::time::sleep(delay) => {}
+            changed = control.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                continue;
+            }
+            Some(manual_poll) = manual_polls.recv() => {
+                run_poll(
+                    &state,
+                    Some(&manual_poll),
+                    &mut entity_sharing,
+                    &entity_subscription_core,
+                    &fence,
+                )
+                .await;
+                polling_cursor = entity_sharing.polling_cursor;
+                let consecutive_failures = state.lock().unwrap().health.consecutive_failures;
+                if consecutive_failures != health.consecutive_failures {
+                    next_poll = None;
+                }
+                continue;
+            }
+        }
+
+        last_poll_started_at = Some(Utc::now());
+        next_poll = None;
+        run_poll(
+            &state,
+            None,
+            &mut entity_sharing,
+            &entity_subscription_core,
+            &fence,
+        )
+        .await;
+        polling_cursor = entity_sharing.polling_cursor;
+    }
+
+    info!("Stopping entity sharing poller");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connected_app::connected_app_core::ConnectedAppCore;
+    use crate::connected_app::connected_app_limiter::ConnectedAppLimiter;
+    use crate::connected_app::connected_app_repository::connected_app_sqlite_repository::ConnectedAppSQLiteRepository;
+    use crate::entity_sharing::entity_sharing_repository::entity_sharing_sqlite_repository::EntitySharingSQLiteRepository;
+    use crate::shared::db::get_db;
+
+    const ENTITY_SHARING_ID: &str = "entity-sharing";
+
+    /// Core of an instance of its own, the way another process would open the database.
+    async fn open_instance(database_url: &str) -> EntitySharingCore<'static> {
+        let pool = Box::leak(Box::new(get_db(database_url).await.unwrap()));
+        let connected_app_core = Arc::new(ConnectedAppCore {
+            connected_app_repository: Box::new(ConnectedAppSQLiteRepository { pool }),
+            connected_app_limiter: ConnectedAppLimiter::default(),
+            publish: Box::new(|_, _| {}),
+        });
+        EntitySharingCore::new(
+            connected_app_core,
+            Box::new(EntitySharingSQLiteRepository { pool }),
+            Box::new(|_, _| {}),
+        )
+    }
+
+    #[tokio::test]
+    async fn two_instances_against_the_same_database() {
+        let path = std::env::temp_dir().join(format!("heutl-{}.db", Uuid::now_v7()));
+        let database_url = format!("sqlite://{}", path.display());
+        let a = open_instance(&database_url).await;
+        let b = open_instance(&database_url).await;
+        let now = Utc::now().timestamp();
+
+        assert!(
+            a.acquire_entity_sharing_lease(ENTITY_SHARING_ID, "a", now, now + 60)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !b.acquire_entity_sharing_lease(ENTITY_SHARING_ID, "b", now, now + 60)
+                .await
+                .unwrap()
+        );
+        let fence_a = PollFence::new("a", true, CancellationToken::new());
+        let fence_b = PollFence::new("b", true, CancellationToken::new());
+        fence_a.check(&a, ENTITY_SHARING_ID).await.unwrap();
+        assert!(fence_b.check(&b, ENTITY_SHARING_ID).await.is_err());
+        // Neither can a manual poll of b write while a polls the sharing.
+        let manual_fence_b = PollFence::new("b", false, CancellationToken::new());
+        assert!(manual_fence_b.check(&b, ENTITY_SHARING_ID).await.is_err());
+
+        // a stopped heartbeating: b takes over once the lease expired, a's poll in progress
+        // mustn't deliver nor save its cursor anymore.
+        assert!(
+            b.acquire_entity_sharing_lease(ENTITY_SHARING_ID, "b", now + 60, now + 120)
+                .await
+                .unwrap()
+        );
+        assert!(fence_a.check(&a, ENTITY_SHARING_ID).await.is_err());
+        fence_b.check(&b, ENTITY_SHARING_ID).await.unwrap();
+
+        // The scheduler cancelling the poller is enough, whatever the database says.
+        fence_b.lease_lost.cancel();
+        assert!(fence_b.check(&b, ENTITY_SHARING_ID).await.is_err());
+        tokio::time::timeout(Duration::from_secs(1), fence_b.lost())
+            .await
+            .unwrap();
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn manual_polls_of_unleased_sharings_pass_the_fence() {
+        let path = std::env::temp_dir().join(format!("heutl-{}.db", Uuid::now_v7()));
+        let instance = open_instance(&format!("sqlite://{}", path.display())).await;
+        let now = Utc::now().timestamp();
+
+        PollFence::new("a", false, CancellationToken::new())
+            .check(&instance, ENTITY_SHARING_ID)
+            .await
+            .unwrap();
+        assert!(
+            PollFence::new("a", true, CancellationToken::new())
+                .check(&instance, ENTITY_SHARING_ID)
+                .await
+                .is_err()
+        );
+        // An expired lease of another instance doesn't count.
+        assert!(
+            instance
+                .acquire_entity_sharing_lease(ENTITY_SHARING_ID, "b", now - 120, now - 60)
+                .await
+                .unwrap()
+        );
+        PollFence::new("a", false, CancellationToken::new())
+            .check(&instance, ENTITY_SHARING_ID)
+            .await
+            .unwrap();
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
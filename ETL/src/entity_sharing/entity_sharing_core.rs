@@ -1,25 +1,43 @@
-use crate::shared::bus::{Commands, TopicIds};
+use crate::shared::bus::{Commands, Publish, TopicIds};
 
 use crate::connected_app::connected_app_core::ConnectedAppCore;
-use crate::entity_sharing::entity_sharing_model::EntitySharing;
+use crate::entity_sharing::entity_sharing_model::{
+    EntitySharing, EntitySharingDetails, EntitySharingHealth, EntitySharingLease,
+    EntitySharingRevision, EntitySharingRevisionDiff, EntitySharingRun, EntitySharingRunStats,
+    EntitySharingTestResult, HeldDelivery, HeldDeliveryStatus, TestEntitySharingDraftParams,
+    TestEntitySharingParams,
+};
 use crate::entity_sharing::entity_sharing_repository::{
-    CreateEntitySharingParams, EntitySharingRepository, UpdateEntitySharingParams,
+    CreateEntitySharingParams, EntitySharingFilter, EntitySharingRepository,
+    UpdateEntitySharingParams,
 };
-use crate::shared::errors::Error;
+use crate::shared::errors::{Error, FieldErrors};
+use crate::shared::json_diff::diff_json;
+use crate::shared::json_schema_validator::{validate_entity_list, validate_json_schema};
 use crate::shared::merge_struct::Merge;
+use crate::shared::pagination::{Page, PageRequest};
+use crate::shared::script_runtime::{run_script, validate_script};
+use chrono::Utc;
+use serde_json::{Value, json};
 use std::sync::Arc;
+use std::time::Instant;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Runs kept per sharing, older ones are deleted as new ones are recorded.
+const RUN_HISTORY_SIZE: i64 = 1000;
 
 pub struct EntitySharingCore<'a> {
     pub connected_app_core: Arc<ConnectedAppCore<'a>>,
     pub entity_sharing_repository: Box<dyn EntitySharingRepository + 'a>,
-    pub publish: Box<dyn Fn(Commands, Option<TopicIds>) -> () + Send + Sync>,
+    pub publish: Publish,
 }
 
 impl<'a> EntitySharingCore<'a> {
     pub fn new(
         connected_app_core: Arc<ConnectedAppCore<'a>>,
         entity_sharing_repository: Box<dyn EntitySharingRepository + 'a>,
-        publish: Box<dyn Fn(Commands, Option<TopicIds>) -> () + Send + Sync>,
+        publish: Publish,
     ) -> Self {
         Self {
             connected_app_core,
@@ -30,67 +48,203 @@ impl<'a> EntitySharingCore<'a> {
     pub async fn create_entity_sharing(
         &self,
         params: &CreateEntitySharingParams,
+        author: &Option<String>,
     ) -> Result<EntitySharing, Error> {
-        let connected_app_core = self.connected_app_core.clone();
-        let entity_sharing_repository = &self.entity_sharing_repository;
+        let mut field_errors = FieldErrors::default();
+        field_errors.check_id(&params.id);
+        if params.name.trim().is_empty() {
+            field_errors.add("name", "Can't be empty".to_string());
+        }
+        field_errors.check_reference(
+            "connected_app_id",
+            self.connected_app_core
+                .get_connected_app(&params.connected_app_id)
+                .await,
+            format!("Unknown connected app {}", params.connected_app_id),
+        )?;
+        field_errors.check("json_schema", validate_json_schema(&params.json_schema))?;
+        if let Some(polling_infos) = &params.polling_infos {
+            field_errors.check("polling_infos", polling_infos.validate())?;
+        }
+        if let Some(python_script) = &params.python_script {
+            field_errors.check(
+                "python_script",
+                validate_script(
+                    params.script_runtime,
+                    python_script,
+                    &params.allowed_modules,
+                )
+                .await,
+            )?;
+        }
+        field_errors.result()?;
 
-        let result = connected_app_core
-            .get_connected_app(&params.connected_app_id)
-            .await
-            .map(async move |_| {
-                entity_sharing_repository
-                    .create_entity_sharing(params)
-                    .await
-            })?
+        let id = params
+            .id
+            .clone()
+            .unwrap_or_else(|| Uuid::now_v7().to_string());
+        let result = self
+            .entity_sharing_repository
+            .create_entity_sharing(&id, params, author)
             .await?;
-        match result.polling_infos {
-            Some(_) => {
-                (self.publish)(
-                    Commands::EntitySharingCreated {
-                        entity_sharing: (result.clone()),
-                    },
-                    Some(TopicIds::EntitySharingCreated),
-                );
-            }
-            None => {}
-        }
+        (self.publish)(
+            Commands::EntitySharingCreated {
+                entity_sharing: (result.clone()),
+            },
+            Some(TopicIds::EntitySharingCreated),
+        );
 
-        return Ok(result);
+        Ok(result)
     }
 
     pub async fn update_entity_sharing(
         &self,
-        id: &String,
+        id: &str,
         params: &UpdateEntitySharingParams,
+        author: &Option<String>,
     ) -> Result<EntitySharing, Error> {
         let current_entity_sharing = self.get_entity_sharing(id).await?;
         let updated_entity_sharing = current_entity_sharing.merge(params.clone());
+        return self
+            .save_entity_sharing(updated_entity_sharing, author)
+            .await;
+    }
+
+    async fn save_entity_sharing(
+        &self,
+        updated_entity_sharing: EntitySharing,
+        author: &Option<String>,
+    ) -> Result<EntitySharing, Error> {
+        if let Some(polling_infos) = &updated_entity_sharing.polling_infos {
+            polling_infos.validate()?;
+        }
+        validate_json_schema(&updated_entity_sharing.json_schema)?;
+        if let Some(python_script) = &updated_entity_sharing.python_script {
+            validate_script(
+                updated_entity_sharing.script_runtime,
+                python_script,
+                &updated_entity_sharing.allowed_modules,
+            )
+            .await?;
+        }
         let _rows_affected = self
             .entity_sharing_repository
-            .update_entity_sharing(&updated_entity_sharing)
+            .update_entity_sharing(&updated_entity_sharing, author)
             .await?;
-        match updated_entity_sharing.polling_infos {
-            Some(_) => {
-                (self.publish)(
-                    Commands::EntitySharingUpdated {
-                        entity_sharing: updated_entity_sharing.clone(),
-                    },
-                    Some(TopicIds::EntitySharingUpdated),
-                );
-            }
-            None => {}
-        }
-        return Ok(updated_entity_sharing);
+        // Published even without polling infos, so that a poller that is no longer wanted stops.
+        (self.publish)(
+            Commands::EntitySharingUpdated {
+                entity_sharing: updated_entity_sharing.clone(),
+            },
+            Some(TopicIds::EntitySharingUpdated),
+        );
+        Ok(updated_entity_sharing)
     }
 
-    pub async fn get_entity_sharing(&self, id: &String) -> Result<EntitySharing, Error> {
+    pub async fn get_entity_sharing(&self, id: &str) -> Result<EntitySharing, Error> {
         return self.entity_sharing_repository.get_entity_sharing(id).await;
     }
 
-    pub async fn get_all_entity_sharings(&self) -> Result<Vec<EntitySharing>, Error> {
+    pub async fn get_entity_sharing_details(
+        &self,
+        id: &str,
+    ) -> Result<EntitySharingDetails, Error> {
+        return self
+            .entity_sharing_repository
+            .get_entity_sharing_details(id)
+            .await;
+    }
+
+    pub async fn get_entity_sharings(
+        &self,
+        filter: &EntitySharingFilter,
+        page: &PageRequest,
+    ) -> Result<Page<EntitySharingDetails>, Error> {
+        return self
+            .entity_sharing_repository
+            .get_entity_sharings(filter, page)
+            .await;
+    }
+
+    pub async fn get_entity_sharing_revisions(
+        &self,
+        id: &str,
+    ) -> Result<Vec<EntitySharingRevision>, Error> {
         return self
             .entity_sharing_repository
-            .get_all_entity_sharings()
+            .get_entity_sharing_revisions(id)
+            .await;
+    }
+
+    pub async fn get_entity_sharing_revision(
+        &self,
+        id: &str,
+        revision: i64,
+    ) -> Result<EntitySharingRevision, Error> {
+        self.entity_sharing_repository
+            .get_entity_sharing_revision(id, revision)
+            .await
+    }
+
+    /// Compares a revision with `against`, or with the revision right before it.
+    pub async fn get_entity_sharing_revision_diff(
+        &self,
+        id: &str,
+        revision: i64,
+        against: Option<i64>,
+    ) -> Result<EntitySharingRevisionDiff, Error> {
+        let to = self
+            .entity_sharing_repository
+            .get_entity_sharing_revision(id, revision)
+            .await?;
+        let from_revision = against.unwrap_or(revision - 1);
+        let from = match from_revision {
+            0 => None,
+            _ => Some(
+                self.entity_sharing_repository
+                    .get_entity_sharing_revision(id, from_revision)
+                    .await?,
+            ),
+        };
+        let before = match &from {
+            Some(from) => serde_json::to_value(&from.snapshot)?,
+            None => json!({}),
+        };
+        Ok(EntitySharingRevisionDiff {
+            entity_sharing_id: id.to_string(),
+            from_revision: from.map(|from| from.revision),
+            to_revision: to.revision,
+            changes: diff_json(&before, &serde_json::to_value(&to.snapshot)?),
+        })
+    }
+
+    /// Restores the definition stored in a revision. This is recorded as a new revision and
+    /// restarts polling with the restored definition.
+    pub async fn rollback_entity_sharing(
+        &self,
+        id: &str,
+        revision: i64,
+        author: &Option<String>,
+    ) -> Result<EntitySharing, Error> {
+        let snapshot = self
+            .entity_sharing_repository
+            .get_entity_sharing_revision(id, revision)
+            .await?
+            .snapshot;
+        let current_entity_sharing = self.get_entity_sharing(id).await?;
+        let restored_entity_sharing = EntitySharing {
+            name: snapshot.name,
+            polling_infos: snapshot.polling_infos,
+            json_schema: snapshot.json_schema,
+            is_array: snapshot.is_array,
+            python_script: snapshot.python_script,
+            script_runtime: snapshot.script_runtime,
+            allowed_modules: snapshot.allowed_modules,
+            updated_at: Utc::now().timestamp(),
+            ..current_entity_sharing
+        };
+        return self
+            .save_entity_sharing(restored_entity_sharing, author)
             .await;
     }
 
@@ -100,4 +254,610 @@ impl<'a> EntitySharingCore<'a> {
             .get_all_polling_entity_sharings()
             .await;
     }
+
+    pub async fn update_entity_sharing_health(
+        &self,
+        id: &str,
+        health: &EntitySharingHealth,
+    ) -> Result<u64, Error> {
+        return self
+            .entity_sharing_repository
+            .update_entity_sharing_health(id, health)
+            .await;
+    }
+
+    pub async fn create_entity_sharing_run(&self, run: &EntitySharingRun) -> Result<(), Error> {
+        self.entity_sharing_repository
+            .create_entity_sharing_run(run)
+            .await?;
+        self.entity_sharing_repository
+            .prune_entity_sharing_runs(&run.entity_sharing_id, RUN_HISTORY_SIZE)
+            .await?;
+        (self.publish)(
+            Commands::EntitySharingRunRecorded { run: run.clone() },
+            Some(TopicIds::EntitySharingRunRecorded),
+        );
+        Ok(())
+    }
+
+    pub async fn get_entity_sharing_runs(
+        &self,
+        entity_sharing_id: &str,
+        limit: i64,
+    ) -> Result<Vec<EntitySharingRun>, Error> {
+        self.get_entity_sharing(entity_sharing_id).await?;
+        return self
+            .entity_sharing_repository
+            .get_entity_sharing_runs(entity_sharing_id, limit.clamp(1, RUN_HISTORY_SIZE))
+            .await;
+    }
+
+    /// Not found until the run is over, runs are only recorded once they end.
+    pub async fn get_entity_sharing_run(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<EntitySharingRun, Error> {
+        return self
+            .entity_sharing_repository
+            .get_entity_sharing_run(entity_sharing_id, id)
+            .await;
+    }
+
+    /// Success rate, p95 duration and last outcomes over the kept run history.
+    pub async fn get_entity_sharing_run_stats(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<EntitySharingRunStats, Error> {
+        let runs = self
+            .get_entity_sharing_runs(entity_sharing_id, RUN_HISTORY_SIZE)
+            .await?;
+        let success_count = runs.iter().filter(|run| run.succeeded()).count();
+        let mut durations: Vec<i64> = runs.iter().map(|run| run.duration_ms).collect();
+        durations.sort_unstable();
+        // Nearest-rank percentile.
+        let p95_duration_ms = match durations.len() {
+            0 => None,
+            count => Some(durations[(count * 95).div_ceil(100) - 1]),
+        };
+        Ok(EntitySharingRunStats {
+            entity_sharing_id: entity_sharing_id.to_string(),
+            run_count: runs.len(),
+            success_count,
+            success_rate: (!runs.is_empty()).then(|| success_count as f64 / runs.len() as f64),
+            p95_duration_ms,
+            last_success_at: runs
+                .iter()
+                .find(|run| run.succeeded())
+                .map(|run| run.ended_at),
+            last_failure_at: runs
+                .iter()
+                .find(|run| !run.succeeded())
+                .map(|run| run.ended_at),
+        })
+    }
+
+    /// Checks a polled list against the entity count guardrail of the sharing. Returns the held
+    /// delivery when the list must not reach the subscriptions, resolving the previous held
+    /// delivery, if any, along the way. The cursor of a held list is kept with it rather than
+    /// saved, a confirming poll saving its own.
+    pub async fn apply_entity_count_guardrail(
+        &self,
+        entity_sharing: &EntitySharing,
+        run_id: &str,
+        data: &Value,
+        entity_count: i64,
+        cursor: &Option<Value>,
+    ) -> Result<Option<HeldDelivery>, Error> {
+        let Some(guardrail) = entity_sharing
+            .polling_infos
+            .as_ref()
+            .and_then(|polling_infos| polling_infos.guardrail.as_ref())
+        else {
+            return Ok(None);
+        };
+        let open_held_delivery = self
+            .entity_sharing_repository
+            .get_open_held_delivery(&entity_sharing.id)
+            .await?;
+        let previous_entity_count = self
+            .entity_sharing_repository
+            .get_last_delivered_entity_count(&entity_sharing.id)
+            .await?;
+        let crossed = previous_entity_count.filter(|previous_entity_count| {
+            guardrail.is_crossed(*previous_entity_count, entity_count)
+        });
+
+        let Some(previous_entity_count) = crossed else {
+            if let Some(open_held_delivery) = open_held_delivery {
+                self.entity_sharing_repository
+                    .resolve_held_delivery(
+                        &open_held_delivery.id,
+                        HeldDeliveryStatus::Discarded,
+                        Utc::now().timestamp(),
+                    )
+                    .await?;
+            }
+            return Ok(None);
+        };
+        if let Some(open_held_delivery) = open_held_delivery {
+            let status = match guardrail.confirm_on_next_poll {
+                true => HeldDeliveryStatus::Confirmed,
+                false => HeldDeliveryStatus::Superseded,
+            };
+            self.entity_sharing_repository
+                .resolve_held_delivery(&open_held_delivery.id, status, Utc::now().timestamp())
+                .await?;
+            if status == HeldDeliveryStatus::Confirmed {
+                return Ok(None);
+            }
+        }
+
+        let held_delivery = HeldDelivery {
+            id: Uuid::now_v7().to_string(),
+            entity_sharing_id: entity_sharing.id.clone(),
+            run_id: run_id.to_string(),
+            data: data.clone(),
+            entity_count,
+            previous_entity_count,
+            cursor: cursor.clone(),
+            status: HeldDeliveryStatus::Held,
+            created_at: Utc::now().timestamp(),
+            resolved_at: None,
+        };
+        self.entity_sharing_repository
+            .create_held_delivery(&held_delivery)
+            .await?;
+        warn!(
+            entity_sharing_id = %entity_sharing.id,
+            held_delivery_id = %held_delivery.id,
+            entity_count,
+            previous_entity_count,
+            "Delivery held, the entity count dropped past the guardrail"
+        );
+        (self.publish)(
+            Commands::DeliveryHeld {
+                held_delivery: held_delivery.clone(),
+            },
+            Some(TopicIds::DeliveryHeld),
+        );
+        Ok(Some(held_delivery))
+    }
+
+    pub async fn get_held_deliveries(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Vec<HeldDelivery>, Error> {
+        self.get_entity_sharing(entity_sharing_id).await?;
+        return self
+            .entity_sharing_repository
+            .get_held_deliveries(entity_sharing_id)
+            .await;
+    }
+
+    pub async fn get_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<HeldDelivery, Error> {
+        return self
+            .entity_sharing_repository
+            .get_held_delivery(entity_sharing_id, id)
+            .await;
+    }
+
+    /// Approves or rejects a delivery still being held.
+    pub async fn resolve_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+        status: HeldDeliveryStatus,
+    ) -> Result<HeldDelivery, Error> {
+        let held_delivery = self
+            .entity_sharing_repository
+            .get_held_delivery(entity_sharing_id, id)
+            .await?;
+        let resolved_at = Utc::now().timestamp();
+        let resolved = held_delivery.status == HeldDeliveryStatus::Held
+            && self
+                .entity_sharing_repository
+                .resolve_held_delivery(id, status, resolved_at)
+                .await?
+                == 1;
+        if !resolved {
+            return Err(Error::ConflictError(format!(
+                "Delivery {} is no longer held",
+                id
+            )));
+        }
+        let held_delivery = HeldDelivery {
+            status,
+            resolved_at: Some(resolved_at),
+            ..held_delivery
+        };
+        (self.publish)(
+            Commands::HeldDeliveryResolved {
+                held_delivery: held_delivery.clone(),
+            },
+            Some(TopicIds::HeldDeliveryResolved),
+        );
+        Ok(held_delivery)
+    }
+
+    /// Records that the instance is alive and polling until `expires_at`.
+    pub async fn heartbeat_polling_instance(
+        &self,
+        instance_id: &str,
+        expires_at: i64,
+    ) -> Result<(), Error> {
+        return self
+            .entity_sharing_repository
+            .heartbeat_polling_instance(instance_id, expires_at)
+            .await;
+    }
+
+    pub async fn count_live_polling_instances(&self, now: i64) -> Result<i64, Error> {
+        return self
+            .entity_sharing_repository
+            .count_live_polling_instances(now)
+            .await;
+    }
+
+    /// Drops the instance and its leases so that other instances take over its sharings
+    /// without waiting for the leases to expire.
+    pub async fn release_polling_instance(&self, instance_id: &str) -> Result<(), Error> {
+        return self
+            .entity_sharing_repository
+            .release_polling_instance(instance_id)
+            .await;
+    }
+
+    pub async fn get_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<EntitySharingLease>, Error> {
+        return self
+            .entity_sharing_repository
+            .get_entity_sharing_lease(entity_sharing_id)
+            .await;
+    }
+
+    pub async fn acquire_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+        instance_id: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, Error> {
+        return self
+            .entity_sharing_repository
+            .acquire_entity_sharing_lease(entity_sharing_id, instance_id, now, expires_at)
+            .await;
+    }
+
+    pub async fn release_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+        instance_id: &str,
+    ) -> Result<u64, Error> {
+        return self
+            .entity_sharing_repository
+            .release_entity_sharing_lease(entity_sharing_id, instance_id)
+            .await;
+    }
+
+    pub async fn update_entity_sharing_polling_cursor(
+        &self,
+        id: &str,
+        polling_cursor: &Option<Value>,
+    ) -> Result<u64, Error> {
+        return self
+            .entity_sharing_repository
+            .update_entity_sharing_polling_cursor(id, polling_cursor)
+            .await;
+    }
+
+    /// Runs the sharing's script and validates its result without notifying any subscription.
+    pub async fn test_entity_sharing(
+        &self,
+        id: &str,
+        params: &TestEntitySharingParams,
+    ) -> Result<EntitySharingTestResult, Error> {
+        let entity_sharing = self.get_entity_sharing(id).await?;
+        let python_script = entity_sharing.python_script.ok_or(Error::BadRequestError(format!(
+            "Entity sharing {} has no script",
+            id
+        )))?;
+        let input = params.input.clone().unwrap_or(
+            entity_sharing
+                .polling_infos
+                .and_then(|polling_infos| polling_infos.input)
+                .unwrap_or(json!({})),
+        );
+        let cursor = params.cursor.clone().or(entity_sharing.polling_cursor);
+        return self
+            .test_entity_sharing_draft(&TestEntitySharingDraftParams {
+                python_script,
+                script_runtime: entity_sharing.script_runtime,
+                allowed_modules: entity_sharing.allowed_modules,
+                json_schema: entity_sharing.json_schema,
+                is_array: entity_sharing.is_array,
+                input: Some(input),
+                cursor,
+            })
+            .await;
+    }
+
+    pub async fn test_entity_sharing_draft(
+        &self,
+        params: &TestEntitySharingDraftParams,
+    ) -> Result<EntitySharingTestResult, Error> {
+        let draft = params.clone();
+        let started_at = Instant::now();
+        let run = tokio::task::spawn_blocking(move || {
+            run_script(
+                draft.script_runtime,
+                &draft.python_script,
+                &draft.input.unwrap_or(json!({})),
+                &draft.cursor,
+                &draft.allowed_modules,
+            )
+        })
+        .await
+        .map_err(|e| Error::ScriptError(e.to_string()))??;
+        let duration_ms = started_at.elapsed().as_millis() as u64;
+
+        let mut test_result = EntitySharingTestResult {
+            output: None,
+            cursor: None,
+            logs: run.logs.clone(),
+            duration_ms,
+            validation_errors: vec![],
+            error: None,
+        };
+        match run.output_json() {
+            Ok(output) => {
+                test_result.validation_errors =
+                    validate_entity_list(&params.json_schema, params.is_array, &output.result)?;
+                test_result.output = Some(output.result);
+                test_result.cursor = output.cursor;
+            }
+            Err(Error::ScriptError(_)) => {
+                test_result.error = Some("Script exited with an error, see logs".to_string());
+            }
+            Err(e) => {
+                test_result.error = Some(format!("{:?}", e));
+            }
+        }
+        Ok(test_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connected_app::connected_app_limiter::ConnectedAppLimiter;
+    use crate::connected_app::connected_app_repository::connected_app_sqlite_repository::ConnectedAppSQLiteRepository;
+    use crate::entity_sharing::entity_polling_schedule::{
+        IntervalMode, PollingRetryPolicy, PollingSchedule,
+    };
+    use crate::entity_sharing::entity_sharing_model::{
+        EntityCountGuardrail, EntitySharingPollingInfos, EntitySharingRunTrigger,
+    };
+    use crate::entity_sharing::entity_sharing_repository::entity_sharing_sqlite_repository::EntitySharingSQLiteRepository;
+    use crate::shared::db::get_db;
+    use crate::shared::script_runtime::ScriptRuntimeKind;
+
+    /// Core with a sharing holding lists that lost half of the entities of the last delivered
+    /// one, 10 entities.
+    async fn open_core(confirm_on_next_poll: bool) -> (EntitySharingCore<'static>, EntitySharing) {
+        let path = std::env::temp_dir().join(format!("heutl-{}.db", Uuid::now_v7()));
+        let pool = Box::leak(Box::new(
+            get_db(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        ));
+        let connected_app_core = Arc::new(ConnectedAppCore {
+            connected_app_repository: Box::new(ConnectedAppSQLiteRepository { pool }),
+            connected_app_limiter: ConnectedAppLimiter::default(),
+            publish: Box::new(|_, _| {}),
+        });
+        let core = EntitySharingCore::new(
+            connected_app_core,
+            Box::new(EntitySharingSQLiteRepository { pool }),
+            Box::new(|_, _| {}),
+        );
+        let entity_sharing = core
+            .entity_sharing_repository
+            .create_entity_sharing(
+                "entity-sharing",
+                &CreateEntitySharingParams {
+                    id: None,
+                    name: "Assets".to_string(),
+                    connected_app_id: "connected-app".to_string(),
+                    json_schema: json!({}),
+                    python_script: None,
+                    is_array: true,
+                    script_runtime: ScriptRuntimeKind::Python,
+                    allowed_modules: vec![],
+                    polling_infos: Some(EntitySharingPollingInfos {
+                        schedule: PollingSchedule::Interval {
+                            polling_interval: 1000,
+                            interval_mode: IntervalMode::FixedDelay,
+                        },
+                        input: None,
+                        retry_policy: PollingRetryPolicy::default(),
+                        guardrail: Some(EntityCountGuardrail {
+                            min_drop_count: None,
+                            min_drop_percent: Some(50),
+                            confirm_on_next_poll,
+                        }),
+                    }),
+                },
+                &None,
+            )
+            .await
+            .unwrap();
+        record_run(&core, 10, None).await;
+        (core, entity_sharing)
+    }
+
+    async fn record_run(
+        core: &EntitySharingCore<'_>,
+        entity_count: i64,
+        held: Option<&HeldDelivery>,
+    ) {
+        core.create_entity_sharing_run(&EntitySharingRun {
+            id: Uuid::now_v7().to_string(),
+            entity_sharing_id: "entity-sharing".to_string(),
+            trigger: EntitySharingRunTrigger::Scheduled,
+            started_at: Utc::now().timestamp(),
+            ended_at: Utc::now().timestamp(),
+            duration_ms: 0,
+            entity_count: Some(entity_count),
+            valid: Some(true),
+            validation_errors: vec![],
+            subscriptions_notified: 0,
+            subscriptions_failed: 0,
+            error: None,
+            held_delivery_id: held.map(|held| held.id.clone()),
+        })
+        .await
+        .unwrap();
+    }
+
+    /// Polls `entity_count` entities, recording the run the way a poll does.
+    async fn poll(
+        core: &EntitySharingCore<'_>,
+        entity_sharing: &EntitySharing,
+        entity_count: i64,
+    ) -> Option<HeldDelivery> {
+        let data = Value::Array(vec![json!({}); entity_count as usize]);
+        let cursor = Some(json!({"count": entity_count}));
+        let held = core
+            .apply_entity_count_guardrail(entity_sharing, "run", &data, entity_count, &cursor)
+            .await
+            .unwrap();
+        record_run(core, entity_count, held.as_ref()).await;
+        held
+    }
+
+    async fn status(core: &EntitySharingCore<'_>, held: &HeldDelivery) -> HeldDeliveryStatus {
+        core.entity_sharing_repository
+            .get_held_delivery("entity-sharing", &held.id)
+            .await
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn drops_past_the_guardrail_are_held_with_their_cursor() {
+        let (core, entity_sharing) = open_core(true).await;
+
+        assert!(poll(&core, &entity_sharing, 6).await.is_none());
+        let held = poll(&core, &entity_sharing, 2).await.unwrap();
+
+        assert_eq!(held.status, HeldDeliveryStatus::Held);
+        assert_eq!(held.previous_entity_count, 6);
+        assert_eq!(held.cursor, Some(json!({"count": 2})));
+        let saved = core
+            .entity_sharing_repository
+            .get_held_delivery("entity-sharing", &held.id)
+            .await
+            .unwrap();
+        assert_eq!(saved.cursor, held.cursor);
+    }
+
+    #[tokio::test]
+    async fn a_second_drop_confirms_the_held_list() {
+        let (core, entity_sharing) = open_core(true).await;
+        let held = poll(&core, &entity_sharing, 4).await.unwrap();
+
+        assert!(poll(&core, &entity_sharing, 3).await.is_none());
+
+        assert_eq!(status(&core, &held).await, HeldDeliveryStatus::Confirmed);
+        // The confirmed count is the one later polls compare to.
+        assert!(poll(&core, &entity_sharing, 2).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_second_drop_supersedes_the_held_list_without_confirmation() {
+        let (core, entity_sharing) = open_core(false).await;
+        let held = poll(&core, &entity_sharing, 4).await.unwrap();
+
+        let next_held = poll(&core, &entity_sharing, 3).await.unwrap();
+
+        assert_eq!(status(&core, &held).await, HeldDeliveryStatus::Superseded);
+        assert_eq!(next_held.status, HeldDeliveryStatus::Held);
+        assert_eq!(next_held.previous_entity_count, 10);
+    }
+
+    #[tokio::test]
+    async fn a_recovered_count_discards_the_held_list() {
+        let (core, entity_sharing) = open_core(true).await;
+        let held = poll(&core, &entity_sharing, 4).await.unwrap();
+
+        assert!(poll(&core, &entity_sharing, 9).await.is_none());
+
+        assert_eq!(status(&core, &held).await, HeldDeliveryStatus::Discarded);
+    }
+
+    #[tokio::test]
+    async fn resolved_lists_are_no_longer_held() {
+        let (core, entity_sharing) = open_core(true).await;
+        let held = poll(&core, &entity_sharing, 4).await.unwrap();
+
+        let rejected = core
+            .resolve_held_delivery("entity-sharing", &held.id, HeldDeliveryStatus::Rejected)
+            .await
+            .unwrap();
+
+        assert_eq!(rejected.status, HeldDeliveryStatus::Rejected);
+        assert!(matches!(
+            core.resolve_held_delivery("entity-sharing", &held.id, HeldDeliveryStatus::Approved)
+                .await,
+            Err(Error::ConflictError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn runs_with_invalid_entities_or_failed_notifications_are_failures() {
+        let (core, _) = open_core(true).await;
+        let succeeded = core
+            .get_entity_sharing_runs("entity-sharing", 1)
+            .await
+            .unwrap()
+            .remove(0);
+        let invalid = EntitySharingRun {
+            id: Uuid::now_v7().to_string(),
+            valid: Some(false),
+            validation_errors: vec!["\"name\" is a required property".to_string()],
+            ended_at: succeeded.ended_at + 1,
+            ..succeeded.clone()
+        };
+        let undelivered = EntitySharingRun {
+            id: Uuid::now_v7().to_string(),
+            subscriptions_notified: 1,
+            subscriptions_failed: 1,
+            ended_at: succeeded.ended_at + 2,
+            ..succeeded.clone()
+        };
+        core.create_entity_sharing_run(&invalid).await.unwrap();
+        core.create_entity_sharing_run(&undelivered).await.unwrap();
+
+        let stats = core
+            .get_entity_sharing_run_stats("entity-sharing")
+            .await
+            .unwrap();
+
+        assert_eq!(stats.run_count, 3);
+        assert_eq!(stats.success_count, 1);
+        assert_eq!(stats.last_success_at, Some(succeeded.ended_at));
+        assert_eq!(stats.last_failure_at, Some(undelivered.ended_at));
+        let run = core
+            .get_entity_sharing_run("entity-sharing", &invalid.id)
+            .await
+            .unwrap();
+        assert_eq!(run.valid, Some(false));
+    }
 }
@@ -1,13 +1,151 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::fmt;
 
+use crate::entity_sharing::entity_polling_schedule::{PollingRetryPolicy, PollingSchedule};
 use crate::entity_sharing::entity_sharing_repository::UpdateEntitySharingParams;
+use crate::shared::json_diff::JsonChange;
+use crate::shared::errors::Error;
 use crate::shared::merge_struct::Merge;
+use crate::shared::pagination::{Paginated, SortField, SortValue};
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use chrono::Utc;
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
 pub struct EntitySharingPollingInfos {
-    pub polling_interval: u64,
+    #[serde(flatten)]
+    pub schedule: PollingSchedule,
+    /// Static input handed to the polling script as `input`.
+    #[serde(default)]
+    pub input: Option<Value>,
+    #[serde(default)]
+    pub retry_policy: PollingRetryPolicy,
+    /// Holds back polled lists that shrink suspiciously, no guardrail when omitted.
+    #[serde(default)]
+    pub guardrail: Option<EntityCountGuardrail>,
+}
+
+impl EntitySharingPollingInfos {
+    pub fn validate(&self) -> Result<(), Error> {
+        self.schedule.validate()?;
+        self.retry_policy.validate()?;
+        if let Some(guardrail) = &self.guardrail {
+            guardrail.validate()?;
+        }
+        Ok(())
+    }
+}
+
+fn default_confirm_on_next_poll() -> bool {
+    true
+}
+
+/// Drop in entity count, versus the last delivered poll, at which a polled list is held for
+/// approval instead of being delivered.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+pub struct EntityCountGuardrail {
+    /// Lists with at least this many entities less are held.
+    #[serde(default)]
+    pub min_drop_count: Option<u64>,
+    /// Lists with at least this percentage of the entities gone are held.
+    #[serde(default)]
+    pub min_drop_percent: Option<u32>,
+    /// A held list is delivered anyway when the next poll crosses the threshold again, the
+    /// drop being genuine then.
+    #[serde(default = "default_confirm_on_next_poll")]
+    pub confirm_on_next_poll: bool,
+}
+
+impl EntityCountGuardrail {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.min_drop_count.is_none() && self.min_drop_percent.is_none() {
+            return Err(Error::BadRequestError(
+                "guardrail needs min_drop_count or min_drop_percent".to_string(),
+            ));
+        }
+        if self.min_drop_count == Some(0) {
+            return Err(Error::BadRequestError(
+                "min_drop_count must be greater than 0".to_string(),
+            ));
+        }
+        if self
+            .min_drop_percent
+            .is_some_and(|min_drop_percent| min_drop_percent == 0 || min_drop_percent > 100)
+        {
+            return Err(Error::BadRequestError(
+                "min_drop_percent must be between 1 and 100".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_crossed(&self, previous_entity_count: i64, entity_count: i64) -> bool {
+        let drop = previous_entity_count - entity_count;
+        if drop <= 0 {
+            return false;
+        }
+        let count_crossed = self
+            .min_drop_count
+            .is_some_and(|min_drop_count| drop as u64 >= min_drop_count);
+        let percent_crossed = self.min_drop_percent.is_some_and(|min_drop_percent| {
+            drop * 100 >= min_drop_percent as i64 * previous_entity_count
+        });
+        count_crossed || percent_crossed
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum EntitySharingHealthStatus {
+    #[default]
+    Healthy,
+    /// The last polls failed and are being retried with backoff.
+    Degraded,
+    /// The circuit breaker is open after too many consecutive failures.
+    Failing,
+    Paused,
+}
+
+/// Outcome of the recent polls of a sharing, maintained by its poller.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, ToSchema)]
+pub struct EntitySharingHealth {
+    pub status: EntitySharingHealthStatus,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_success_at: Option<i64>,
+    pub last_failure_at: Option<i64>,
+}
+
+impl EntitySharingHealth {
+    pub fn record_poll(&mut self, error: Option<String>, retry_policy: &PollingRetryPolicy) {
+        let now = Utc::now().timestamp();
+        match error {
+            Some(error) => {
+                self.consecutive_failures += 1;
+                self.last_error = Some(error);
+                self.last_failure_at = Some(now);
+            }
+            None => {
+                self.consecutive_failures = 0;
+                self.last_success_at = Some(now);
+            }
+        }
+        self.refresh_status(retry_policy);
+    }
+
+    pub fn refresh_status(&mut self, retry_policy: &PollingRetryPolicy) {
+        self.status = if retry_policy.is_circuit_open(self.consecutive_failures) {
+            EntitySharingHealthStatus::Failing
+        } else if self.consecutive_failures > 0 {
+            EntitySharingHealthStatus::Degraded
+        } else {
+            EntitySharingHealthStatus::Healthy
+        };
+    }
 }
 
 impl From<String> for EntitySharingPollingInfos {
@@ -16,13 +154,13 @@ impl From<String> for EntitySharingPollingInfos {
     }
 }
 
-impl ToString for EntitySharingPollingInfos {
-    fn to_string(&self) -> String {
-        serde_json::to_string(self).unwrap()
+impl fmt::Display for EntitySharingPollingInfos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap())
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
 pub struct EntitySharing {
     pub id: String,
     pub name: String,
@@ -33,6 +171,256 @@ pub struct EntitySharing {
     pub json_schema: Value,
     pub is_array: bool,
     pub python_script: Option<String>,
+    /// Runtime `python_script` is written for.
+    pub script_runtime: ScriptRuntimeKind,
+    /// Modules `python_script` may import, submodules included.
+    pub allowed_modules: Vec<String>,
+    /// Cursor returned by the last successful poll, handed back to the script as `cursor`.
+    pub polling_cursor: Option<Value>,
+    #[serde(default)]
+    pub health: EntitySharingHealth,
+}
+
+impl EntitySharing {
+    pub fn mode(&self) -> EntitySharingMode {
+        match self.polling_infos {
+            Some(_) => EntitySharingMode::Polling,
+            None => EntitySharingMode::Push,
+        }
+    }
+}
+
+impl Paginated for EntitySharing {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn sort_value(&self, sort: SortField) -> SortValue {
+        match sort {
+            SortField::CreatedAt => SortValue::Integer(self.created_at),
+            SortField::UpdatedAt => SortValue::Integer(self.updated_at),
+            SortField::Name => SortValue::Text(self.name.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntitySharingMode {
+    /// Sharings with `polling_infos`, whose entities HEUTL polls.
+    Polling,
+    /// Sharings without `polling_infos`, whose entities the app pushes to `/entity/{id}`.
+    Push,
+}
+
+/// Entity sharing with how its entities are ingested and the number of its subscriptions.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySharingDetails {
+    #[serde(flatten)]
+    pub entity_sharing: EntitySharing,
+    pub mode: EntitySharingMode,
+    pub subscription_count: i64,
+}
+
+impl Paginated for EntitySharingDetails {
+    fn id(&self) -> &str {
+        self.entity_sharing.id()
+    }
+
+    fn sort_value(&self, sort: SortField) -> SortValue {
+        self.entity_sharing.sort_value(sort)
+    }
+}
+
+/// Immutable snapshot of a sharing definition, recorded on every change.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySharingRevision {
+    pub id: String,
+    pub entity_sharing_id: String,
+    pub revision: i64,
+    pub author: Option<String>,
+    pub created_at: i64,
+    pub snapshot: EntitySharingSnapshot,
+}
+
+/// Definition of a sharing as a revision records it, without the cursor and the health its
+/// polls keep changing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct EntitySharingSnapshot {
+    pub id: String,
+    pub name: String,
+    pub connected_app_id: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub polling_infos: Option<EntitySharingPollingInfos>,
+    pub json_schema: Value,
+    pub is_array: bool,
+    pub python_script: Option<String>,
+    pub script_runtime: ScriptRuntimeKind,
+    pub allowed_modules: Vec<String>,
+}
+
+impl From<&EntitySharing> for EntitySharingSnapshot {
+    fn from(entity_sharing: &EntitySharing) -> Self {
+        Self {
+            id: entity_sharing.id.clone(),
+            name: entity_sharing.name.clone(),
+            connected_app_id: entity_sharing.connected_app_id.clone(),
+            created_at: entity_sharing.created_at,
+            updated_at: entity_sharing.updated_at,
+            polling_infos: entity_sharing.polling_infos.clone(),
+            json_schema: entity_sharing.json_schema.clone(),
+            is_array: entity_sharing.is_array,
+            python_script: entity_sharing.python_script.clone(),
+            script_runtime: entity_sharing.script_runtime,
+            allowed_modules: entity_sharing.allowed_modules.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySharingRevisionDiff {
+    pub entity_sharing_id: String,
+    pub from_revision: Option<i64>,
+    pub to_revision: i64,
+    pub changes: Vec<JsonChange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum EntitySharingRunTrigger {
+    Scheduled,
+    /// Requested through `POST /entity-sharings/{id}/poll`.
+    Manual,
+}
+
+/// Record of one poll cycle of a sharing.
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, ToSchema)]
+pub struct EntitySharingRun {
+    pub id: String,
+    pub entity_sharing_id: String,
+    pub trigger: EntitySharingRunTrigger,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub duration_ms: i64,
+    /// Entities returned by the script, `None` when it didn't run to the end.
+    pub entity_count: Option<i64>,
+    /// Whether the entities matched the sharing schema, `None` when they weren't checked.
+    pub valid: Option<bool>,
+    #[sqlx(json)]
+    pub validation_errors: Vec<String>,
+    pub subscriptions_notified: i64,
+    pub subscriptions_failed: i64,
+    pub error: Option<String>,
+    /// Set when the guardrail held the entities back instead of delivering them.
+    pub held_delivery_id: Option<String>,
+}
+
+impl EntitySharingRun {
+    /// Whether the run ended without error, with entities matching the schema and every
+    /// subscription notified. Held entities aren't a failure, they wait for approval.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.valid != Some(false) && self.subscriptions_failed == 0
+    }
+}
+
+/// Aggregates over the most recent runs of a sharing.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySharingRunStats {
+    pub entity_sharing_id: String,
+    pub run_count: usize,
+    pub success_count: usize,
+    /// Share of the runs that succeeded, `None` without any run. Runs delivering entities that
+    /// don't match the schema or failing to notify a subscription count as failures.
+    pub success_rate: Option<f64>,
+    pub p95_duration_ms: Option<i64>,
+    pub last_success_at: Option<i64>,
+    pub last_failure_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum HeldDeliveryStatus {
+    /// Waiting for approval or for the next poll.
+    Held,
+    Approved,
+    Rejected,
+    /// Delivered because the next poll crossed the threshold again.
+    Confirmed,
+    /// Dropped because the next poll was back within the threshold.
+    Discarded,
+    /// Dropped for a more recent held list.
+    Superseded,
+}
+
+/// Polled entity list the guardrail kept from the subscriptions.
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, ToSchema)]
+pub struct HeldDelivery {
+    pub id: String,
+    pub entity_sharing_id: String,
+    pub run_id: String,
+    #[sqlx(json)]
+    pub data: Value,
+    pub entity_count: i64,
+    pub previous_entity_count: i64,
+    /// Cursor the held poll returned, saved as the sharing cursor when the list is approved.
+    /// Until then polls start over from the cursor of the last delivered list.
+    #[sqlx(json(nullable))]
+    pub cursor: Option<Value>,
+    pub status: HeldDeliveryStatus,
+    pub created_at: i64,
+    pub resolved_at: Option<i64>,
+}
+
+/// Held delivery approved, with how the subscriptions took the released list.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ApprovedHeldDelivery {
+    #[serde(flatten)]
+    pub held_delivery: HeldDelivery,
+    pub subscriptions_notified: i64,
+    pub subscriptions_failed: i64,
+}
+
+/// Claim of a HEUTL instance on polling a sharing, renewed on each of its heartbeats.
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone)]
+pub struct EntitySharingLease {
+    pub entity_sharing_id: String,
+    pub instance_id: String,
+    pub acquired_at: i64,
+    pub expires_at: i64,
+}
+
+/// Input of a dry run. When omitted, the sharing's configured input and stored cursor are used.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct TestEntitySharingParams {
+    pub input: Option<Value>,
+    pub cursor: Option<Value>,
+}
+
+/// An unsaved sharing definition to dry run.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TestEntitySharingDraftParams {
+    pub python_script: String,
+    #[serde(default)]
+    pub script_runtime: ScriptRuntimeKind,
+    #[serde(default)]
+    pub allowed_modules: Vec<String>,
+    pub json_schema: Value,
+    pub is_array: bool,
+    pub input: Option<Value>,
+    pub cursor: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySharingTestResult {
+    pub output: Option<Value>,
+    pub cursor: Option<Value>,
+    pub logs: String,
+    pub duration_ms: u64,
+    pub validation_errors: Vec<String>,
+    pub error: Option<String>,
 }
 
 impl Merge<UpdateEntitySharingParams> for EntitySharing {
@@ -42,11 +430,17 @@ impl Merge<UpdateEntitySharingParams> for EntitySharing {
             merged.name = name;
         }
         if let Some(polling_infos) = other.polling_infos {
-            merged.polling_infos = Some(polling_infos);
+            merged.polling_infos = polling_infos;
         }
         if let Some(python_script) = other.python_script {
             merged.python_script = Some(python_script);
         }
+        if let Some(script_runtime) = other.script_runtime {
+            merged.script_runtime = script_runtime;
+        }
+        if let Some(allowed_modules) = other.allowed_modules {
+            merged.allowed_modules = allowed_modules;
+        }
         if let Some(is_array) = other.is_array {
             merged.is_array = is_array;
         }
@@ -54,6 +448,56 @@ impl Merge<UpdateEntitySharingParams> for EntitySharing {
             merged.json_schema = json_schema;
         }
         merged.updated_at = Utc::now().timestamp();
-        return merged;
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guardrail(
+        min_drop_count: Option<u64>,
+        min_drop_percent: Option<u32>,
+    ) -> EntityCountGuardrail {
+        EntityCountGuardrail {
+            min_drop_count,
+            min_drop_percent,
+            confirm_on_next_poll: true,
+        }
+    }
+
+    #[test]
+    fn drops_of_at_least_the_count_are_crossed() {
+        let guardrail = guardrail(Some(5), None);
+        assert!(guardrail.is_crossed(100, 95));
+        assert!(guardrail.is_crossed(5, 0));
+        assert!(!guardrail.is_crossed(100, 96));
+    }
+
+    #[test]
+    fn drops_of_at_least_the_percentage_are_crossed() {
+        let guardrail = guardrail(None, Some(50));
+        assert!(guardrail.is_crossed(10, 5));
+        assert!(guardrail.is_crossed(3, 1));
+        assert!(!guardrail.is_crossed(3, 2));
+        assert!(!guardrail.is_crossed(1000, 501));
+        assert!(guardrail.is_crossed(1, 0));
+    }
+
+    #[test]
+    fn either_threshold_is_enough() {
+        let guardrail = guardrail(Some(100), Some(50));
+        assert!(guardrail.is_crossed(1000, 900));
+        assert!(guardrail.is_crossed(10, 5));
+        assert!(!guardrail.is_crossed(1000, 901));
+    }
+
+    #[test]
+    fn growth_and_steady_counts_are_never_crossed() {
+        let guardrail = guardrail(Some(1), Some(1));
+        assert!(!guardrail.is_crossed(10, 10));
+        assert!(!guardrail.is_crossed(10, 20));
+        assert!(!guardrail.is_crossed(0, 0));
     }
 }
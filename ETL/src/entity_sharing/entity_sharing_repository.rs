@@ -1,39 +1,172 @@
 use crate::entity_sharing::entity_sharing_model::EntitySharing;
-use crate::entity_sharing::entity_sharing_model::EntitySharingPollingInfos;
+use crate::entity_sharing::entity_sharing_model::EntitySharingDetails;
+use crate::entity_sharing::entity_sharing_model::EntitySharingHealth;
+use crate::entity_sharing::entity_sharing_model::EntitySharingLease;
+use crate::entity_sharing::entity_sharing_model::EntitySharingRevision;
+use crate::entity_sharing::entity_sharing_model::EntitySharingRun;
+use crate::entity_sharing::entity_sharing_model::{HeldDelivery, HeldDeliveryStatus};
+use crate::entity_sharing::entity_sharing_model::{EntitySharingMode, EntitySharingPollingInfos};
 use crate::shared::errors::Error;
+use crate::shared::merge_struct::deserialize_nullable;
+use crate::shared::pagination::{Page, PageRequest};
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
 pub mod entity_sharing_sqlite_repository;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
 pub struct CreateEntitySharingParams {
-    pub id: String,
+    /// Generated as a UUIDv7 when left out.
+    #[serde(default)]
+    pub id: Option<String>,
     pub name: String,
     pub connected_app_id: String,
     pub json_schema: Value,
     pub polling_infos: Option<EntitySharingPollingInfos>,
     pub is_array: bool,
     pub python_script: Option<String>,
+    #[serde(default)]
+    pub script_runtime: ScriptRuntimeKind,
+    #[serde(default)]
+    pub allowed_modules: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
 pub struct UpdateEntitySharingParams {
     pub name: Option<String>,
-    pub polling_infos: Option<EntitySharingPollingInfos>,
+    /// `null` stops polling the sharing, leaving the field out keeps the current schedule.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<EntitySharingPollingInfos>)]
+    pub polling_infos: Option<Option<EntitySharingPollingInfos>>,
     pub python_script: Option<String>,
+    pub script_runtime: Option<ScriptRuntimeKind>,
+    pub allowed_modules: Option<Vec<String>>,
     pub is_array: Option<bool>,
     pub json_schema: Option<Value>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EntitySharingFilter {
+    pub connected_app_id: Option<String>,
+    pub mode: Option<EntitySharingMode>,
+    /// Part of the name, case insensitive.
+    pub name: Option<String>,
+    /// Unix timestamp the sharings were last updated at or after.
+    pub updated_since: Option<i64>,
+}
+
 #[async_trait]
 pub trait EntitySharingRepository: Send + Sync {
+    /// Records the first revision of the sharing along with it.
     async fn create_entity_sharing(
         &self,
+        id: &str,
         params: &CreateEntitySharingParams,
+        author: &Option<String>,
     ) -> Result<EntitySharing, Error>;
-    async fn get_entity_sharing(&self, id: &String) -> Result<EntitySharing, Error>;
+    async fn get_entity_sharing(&self, id: &str) -> Result<EntitySharing, Error>;
+    async fn get_entity_sharing_details(&self, id: &str) -> Result<EntitySharingDetails, Error>;
     async fn get_all_polling_entity_sharings(&self) -> Result<Vec<EntitySharing>, Error>;
-    async fn update_entity_sharing(&self, entity_sharing: &EntitySharing) -> Result<u64, Error>;
-    async fn get_all_entity_sharings(&self) -> Result<Vec<EntitySharing>, Error>;
+    /// Records the next revision of the sharing along with the change.
+    async fn update_entity_sharing(
+        &self,
+        entity_sharing: &EntitySharing,
+        author: &Option<String>,
+    ) -> Result<u64, Error>;
+    async fn get_entity_sharings(
+        &self,
+        filter: &EntitySharingFilter,
+        page: &PageRequest,
+    ) -> Result<Page<EntitySharingDetails>, Error>;
+    async fn update_entity_sharing_polling_cursor(
+        &self,
+        id: &str,
+        polling_cursor: &Option<Value>,
+    ) -> Result<u64, Error>;
+    async fn update_entity_sharing_health(
+        &self,
+        id: &str,
+        health: &EntitySharingHealth,
+    ) -> Result<u64, Error>;
+    async fn get_entity_sharing_revisions(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Vec<EntitySharingRevision>, Error>;
+    async fn get_entity_sharing_revision(
+        &self,
+        entity_sharing_id: &str,
+        revision: i64,
+    ) -> Result<EntitySharingRevision, Error>;
+    async fn create_entity_sharing_run(&self, run: &EntitySharingRun) -> Result<(), Error>;
+    /// Most recent runs first.
+    async fn get_entity_sharing_runs(
+        &self,
+        entity_sharing_id: &str,
+        limit: i64,
+    ) -> Result<Vec<EntitySharingRun>, Error>;
+    async fn get_entity_sharing_run(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<EntitySharingRun, Error>;
+    /// Deletes the runs of the sharing beyond the `keep` most recent ones.
+    async fn prune_entity_sharing_runs(
+        &self,
+        entity_sharing_id: &str,
+        keep: i64,
+    ) -> Result<u64, Error>;
+    /// Entity count of the most recent run whose entities reached the subscriptions.
+    async fn get_last_delivered_entity_count(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<i64>, Error>;
+    async fn create_held_delivery(&self, held_delivery: &HeldDelivery) -> Result<(), Error>;
+    async fn get_held_deliveries(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Vec<HeldDelivery>, Error>;
+    async fn get_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<HeldDelivery, Error>;
+    /// Most recent delivery of the sharing still in the `held` status, if any.
+    async fn get_open_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<HeldDelivery>, Error>;
+    /// Moves a delivery out of the `held` status, returns 0 if it already was.
+    async fn resolve_held_delivery(
+        &self,
+        id: &str,
+        status: HeldDeliveryStatus,
+        resolved_at: i64,
+    ) -> Result<u64, Error>;
+    async fn heartbeat_polling_instance(
+        &self,
+        instance_id: &str,
+        expires_at: i64,
+    ) -> Result<(), Error>;
+    async fn count_live_polling_instances(&self, now: i64) -> Result<i64, Error>;
+    async fn release_polling_instance(&self, instance_id: &str) -> Result<(), Error>;
+    async fn get_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<EntitySharingLease>, Error>;
+    /// Takes or renews the lease, returns false when another instance holds it.
+    async fn acquire_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+        instance_id: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, Error>;
+    async fn release_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+        instance_id: &str,
+    ) -> Result<u64, Error>;
 }
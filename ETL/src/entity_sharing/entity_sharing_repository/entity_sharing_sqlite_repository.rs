@@ -1,12 +1,21 @@
-use crate::entity_sharing::entity_sharing_model::EntitySharing;
+use crate::entity_sharing::entity_sharing_model::{
+    EntitySharing, EntitySharingDetails, EntitySharingHealth, EntitySharingHealthStatus,
+    EntitySharingLease, EntitySharingMode, EntitySharingRevision, EntitySharingRun,
+    EntitySharingSnapshot, HeldDelivery, HeldDeliveryStatus,
+};
 use crate::entity_sharing::entity_sharing_repository::{
-    CreateEntitySharingParams, EntitySharingRepository,
+    CreateEntitySharingParams, EntitySharingFilter, EntitySharingRepository,
 };
 use crate::shared::errors::Error;
+use crate::shared::pagination::{Page, PageRequest};
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use async_trait::async_trait;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::SqlitePool;
+use serde_json::Value;
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::{QueryBuilder, Transaction};
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq)]
 pub struct EntitySharingDTO {
@@ -19,6 +28,14 @@ pub struct EntitySharingDTO {
     pub json_schema: String,
     pub is_array: bool,
     pub python_script: Option<String>,
+    pub script_runtime: ScriptRuntimeKind,
+    pub allowed_modules: String,
+    pub polling_cursor: Option<String>,
+    pub health_status: EntitySharingHealthStatus,
+    pub consecutive_failures: i64,
+    pub last_error: Option<String>,
+    pub last_success_at: Option<i64>,
+    pub last_failure_at: Option<i64>,
 }
 
 fn entity_sharing_dto_to_entity_sharing(
@@ -37,8 +54,88 @@ fn entity_sharing_dto_to_entity_sharing(
         },
         json_schema: serde_json::from_str(&entity_sharing_dto.json_schema)?,
         python_script: entity_sharing_dto.python_script,
+        script_runtime: entity_sharing_dto.script_runtime,
+        allowed_modules: serde_json::from_str(&entity_sharing_dto.allowed_modules)?,
+        polling_cursor: match entity_sharing_dto.polling_cursor {
+            Some(s) => serde_json::from_str(&s)?,
+            None => None,
+        },
+        health: EntitySharingHealth {
+            status: entity_sharing_dto.health_status,
+            consecutive_failures: entity_sharing_dto.consecutive_failures as u32,
+            last_error: entity_sharing_dto.last_error,
+            last_success_at: entity_sharing_dto.last_success_at,
+            last_failure_at: entity_sharing_dto.last_failure_at,
+        },
     };
-    return Ok(entity_sharing);
+    Ok(entity_sharing)
+}
+
+const ENTITY_SHARING_DETAILS_SELECT: &str = "SELECT *,
+        (SELECT COUNT(*) FROM entity_subscriptions WHERE entity_sharing_id = entity_sharings.id) AS subscription_count
+        FROM entity_sharings";
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq)]
+pub struct EntitySharingDetailsDTO {
+    #[sqlx(flatten)]
+    pub entity_sharing: EntitySharingDTO,
+    pub subscription_count: i64,
+}
+
+fn entity_sharing_details_dto_to_entity_sharing_details(
+    entity_sharing_details_dto: EntitySharingDetailsDTO,
+) -> Result<EntitySharingDetails, Error> {
+    let entity_sharing =
+        entity_sharing_dto_to_entity_sharing(entity_sharing_details_dto.entity_sharing)?;
+    Ok(EntitySharingDetails {
+        mode: entity_sharing.mode(),
+        entity_sharing,
+        subscription_count: entity_sharing_details_dto.subscription_count,
+    })
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq)]
+pub struct EntitySharingRevisionDTO {
+    pub id: String,
+    pub entity_sharing_id: String,
+    pub revision: i64,
+    pub snapshot: String,
+    pub author: Option<String>,
+    pub created_at: i64,
+}
+
+fn entity_sharing_revision_dto_to_entity_sharing_revision(
+    entity_sharing_revision_dto: EntitySharingRevisionDTO,
+) -> Result<EntitySharingRevision, Error> {
+    Ok(EntitySharingRevision {
+        id: entity_sharing_revision_dto.id,
+        entity_sharing_id: entity_sharing_revision_dto.entity_sharing_id,
+        revision: entity_sharing_revision_dto.revision,
+        author: entity_sharing_revision_dto.author,
+        created_at: entity_sharing_revision_dto.created_at,
+        snapshot: serde_json::from_str(&entity_sharing_revision_dto.snapshot)?,
+    })
+}
+
+/// Records the next revision of a sharing, in the transaction changing it.
+async fn insert_entity_sharing_revision(
+    transaction: &mut Transaction<'_, Sqlite>,
+    entity_sharing: &EntitySharing,
+    author: &Option<String>,
+) -> Result<EntitySharingRevision, Error> {
+    let result: EntitySharingRevisionDTO = sqlx::query_as(
+        "INSERT INTO entity_sharing_revisions (id, entity_sharing_id, revision, snapshot, author, created_at)
+    VALUES ($1, $2, (SELECT COALESCE(MAX(revision), 0) + 1 FROM entity_sharing_revisions WHERE entity_sharing_id = $2), $3, $4, $5)
+    RETURNING *",
+    )
+    .bind(Uuid::now_v7().to_string())
+    .bind(&entity_sharing.id)
+    .bind(serde_json::to_string(&EntitySharingSnapshot::from(entity_sharing))?)
+    .bind(author)
+    .bind(Utc::now().timestamp())
+    .fetch_one(&mut **transaction)
+    .await?;
+    entity_sharing_revision_dto_to_entity_sharing_revision(result)
 }
 
 pub struct EntitySharingSQLiteRepository<'a> {
@@ -49,10 +146,12 @@ pub struct EntitySharingSQLiteRepository<'a> {
 impl<'a> EntitySharingRepository for EntitySharingSQLiteRepository<'a> {
     async fn create_entity_sharing(
         &self,
+        id: &str,
         params: &CreateEntitySharingParams,
+        author: &Option<String>,
     ) -> Result<EntitySharing, Error> {
         let entity_sharing = EntitySharing {
-            id: params.id.clone(),
+            id: id.to_string(),
             name: params.name.clone(),
             connected_app_id: params.connected_app_id.clone(),
             created_at: Utc::now().timestamp(),
@@ -61,23 +160,34 @@ impl<'a> EntitySharingRepository for EntitySharingSQLiteRepository<'a> {
             json_schema: params.json_schema.clone(),
             is_array: params.is_array,
             python_script: params.python_script.clone(),
+            script_runtime: params.script_runtime,
+            allowed_modules: params.allowed_modules.clone(),
+            polling_cursor: None,
+            health: EntitySharingHealth::default(),
         };
 
-        sqlx::query("INSERT INTO entity_sharings (id, name, created_at, updated_at, polling_infos, json_schema, connected_app_id, is_array, python_script) 
-        VALUES ($1, $2, $3, $4, json($5), json($6), $7, $8, $9)").bind(&entity_sharing.id)
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("INSERT INTO entity_sharings (id, name, created_at, updated_at, polling_infos, json_schema, connected_app_id, is_array, python_script, script_runtime, allowed_modules) 
+        VALUES ($1, $2, $3, $4, json($5), json($6), $7, $8, $9, $10, json($11))").bind(&entity_sharing.id)
         .bind(&entity_sharing.name)
-        .bind(&entity_sharing.created_at)
-        .bind(&entity_sharing.updated_at)
-        .bind(serde_json::to_string(&entity_sharing.polling_infos).unwrap())
+        .bind(entity_sharing.created_at)
+        .bind(entity_sharing.updated_at)
+        // Bound as NULL rather than 'null' so that non polling sharings are filtered out by the
+        // `polling_infos IS NOT NULL` queries.
+        .bind(entity_sharing.polling_infos.as_ref().map(serde_json::to_string).transpose()?)
         .bind(serde_json::to_string(&entity_sharing.json_schema).unwrap())
         .bind(&entity_sharing.connected_app_id)
-        .bind(&entity_sharing.is_array)
+        .bind(entity_sharing.is_array)
         .bind(&entity_sharing.python_script)
-        .execute(self.pool).await?;
+        .bind(entity_sharing.script_runtime)
+        .bind(serde_json::to_string(&entity_sharing.allowed_modules)?)
+        .execute(&mut *transaction).await?;
+        insert_entity_sharing_revision(&mut transaction, &entity_sharing, author).await?;
+        transaction.commit().await?;
         Ok(entity_sharing)
     }
 
-    async fn get_entity_sharing(&self, id: &String) -> Result<EntitySharing, Error> {
+    async fn get_entity_sharing(&self, id: &str) -> Result<EntitySharing, Error> {
         let result: EntitySharingDTO =
             sqlx::query_as("SELECT * FROM entity_sharings WHERE id = $1 LIMIT 1")
                 .bind(id)
@@ -86,6 +196,17 @@ impl<'a> EntitySharingRepository for EntitySharingSQLiteRepository<'a> {
         return entity_sharing_dto_to_entity_sharing(result);
     }
 
+    async fn get_entity_sharing_details(&self, id: &str) -> Result<EntitySharingDetails, Error> {
+        let result: EntitySharingDetailsDTO = sqlx::query_as(&format!(
+            "{} WHERE id = $1 LIMIT 1",
+            ENTITY_SHARING_DETAILS_SELECT
+        ))
+        .bind(id)
+        .fetch_one(self.pool)
+        .await?;
+        return entity_sharing_details_dto_to_entity_sharing_details(result);
+    }
+
     async fn get_all_polling_entity_sharings(&self) -> Result<Vec<EntitySharing>, Error> {
         let result: Vec<EntitySharingDTO> =
             sqlx::query_as("SELECT * FROM entity_sharings WHERE polling_infos IS NOT NULL")
@@ -98,28 +219,577 @@ impl<'a> EntitySharingRepository for EntitySharingSQLiteRepository<'a> {
         return Ok(entity_sharings);
     }
 
-    async fn get_all_entity_sharings(&self) -> Result<Vec<EntitySharing>, Error> {
-        let result: Vec<EntitySharingDTO> = sqlx::query_as("SELECT * FROM entity_sharings")
-            .fetch_all(self.pool)
-            .await?;
-        return result
+
+    async fn get_entity_sharings(
+        &self,
+        filter: &EntitySharingFilter,
+        page: &PageRequest,
+    ) -> Result<Page<EntitySharingDetails>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(ENTITY_SHARING_DETAILS_SELECT);
+        query.push(" WHERE 1 = 1");
+        if let Some(connected_app_id) = &filter.connected_app_id {
+            query
+                .push(" AND connected_app_id = ")
+                .push_bind(connected_app_id);
+        }
+        match filter.mode {
+            Some(EntitySharingMode::Polling) => {
+                query.push(" AND polling_infos IS NOT NULL");
+            }
+            Some(EntitySharingMode::Push) => {
+                query.push(" AND polling_infos IS NULL");
+            }
+            None => {}
+        }
+        if let Some(name) = &filter.name {
+            query
+                .push(" AND instr(lower(name), lower(")
+                .push_bind(name)
+                .push(")) > 0");
+        }
+        if let Some(updated_since) = filter.updated_since {
+            query.push(" AND updated_at >= ").push_bind(updated_since);
+        }
+        page.push_to(&mut query);
+        let result: Vec<EntitySharingDetailsDTO> =
+            query.build_query_as().fetch_all(self.pool).await?;
+        let entity_sharings = result
             .into_iter()
-            .map(entity_sharing_dto_to_entity_sharing)
-            .collect::<Result<Vec<EntitySharing>, Error>>();
+            .map(entity_sharing_details_dto_to_entity_sharing_details)
+            .collect::<Result<Vec<EntitySharingDetails>, Error>>()?;
+        Ok(page.page_of(entity_sharings))
     }
 
-    async fn update_entity_sharing(&self, entity_sharing: &EntitySharing) -> Result<u64, Error> {
+    async fn update_entity_sharing(
+        &self,
+        entity_sharing: &EntitySharing,
+        author: &Option<String>,
+    ) -> Result<u64, Error> {
+        let mut transaction = self.pool.begin().await?;
         let result = sqlx::query("UPDATE entity_sharings SET name = $1, created_at = $2, updated_at = $3, polling_infos = json($4), 
-        json_schema = json($5), connected_app_id = $6, python_script = $7 WHERE id = $8")
+        json_schema = json($5), connected_app_id = $6, python_script = $7, script_runtime = $8, is_array = $9, allowed_modules = json($10) WHERE id = $11")
         .bind(&entity_sharing.name)
-        .bind(&entity_sharing.created_at)
-        .bind(&entity_sharing.updated_at)
-        .bind(serde_json::to_string(&entity_sharing.polling_infos).unwrap())
+        .bind(entity_sharing.created_at)
+        .bind(entity_sharing.updated_at)
+        .bind(entity_sharing.polling_infos.as_ref().map(serde_json::to_string).transpose()?)
         .bind(serde_json::to_string(&entity_sharing.json_schema).unwrap())
         .bind(&entity_sharing.connected_app_id)
         .bind(&entity_sharing.python_script)
+        .bind(entity_sharing.script_runtime)
+        .bind(entity_sharing.is_array)
+        .bind(serde_json::to_string(&entity_sharing.allowed_modules)?)
         .bind(&entity_sharing.id)
-        .execute(self.pool).await?;
+        .execute(&mut *transaction).await?;
+        if result.rows_affected() > 0 {
+            insert_entity_sharing_revision(&mut transaction, entity_sharing, author).await?;
+        }
+        transaction.commit().await?;
         return Ok(result.rows_affected());
     }
+
+    async fn update_entity_sharing_polling_cursor(
+        &self,
+        id: &str,
+        polling_cursor: &Option<Value>,
+    ) -> Result<u64, Error> {
+        let result =
+            sqlx::query("UPDATE entity_sharings SET polling_cursor = json($1) WHERE id = $2")
+                .bind(serde_json::to_string(polling_cursor)?)
+                .bind(id)
+                .execute(self.pool)
+                .await?;
+        return Ok(result.rows_affected());
+    }
+
+    async fn update_entity_sharing_health(
+        &self,
+        id: &str,
+        health: &EntitySharingHealth,
+    ) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE entity_sharings SET health_status = $1, consecutive_failures = $2, last_error = $3,
+        last_success_at = $4, last_failure_at = $5 WHERE id = $6",
+        )
+        .bind(health.status)
+        .bind(health.consecutive_failures as i64)
+        .bind(&health.last_error)
+        .bind(health.last_success_at)
+        .bind(health.last_failure_at)
+        .bind(id)
+        .execute(self.pool)
+        .await?;
+        return Ok(result.rows_affected());
+    }
+
+    async fn get_entity_sharing_revisions(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Vec<EntitySharingRevision>, Error> {
+        let result: Vec<EntitySharingRevisionDTO> = sqlx::query_as(
+            "SELECT * FROM entity_sharing_revisions WHERE entity_sharing_id = $1 ORDER BY revision DESC",
+        )
+        .bind(entity_sharing_id)
+        .fetch_all(self.pool)
+        .await?;
+        return result
+            .into_iter()
+            .map(entity_sharing_revision_dto_to_entity_sharing_revision)
+            .collect::<Result<Vec<EntitySharingRevision>, Error>>();
+    }
+
+    async fn get_entity_sharing_revision(
+        &self,
+        entity_sharing_id: &str,
+        revision: i64,
+    ) -> Result<EntitySharingRevision, Error> {
+        let result: EntitySharingRevisionDTO = sqlx::query_as(
+            "SELECT * FROM entity_sharing_revisions WHERE entity_sharing_id = $1 AND revision = $2 LIMIT 1",
+        )
+        .bind(entity_sharing_id)
+        .bind(revision)
+        .fetch_one(self.pool)
+        .await?;
+        return entity_sharing_revision_dto_to_entity_sharing_revision(result);
+    }
+
+    async fn create_entity_sharing_run(&self, run: &EntitySharingRun) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO entity_sharing_runs (id, entity_sharing_id, trigger, started_at, ended_at, duration_ms, entity_count,
+        valid, validation_errors, subscriptions_notified, subscriptions_failed, error, held_delivery_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, json($9), $10, $11, $12, $13)",
+        )
+        .bind(&run.id)
+        .bind(&run.entity_sharing_id)
+        .bind(run.trigger)
+        .bind(run.started_at)
+        .bind(run.ended_at)
+        .bind(run.duration_ms)
+        .bind(run.entity_count)
+        .bind(run.valid)
+        .bind(serde_json::to_string(&run.validation_errors)?)
+        .bind(run.subscriptions_notified)
+        .bind(run.subscriptions_failed)
+        .bind(&run.error)
+        .bind(&run.held_delivery_id)
+        .execute(self.pool)
+        .await?;
+        return Ok(());
+    }
+
+    async fn get_entity_sharing_runs(
+        &self,
+        entity_sharing_id: &str,
+        limit: i64,
+    ) -> Result<Vec<EntitySharingRun>, Error> {
+        let result: Vec<EntitySharingRun> = sqlx::query_as(
+            "SELECT * FROM entity_sharing_runs WHERE entity_sharing_id = $1
+        ORDER BY started_at DESC, id DESC LIMIT $2",
+        )
+        .bind(entity_sharing_id)
+        .bind(limit)
+        .fetch_all(self.pool)
+        .await?;
+        return Ok(result);
+    }
+
+    async fn get_entity_sharing_run(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<EntitySharingRun, Error> {
+        let result: EntitySharingRun = sqlx::query_as(
+            "SELECT * FROM entity_sharing_runs WHERE entity_sharing_id = $1 AND id = $2 LIMIT 1",
+        )
+        .bind(entity_sharing_id)
+        .bind(id)
+        .fetch_one(self.pool)
+        .await?;
+        return Ok(result);
+    }
+
+    async fn prune_entity_sharing_runs(
+        &self,
+        entity_sharing_id: &str,
+        keep: i64,
+    ) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "DELETE FROM entity_sharing_runs WHERE entity_sharing_id = $1 AND id NOT IN
+        (SELECT id FROM entity_sharing_runs WHERE entity_sharing_id = $1 ORDER BY started_at DESC, id DESC LIMIT $2)",
+        )
+        .bind(entity_sharing_id)
+        .bind(keep)
+        .execute(self.pool)
+        .await?;
+        return Ok(result.rows_affected());
+    }
+
+    async fn get_last_delivered_entity_count(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<i64>, Error> {
+        let result: Option<i64> = sqlx::query_scalar(
+            "SELECT r.entity_count FROM entity_sharing_runs r LEFT JOIN held_deliveries h ON h.id = r.held_delivery_id
+        WHERE r.entity_sharing_id = $1 AND r.error IS NULL AND r.entity_count IS NOT NULL
+        AND (r.held_delivery_id IS NULL OR h.status IN ('approved', 'confirmed'))
+        ORDER BY r.started_at DESC, r.id DESC LIMIT 1",
+        )
+        .bind(entity_sharing_id)
+        .fetch_optional(self.pool)
+        .await?;
+        return Ok(result);
+    }
+
+    async fn create_held_delivery(&self, held_delivery: &HeldDelivery) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO held_deliveries (id, entity_sharing_id, run_id, data, entity_count, previous_entity_count, cursor, status, created_at, resolved_at)
+        VALUES ($1, $2, $3, json($4), $5, $6, json($7), $8, $9, $10)",
+        )
+        .bind(&held_delivery.id)
+        .bind(&held_delivery.entity_sharing_id)
+        .bind(&held_delivery.run_id)
+        .bind(serde_json::to_string(&held_delivery.data)?)
+        .bind(held_delivery.entity_count)
+        .bind(held_delivery.previous_entity_count)
+        .bind(held_delivery.cursor.as_ref().map(serde_json::to_string).transpose()?)
+        .bind(held_delivery.status)
+        .bind(held_delivery.created_at)
+        .bind(held_delivery.resolved_at)
+        .execute(self.pool)
+        .await?;
+        return Ok(());
+    }
+
+    async fn get_held_deliveries(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Vec<HeldDelivery>, Error> {
+        let result: Vec<HeldDelivery> = sqlx::query_as(
+            "SELECT * FROM held_deliveries WHERE entity_sharing_id = $1 ORDER BY created_at DESC, id DESC",
+        )
+        .bind(entity_sharing_id)
+        .fetch_all(self.pool)
+        .await?;
+        return Ok(result);
+    }
+
+    async fn get_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<HeldDelivery, Error> {
+        let result: HeldDelivery = sqlx::query_as(
+            "SELECT * FROM held_deliveries WHERE entity_sharing_id = $1 AND id = $2 LIMIT 1",
+        )
+        .bind(entity_sharing_id)
+        .bind(id)
+        .fetch_one(self.pool)
+        .await?;
+        return Ok(result);
+    }
+
+    async fn get_open_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<HeldDelivery>, Error> {
+        let result: Option<HeldDelivery> = sqlx::query_as(
+            "SELECT * FROM held_deliveries WHERE entity_sharing_id = $1 AND status = $2
+        ORDER BY created_at DESC, id DESC LIMIT 1",
+        )
+        .bind(entity_sharing_id)
+        .bind(HeldDeliveryStatus::Held)
+        .fetch_optional(self.pool)
+        .await?;
+        return Ok(result);
+    }
+
+    async fn resolve_held_delivery(
+        &self,
+        id: &str,
+        status: HeldDeliveryStatus,
+        resolved_at: i64,
+    ) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE held_deliveries SET status = $1, resolved_at = $2 WHERE id = $3 AND status = $4",
+        )
+        .bind(status)
+        .bind(resolved_at)
+        .bind(id)
+        .bind(HeldDeliveryStatus::Held)
+        .execute(self.pool)
+        .await?;
+        return Ok(result.rows_affected());
+    }
+
+    async fn heartbeat_polling_instance(
+        &self,
+        instance_id: &str,
+        expires_at: i64,
+    ) -> Result<(), Error> {
+        let now = Utc::now().timestamp();
+        sqlx::query("DELETE FROM polling_instances WHERE expires_at <= $1")
+            .bind(now)
+            .execute(self.pool)
+            .await?;
+        sqlx::query(
+            "INSERT INTO polling_instances (id, started_at, expires_at) VALUES ($1, $2, $3)
+        ON CONFLICT(id) DO UPDATE SET expires_at = excluded.expires_at",
+        )
+        .bind(instance_id)
+        .bind(now)
+        .bind(expires_at)
+        .execute(self.pool)
+        .await?;
+        return Ok(());
+    }
+
+    async fn count_live_polling_instances(&self, now: i64) -> Result<i64, Error> {
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM polling_instances WHERE expires_at > $1")
+                .bind(now)
+                .fetch_one(self.pool)
+                .await?;
+        return Ok(count);
+    }
+
+    async fn release_polling_instance(&self, instance_id: &str) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM entity_sharing_leases WHERE instance_id = $1")
+            .bind(instance_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("DELETE FROM polling_instances WHERE id = $1")
+            .bind(instance_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        return Ok(());
+    }
+
+    async fn get_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+    ) -> Result<Option<EntitySharingLease>, Error> {
+        let result: Option<EntitySharingLease> =
+            sqlx::query_as("SELECT * FROM entity_sharing_leases WHERE entity_sharing_id = $1")
+                .bind(entity_sharing_id)
+                .fetch_optional(self.pool)
+                .await?;
+        return Ok(result);
+    }
+
+    async fn acquire_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+        instance_id: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, Error> {
+        // The conflict update only applies to our own lease or to an expired one, so two
+        // instances can't both come out holding it.
+        let result = sqlx::query(
+            "INSERT INTO entity_sharing_leases (entity_sharing_id, instance_id, acquired_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT(entity_sharing_id) DO UPDATE SET instance_id = excluded.instance_id,
+        acquired_at = CASE WHEN entity_sharing_leases.instance_id = excluded.instance_id
+        THEN entity_sharing_leases.acquired_at ELSE excluded.acquired_at END,
+        expires_at = excluded.expires_at
+        WHERE entity_sharing_leases.instance_id = excluded.instance_id
+        OR entity_sharing_leases.expires_at <= excluded.acquired_at",
+        )
+        .bind(entity_sharing_id)
+        .bind(instance_id)
+        .bind(now)
+        .bind(expires_at)
+        .execute(self.pool)
+        .await?;
+        return Ok(result.rows_affected() == 1);
+    }
+
+    async fn release_entity_sharing_lease(
+        &self,
+        entity_sharing_id: &str,
+        instance_id: &str,
+    ) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "DELETE FROM entity_sharing_leases WHERE entity_sharing_id = $1 AND instance_id = $2",
+        )
+        .bind(entity_sharing_id)
+        .bind(instance_id)
+        .execute(self.pool)
+        .await?;
+        return Ok(result.rows_affected());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_sharing::entity_polling_schedule::{
+        IntervalMode, PollingRetryPolicy, PollingSchedule,
+    };
+    use crate::entity_sharing::entity_sharing_model::EntitySharingPollingInfos;
+    use crate::entity_subscription::entity_subscription_repository::entity_subscription_sqlite_repository::EntitySubscriptionSQLiteRepository;
+    use crate::entity_subscription::entity_subscription_repository::{
+        CreateEntitySubscriptionParams, EntitySubscriptionRepository,
+    };
+    use crate::shared::db::get_db;
+    use serde_json::json;
+
+    async fn open_pool() -> &'static SqlitePool {
+        let path = std::env::temp_dir().join(format!("heutl-{}.db", Uuid::now_v7()));
+        Box::leak(Box::new(
+            get_db(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        ))
+    }
+
+    fn create_params() -> CreateEntitySharingParams {
+        CreateEntitySharingParams {
+            id: None,
+            name: "Assets".to_string(),
+            connected_app_id: "connected-app".to_string(),
+            json_schema: json!({"type": "array"}),
+            python_script: Some("result = []".to_string()),
+            is_array: true,
+            script_runtime: ScriptRuntimeKind::Python,
+            allowed_modules: vec!["json".to_string()],
+            polling_infos: Some(EntitySharingPollingInfos {
+                schedule: PollingSchedule::Interval {
+                    polling_interval: 1000,
+                    interval_mode: IntervalMode::FixedDelay,
+                },
+                input: None,
+                retry_policy: PollingRetryPolicy::default(),
+                guardrail: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn revisions_are_recorded_with_the_changes_and_without_runtime_state() {
+        let repository = EntitySharingSQLiteRepository {
+            pool: open_pool().await,
+        };
+        let author = Some("admin".to_string());
+        let created = repository
+            .create_entity_sharing("sharing", &create_params(), &author)
+            .await
+            .unwrap();
+        repository
+            .update_entity_sharing_polling_cursor("sharing", &Some(json!({"page": 2})))
+            .await
+            .unwrap();
+        let updated = EntitySharing {
+            name: "Renamed assets".to_string(),
+            ..repository.get_entity_sharing("sharing").await.unwrap()
+        };
+        repository
+            .update_entity_sharing(&updated, &author)
+            .await
+            .unwrap();
+
+        let revisions = repository
+            .get_entity_sharing_revisions("sharing")
+            .await
+            .unwrap();
+        assert_eq!(
+            revisions.iter().map(|r| r.revision).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert_eq!(revisions[0].snapshot, EntitySharingSnapshot::from(&updated));
+        assert_eq!(revisions[1].snapshot, EntitySharingSnapshot::from(&created));
+        let snapshot: String =
+            sqlx::query_scalar("SELECT snapshot FROM entity_sharing_revisions WHERE revision = 2")
+                .fetch_one(repository.pool)
+                .await
+                .unwrap();
+        assert!(!snapshot.contains("polling_cursor"));
+        assert!(!snapshot.contains("health"));
+    }
+
+    #[tokio::test]
+    async fn updates_of_missing_sharings_record_no_revision() {
+        let repository = EntitySharingSQLiteRepository {
+            pool: open_pool().await,
+        };
+        let created = repository
+            .create_entity_sharing("sharing", &create_params(), &None)
+            .await
+            .unwrap();
+        let missing = EntitySharing {
+            id: "missing".to_string(),
+            ..created
+        };
+        assert_eq!(
+            repository
+                .update_entity_sharing(&missing, &None)
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(
+            repository
+                .get_entity_sharing_revisions("missing")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn backfilled_revisions_match_the_existing_definitions() {
+        let pool = open_pool().await;
+        let entity_sharing_repository = EntitySharingSQLiteRepository { pool };
+        let entity_subscription_repository = EntitySubscriptionSQLiteRepository { pool };
+        let entity_sharing = entity_sharing_repository
+            .create_entity_sharing("sharing", &create_params(), &None)
+            .await
+            .unwrap();
+        let entity_subscription = entity_subscription_repository
+            .create_entity_subscription(
+                "subscription",
+                &CreateEntitySubscriptionParams {
+                    id: None,
+                    entity_sharing_id: "sharing".to_string(),
+                    connected_app_id: "other-connected-app".to_string(),
+                    jdm_transform: Some(json!({"nodes": []})),
+                    python_script: None,
+                    script_runtime: ScriptRuntimeKind::Python,
+                    allowed_modules: vec![],
+                },
+                &None,
+            )
+            .await
+            .unwrap();
+        // Rows created before revisions were recorded.
+        sqlx::raw_sql(
+            "DELETE FROM entity_sharing_revisions; DELETE FROM entity_subscription_revisions;",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        sqlx::raw_sql(include_str!(
+            "../../shared/migrations/20251019220000_backfill_revisions.sql"
+        ))
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let entity_sharing_revision = entity_sharing_repository
+            .get_entity_sharing_revision("sharing", 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            entity_sharing_revision.snapshot,
+            EntitySharingSnapshot::from(&entity_sharing)
+        );
+        assert_eq!(entity_sharing_revision.author, None);
+        let entity_subscription_revision = entity_subscription_repository
+            .get_entity_subscription_revision("subscription", 1)
+            .await
+            .unwrap();
+        assert_eq!(entity_subscription_revision.snapshot, entity_subscription);
+    }
 }
@@ -1,63 +1,727 @@
+use crate::entity_sharing::entity_polling_scheduler::{PollerState, TriggeredPoll};
+use crate::entity_sharing::entity_sharing_model::{
+    ApprovedHeldDelivery, EntitySharing, EntitySharingDetails, EntitySharingRevision,
+    EntitySharingRevisionDiff, EntitySharingRun, EntitySharingRunStats, EntitySharingTestResult,
+    HeldDelivery, HeldDeliveryStatus, TestEntitySharingDraftParams, TestEntitySharingParams,
+};
 use crate::entity_sharing::entity_sharing_repository::{
-    CreateEntitySharingParams, UpdateEntitySharingParams,
+    CreateEntitySharingParams, EntitySharingFilter, UpdateEntitySharingParams,
+};
+use crate::services::auth::Caller;
+use crate::services::policy::{
+    require_connected_app, require_connected_app_owner, require_entity_sharing_owner,
+    require_not_auditor, require_script_sandbox_unchanged,
 };
-use crate::services::web_api::WebAppCores;
+use crate::services::web_api::{Author, RevisionDiffQuery, RunsQuery, WebAppCores};
+use crate::shared::errors::{Error, ErrorBody};
+use crate::shared::pagination::{Page, PageQuery};
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use axum::{
     Json, debug_handler,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
 };
 use reqwest::StatusCode;
 use serde_json::Value;
 
+#[utoipa::path(
+    post,
+    path = "/entity-sharings",
+    tag = "entity-sharings",
+    params(
+        ("X-Author" = Option<String>, Header, description = "Author recorded on the revision"),
+    ),
+    request_body = CreateEntitySharingParams,
+    responses(
+        (status = 201, description = "Created entity sharing", body = EntitySharing),
+        (status = 409, description = "Id already taken", body = ErrorBody),
+        (status = 422, description = "Invalid fields, listed in `fields`", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
 #[debug_handler]
 pub async fn create_entity_sharing(
     State(web_app_cores): State<WebAppCores>,
+    Author(author): Author,
+    caller: Caller,
     Json(data): Json<CreateEntitySharingParams>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Error> {
+    require_connected_app_owner(
+        &caller,
+        &data.connected_app_id,
+        "create entity sharings for this connected app",
+    )?;
+    require_script_sandbox_unchanged(
+        &caller,
+        (ScriptRuntimeKind::default(), &[]),
+        (Some(data.script_runtime), Some(&data.allowed_modules)),
+    )?;
     let entity_sharing = web_app_cores
         .entity_sharing_core
-        .create_entity_sharing(&data)
-        .await
-        .unwrap();
-    return (StatusCode::CREATED, Json(entity_sharing));
+        .create_entity_sharing(&data, &author)
+        .await?;
+    Ok((StatusCode::CREATED, Json(entity_sharing)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/entity/{entity_sharing_id}",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    request_body = Value,
+    responses(
+        (status = 200, description = "Entity list pushed to the subscriptions", body = String),
+        (status = 403, description = "Sharing owned by another app", body = ErrorBody),
+        (status = 404, description = "Unknown entity sharing", body = ErrorBody),
+    )
+)]
 #[debug_handler]
 pub async fn notify_new_entity_list(
     State(web_app_cores): State<WebAppCores>,
     Path(entity_sharing_id): Path<String>,
+    caller: Caller,
     Json(data): Json<Value>,
-) -> impl IntoResponse {
-    let result = web_app_cores
+) -> Result<impl IntoResponse, Error> {
+    let entity_sharing = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing(&entity_sharing_id)
+        .await?;
+    require_connected_app(
+        &caller,
+        &entity_sharing.connected_app_id,
+        "push entities to this entity sharing",
+    )?;
+    web_app_cores
         .entity_subscription_core
         .notify_all_subscriptions_of_new_entity_list(&entity_sharing_id, &data)
-        .await
-        .unwrap();
+        .await?;
 
-    return (StatusCode::OK, Json("ok"));
+    Ok((StatusCode::OK, Json("ok")))
 }
 
+#[utoipa::path(
+    get,
+    path = "/entity-sharings",
+    tag = "entity-sharings",
+    params(EntitySharingFilter, PageQuery),
+    responses(
+        (
+            status = 200,
+            description = "Page of entity sharings, polled and pushed",
+            body = Page<EntitySharingDetails>,
+        ),
+        (status = 400, description = "Invalid page", body = ErrorBody),
+    )
+)]
 #[debug_handler]
-pub async fn get_entity_sharings(State(web_app_cores): State<WebAppCores>) -> impl IntoResponse {
+pub async fn get_entity_sharings(
+    State(web_app_cores): State<WebAppCores>,
+    Query(filter): Query<EntitySharingFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<impl IntoResponse, Error> {
     let entity_sharings = web_app_cores
         .entity_sharing_core
-        .get_all_polling_entity_sharings()
-        .await
-        .unwrap();
-    return (StatusCode::OK, Json(entity_sharings));
+        .get_entity_sharings(&filter, &page.page_request()?)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_sharings)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (status = 200, description = "Entity sharing", body = EntitySharingDetails),
+        (status = 404, description = "Unknown entity sharing", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let entity_sharing = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_details(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_sharing)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/entity-sharings/{entity_sharing_id}",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        ("X-Author" = Option<String>, Header, description = "Author recorded on the revision"),
+    ),
+    request_body = UpdateEntitySharingParams,
+    responses(
+        (status = 200, description = "Updated entity sharing", body = EntitySharing),
+        (status = 400, description = "Invalid polling infos", body = ErrorBody),
+        (status = 404, description = "Unknown entity sharing", body = ErrorBody),
+        (status = 422, description = "Invalid JSON schema or script", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
 #[debug_handler]
 pub async fn update_entity_sharing(
     State(web_app_cores): State<WebAppCores>,
     Path(entity_sharing_id): Path<String>,
+    Author(author): Author,
+    caller: Caller,
     Json(data): Json<UpdateEntitySharingParams>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Error> {
+    let entity_sharing = require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "update this entity sharing",
+    )
+    .await?;
+    require_script_sandbox_unchanged(
+        &caller,
+        (entity_sharing.script_runtime, &entity_sharing.allowed_modules),
+        (data.script_runtime, data.allowed_modules.as_deref()),
+    )?;
+    let entity_sharing = web_app_cores
+        .entity_sharing_core
+        .update_entity_sharing(&entity_sharing_id, &data, &author)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_sharing)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/test",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    request_body = TestEntitySharingParams,
+    responses(
+        (status = 200, description = "Outcome of the dry run", body = EntitySharingTestResult),
+        (status = 404, description = "Unknown entity sharing", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn test_entity_sharing(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+    caller: Caller,
+    Json(data): Json<TestEntitySharingParams>,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "test this entity sharing",
+    )
+    .await?;
+    let test_result = web_app_cores
+        .entity_sharing_core
+        .test_entity_sharing(&entity_sharing_id, &data)
+        .await?;
+    Ok((StatusCode::OK, Json(test_result)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/test",
+    tag = "entity-sharings",
+    request_body = TestEntitySharingDraftParams,
+    responses(
+        (status = 200, description = "Outcome of the dry run", body = EntitySharingTestResult),
+        (status = 422, description = "Invalid JSON schema or script", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn test_entity_sharing_draft(
+    State(web_app_cores): State<WebAppCores>,
+    caller: Caller,
+    Json(data): Json<TestEntitySharingDraftParams>,
+) -> Result<impl IntoResponse, Error> {
+    require_not_auditor(&caller, "test entity sharings")?;
+    require_script_sandbox_unchanged(
+        &caller,
+        (ScriptRuntimeKind::default(), &[]),
+        (Some(data.script_runtime), Some(&data.allowed_modules)),
+    )?;
+    let test_result = web_app_cores
+        .entity_sharing_core
+        .test_entity_sharing_draft(&data)
+        .await?;
+    Ok((StatusCode::OK, Json(test_result)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/revisions",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Revisions of the entity sharing",
+            body = Vec<EntitySharingRevision>,
+        ),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_revisions(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let revisions = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_revisions(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(revisions)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/revisions/{revision}/diff",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        ("revision" = i64, Path, description = "Revision number"),
+        RevisionDiffQuery,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Changes made by the revision",
+            body = EntitySharingRevisionDiff,
+        ),
+        (status = 404, description = "Unknown revision", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_revision_diff(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_sharing_id, revision)): Path<(String, i64)>,
+    Query(query): Query<RevisionDiffQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let diff = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_revision_diff(&entity_sharing_id, revision, query.against)
+        .await?;
+    Ok((StatusCode::OK, Json(diff)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/revisions/{revision}/rollback",
+    tag = "entity-sharings",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        ("revision" = i64, Path, description = "Revision number"),
+        ("X-Author" = Option<String>, Header, description = "Author recorded on the revision"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Entity sharing restored as a new revision",
+            body = EntitySharing,
+        ),
+        (status = 404, description = "Unknown revision", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn rollback_entity_sharing(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_sharing_id, revision)): Path<(String, i64)>,
+    Author(author): Author,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    let entity_sharing = require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "roll back this entity sharing",
+    )
+    .await?;
+    let snapshot = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_revision(&entity_sharing_id, revision)
+        .await?
+        .snapshot;
+    require_script_sandbox_unchanged(
+        &caller,
+        (entity_sharing.script_runtime, &entity_sharing.allowed_modules),
+        (Some(snapshot.script_runtime), Some(&snapshot.allowed_modules)),
+    )?;
     let entity_sharing = web_app_cores
         .entity_sharing_core
-        .update_entity_sharing(&entity_sharing_id, &data)
-        .await
-        .unwrap();
-    return (StatusCode::OK, Json(entity_sharing));
+        .rollback_entity_sharing(&entity_sharing_id, revision, &author)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_sharing)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharing-pollers",
+    tag = "pollers",
+    responses(
+        (status = 200, description = "Pollers running on this instance", body = Vec<PollerState>),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_pollers(
+    State(web_app_cores): State<WebAppCores>,
+) -> Result<impl IntoResponse, Error> {
+    let pollers = web_app_cores.entity_polling_scheduler.get_pollers().await?;
+    Ok((StatusCode::OK, Json(pollers)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/poller",
+    tag = "pollers",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (status = 200, description = "Poller of the entity sharing", body = PollerState),
+        (status = 404, description = "No poller for the entity sharing", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_poller(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let poller = web_app_cores
+        .entity_polling_scheduler
+        .get_poller(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(poller)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/poller/pause",
+    tag = "pollers",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (status = 200, description = "Paused poller", body = PollerState),
+        (status = 404, description = "No poller for the entity sharing", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn pause_entity_sharing_poller(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "pause this entity sharing",
+    )
+    .await?;
+    let poller = web_app_cores
+        .entity_polling_scheduler
+        .pause(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(poller)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/poller/resume",
+    tag = "pollers",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (status = 200, description = "Resumed poller", body = PollerState),
+        (status = 404, description = "No poller for the entity sharing", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn resume_entity_sharing_poller(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "resume this entity sharing",
+    )
+    .await?;
+    let poller = web_app_cores
+        .entity_polling_scheduler
+        .resume(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(poller)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/poller/cancel",
+    tag = "pollers",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (status = 200, description = "Poller with its running poll cancelled", body = PollerState),
+        (status = 404, description = "No poller for the entity sharing", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn cancel_entity_sharing_poller(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "cancel polls of this entity sharing",
+    )
+    .await?;
+    let poller = web_app_cores
+        .entity_polling_scheduler
+        .cancel(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(poller)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/poll",
+    tag = "pollers",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (status = 202, description = "Poll queued, recorded as the given run", body = TriggeredPoll),
+        (status = 400, description = "The entity sharing has no script to poll", body = ErrorBody),
+        (status = 404, description = "Unknown entity sharing", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+        (status = 409, description = "Another instance polls the entity sharing", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn trigger_entity_sharing_poll(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "poll this entity sharing",
+    )
+    .await?;
+    let triggered_poll = web_app_cores
+        .entity_polling_scheduler
+        .trigger_poll(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::ACCEPTED, Json(triggered_poll)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/runs",
+    tag = "runs",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        RunsQuery,
+    ),
+    responses(
+        (status = 200, description = "Most recent poll cycles first", body = Vec<EntitySharingRun>),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_runs(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+    Query(query): Query<RunsQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let runs = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_runs(&entity_sharing_id, query.limit.unwrap_or(100))
+        .await?;
+    Ok((StatusCode::OK, Json(runs)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/runs/{run_id}",
+    tag = "runs",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        ("run_id" = String, Path, description = "Id of the run, the one returned for a manual poll"),
+    ),
+    responses(
+        (status = 200, description = "Outcome of the poll cycle", body = EntitySharingRun),
+        (status = 404, description = "Unknown run, or one that isn't over yet", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_run(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_sharing_id, run_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, Error> {
+    let run = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_run(&entity_sharing_id, &run_id)
+        .await?;
+    Ok((StatusCode::OK, Json(run)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/runs/stats",
+    tag = "runs",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Aggregates over the recorded poll cycles",
+            body = EntitySharingRunStats,
+        ),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_sharing_run_stats(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let stats = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing_run_stats(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(stats)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/held-deliveries",
+    tag = "held-deliveries",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Entity lists held by the guardrail",
+            body = Vec<HeldDelivery>,
+        ),
+    )
+)]
+#[debug_handler]
+pub async fn get_held_deliveries(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_sharing_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let held_deliveries = web_app_cores
+        .entity_sharing_core
+        .get_held_deliveries(&entity_sharing_id)
+        .await?;
+    Ok((StatusCode::OK, Json(held_deliveries)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/held-deliveries/{held_delivery_id}/approve",
+    tag = "held-deliveries",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        ("held_delivery_id" = String, Path, description = "Id of the held delivery"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Held list delivered to the subscriptions, some of them possibly failing",
+            body = ApprovedHeldDelivery,
+        ),
+        (status = 404, description = "Unknown held delivery", body = ErrorBody),
+        (status = 409, description = "Delivery no longer held", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+        (status = 500, description = "No subscription took the list, it stays held", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn approve_held_delivery(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_sharing_id, held_delivery_id)): Path<(String, String)>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "approve deliveries of this entity sharing",
+    )
+    .await?;
+    let approved_held_delivery = web_app_cores
+        .entity_subscription_core
+        .approve_held_delivery(&entity_sharing_id, &held_delivery_id)
+        .await?;
+    Ok((StatusCode::OK, Json(approved_held_delivery)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-sharings/{entity_sharing_id}/held-deliveries/{held_delivery_id}/reject",
+    tag = "held-deliveries",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        ("held_delivery_id" = String, Path, description = "Id of the held delivery"),
+    ),
+    responses(
+        (status = 200, description = "Held list dropped", body = HeldDelivery),
+        (status = 404, description = "Unknown held delivery", body = ErrorBody),
+        (status = 409, description = "Delivery no longer held", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn reject_held_delivery(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_sharing_id, held_delivery_id)): Path<(String, String)>,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    require_entity_sharing_owner(
+        &caller,
+        &web_app_cores,
+        &entity_sharing_id,
+        "reject deliveries of this entity sharing",
+    )
+    .await?;
+    let held_delivery = web_app_cores
+        .entity_sharing_core
+        .resolve_held_delivery(
+            &entity_sharing_id,
+            &held_delivery_id,
+            HeldDeliveryStatus::Rejected,
+        )
+        .await?;
+    Ok((StatusCode::OK, Json(held_delivery)))
 }
@@ -1,42 +1,219 @@
 use crate::entity_sharing::entity_sharing_core::EntitySharingCore;
-use crate::entity_subscription::entity_subscription_model::EntitySubscription;
+use crate::entity_sharing::entity_sharing_model::{ApprovedHeldDelivery, HeldDeliveryStatus};
+use crate::entity_subscription::entity_subscription_model::{
+    DeliveryResult, EntitySubscription, EntitySubscriptionRevision, EntitySubscriptionRevisionDiff,
+    PendingDelivery,
+};
 use crate::entity_subscription::entity_subscription_repository::{
-    CreateEntitySubscriptionParams, EntitySubscriptionRepository,
+    CreateEntitySubscriptionParams, EntitySubscriptionFilter, EntitySubscriptionRepository,
+    UpdateEntitySubscriptionParams,
 };
-use crate::shared::errors::Error;
-use crate::shared::python_runner::run_python_script_output_json;
+use crate::shared::bus::{Commands, Publish, TopicIds};
+use crate::shared::errors::{Error, FieldErrors};
+use crate::shared::json_diff::diff_json;
+use crate::shared::merge_struct::Merge;
+use crate::shared::metrics::{record_delivery, record_delivery_retry, set_in_flight_deliveries};
+use crate::shared::pagination::{Page, PageRequest, SortField};
+use crate::shared::script_runtime::{spawn_script_output_json, validate_script};
+use chrono::Utc;
 use futures::future;
-use serde_json::Value;
-use std::sync::Arc;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{Instrument, error, info, info_span, instrument};
+use uuid::Uuid;
 
 pub struct EntitySubscriptionCore<'a> {
     pub entity_subscription_repository: Box<dyn EntitySubscriptionRepository + 'a>,
     pub entity_sharing_core: Arc<EntitySharingCore<'a>>,
+    /// Deliveries currently running, saved as pending when shutdown cuts them off.
+    pub in_flight_deliveries: Mutex<HashMap<String, PendingDelivery>>,
+    /// Ids of the in-flight deliveries saved as pending, whose row goes once they end after all.
+    pub saved_deliveries: Mutex<HashSet<String>>,
+    pub publish: Publish,
 }
 
 impl<'a> EntitySubscriptionCore<'a> {
     pub async fn create_entity_subscription(
         &self,
         params: &CreateEntitySubscriptionParams,
+        author: &Option<String>,
     ) -> Result<EntitySubscription, Error> {
+        let mut field_errors = FieldErrors::default();
+        field_errors.check_id(&params.id);
+        field_errors.check_reference(
+            "entity_sharing_id",
+            self.entity_sharing_core
+                .get_entity_sharing(&params.entity_sharing_id)
+                .await,
+            format!("Unknown entity sharing {}", params.entity_sharing_id),
+        )?;
+        field_errors.check_reference(
+            "connected_app_id",
+            self.entity_sharing_core
+                .connected_app_core
+                .get_connected_app(&params.connected_app_id)
+                .await,
+            format!("Unknown connected app {}", params.connected_app_id),
+        )?;
+        if let Some(python_script) = &params.python_script {
+            field_errors.check(
+                "python_script",
+                validate_script(
+                    params.script_runtime,
+                    python_script,
+                    &params.allowed_modules,
+                )
+                .await,
+            )?;
+        }
+        field_errors.result()?;
+
+        let id = params
+            .id
+            .clone()
+            .unwrap_or_else(|| Uuid::now_v7().to_string());
         let result = self
-            .entity_sharing_core
-            .get_entity_sharing(&params.entity_sharing_id)
+            .entity_subscription_repository
+            .create_entity_subscription(&id, params, author)
+            .await?;
+        (self.publish)(
+            Commands::EntitySubscriptionCreated {
+                entity_subscription: result.clone(),
+            },
+            Some(TopicIds::EntitySubscriptionCreated),
+        );
+        Ok(result)
+    }
+
+    pub async fn get_entity_subscription(&self, id: &str) -> Result<EntitySubscription, Error> {
+        return self
+            .entity_subscription_repository
+            .get_entity_subscription_by_id(id)
+            .await;
+    }
+
+    pub async fn update_entity_subscription(
+        &self,
+        id: &str,
+        params: &UpdateEntitySubscriptionParams,
+        author: &Option<String>,
+    ) -> Result<EntitySubscription, Error> {
+        let current_entity_subscription = self.get_entity_subscription(id).await?;
+        let updated_entity_subscription = current_entity_subscription.merge(params.clone());
+        return self
+            .save_entity_subscription(updated_entity_subscription, author)
+            .await;
+    }
+
+    async fn save_entity_subscription(
+        &self,
+        updated_entity_subscription: EntitySubscription,
+        author: &Option<String>,
+    ) -> Result<EntitySubscription, Error> {
+        if let Some(python_script) = &updated_entity_subscription.python_script {
+            validate_script(
+                updated_entity_subscription.script_runtime,
+                python_script,
+                &updated_entity_subscription.allowed_modules,
+            )
+            .await?;
+        }
+        let _rows_affected = self
+            .entity_subscription_repository
+            .update_entity_subscription(&updated_entity_subscription, author)
+            .await?;
+        (self.publish)(
+            Commands::EntitySubscriptionUpdated {
+                entity_subscription: updated_entity_subscription.clone(),
+            },
+            Some(TopicIds::EntitySubscriptionUpdated),
+        );
+        Ok(updated_entity_subscription)
+    }
+
+    pub async fn get_entity_subscription_revisions(
+        &self,
+        id: &str,
+    ) -> Result<Vec<EntitySubscriptionRevision>, Error> {
+        return self
+            .entity_subscription_repository
+            .get_entity_subscription_revisions(id)
+            .await;
+    }
+
+    pub async fn get_entity_subscription_revision(
+        &self,
+        id: &str,
+        revision: i64,
+    ) -> Result<EntitySubscriptionRevision, Error> {
+        self.entity_subscription_repository
+            .get_entity_subscription_revision(id, revision)
             .await
-            .map(async move |_| {
-                let result = self
-                    .entity_subscription_repository
-                    .create_entity_subscription(params)
-                    .await;
-                return result;
-            })?
+    }
+
+    /// Compares a revision with `against`, or with the revision right before it.
+    pub async fn get_entity_subscription_revision_diff(
+        &self,
+        id: &str,
+        revision: i64,
+        against: Option<i64>,
+    ) -> Result<EntitySubscriptionRevisionDiff, Error> {
+        let to = self
+            .entity_subscription_repository
+            .get_entity_subscription_revision(id, revision)
+            .await?;
+        let from_revision = against.unwrap_or(revision - 1);
+        let from = match from_revision {
+            0 => None,
+            _ => Some(
+                self.entity_subscription_repository
+                    .get_entity_subscription_revision(id, from_revision)
+                    .await?,
+            ),
+        };
+        let before = match &from {
+            Some(from) => serde_json::to_value(&from.snapshot)?,
+            None => json!({}),
+        };
+        Ok(EntitySubscriptionRevisionDiff {
+            entity_subscription_id: id.to_string(),
+            from_revision: from.map(|from| from.revision),
+            to_revision: to.revision,
+            changes: diff_json(&before, &serde_json::to_value(&to.snapshot)?),
+        })
+    }
+
+    /// Restores the definition stored in a revision, which is recorded as a new revision.
+    pub async fn rollback_entity_subscription(
+        &self,
+        id: &str,
+        revision: i64,
+        author: &Option<String>,
+    ) -> Result<EntitySubscription, Error> {
+        let snapshot = self
+            .entity_subscription_repository
+            .get_entity_subscription_revision(id, revision)
+            .await?
+            .snapshot;
+        let current_entity_subscription = self.get_entity_subscription(id).await?;
+        let restored_entity_subscription = EntitySubscription {
+            jdm_transform: snapshot.jdm_transform,
+            python_script: snapshot.python_script,
+            script_runtime: snapshot.script_runtime,
+            allowed_modules: snapshot.allowed_modules,
+            updated_at: Utc::now().timestamp(),
+            ..current_entity_subscription
+        };
+        return self
+            .save_entity_subscription(restored_entity_subscription, author)
             .await;
-        return result;
     }
 
     pub async fn get_all_entity_subscriptions_for_entity_sharing(
         &self,
-        entity_sharing_id: &String,
+        entity_sharing_id: &str,
     ) -> Result<Vec<EntitySubscription>, Error> {
         return self
             .entity_subscription_repository
@@ -44,9 +221,22 @@ impl<'a> EntitySubscriptionCore<'a> {
             .await;
     }
 
+    pub async fn get_entity_subscriptions_for_entity_sharing(
+        &self,
+        entity_sharing_id: &str,
+        filter: &EntitySubscriptionFilter,
+        page: &PageRequest,
+    ) -> Result<Page<EntitySubscription>, Error> {
+        page.require_sort(&[SortField::CreatedAt, SortField::UpdatedAt])?;
+        return self
+            .entity_subscription_repository
+            .get_entity_subscriptions_for_entity_sharing(entity_sharing_id, filter, page)
+            .await;
+    }
+
     pub async fn notify_all_subscriptions_of_new_entity_list(
         &self,
-        entity_sharing_id: &String,
+        entity_sharing_id: &str,
         data: &Value,
     ) -> Result<Vec<Result<(), Error>>, Error> {
         let entity_subscriptions = self
@@ -57,7 +247,57 @@ impl<'a> EntitySubscriptionCore<'a> {
                 .await
         }))
         .await;
-        return Ok(result);
+        Ok(result)
+    }
+
+    /// Releases a held delivery to the subscriptions of its sharing. Once the list reached at
+    /// least one of them, the delivery is approved and the cursor of the held poll saved so that
+    /// the next poll carries on from it. When every delivery failed, the delivery stays held to
+    /// be approved again.
+    pub async fn approve_held_delivery(
+        &self,
+        entity_sharing_id: &str,
+        id: &str,
+    ) -> Result<ApprovedHeldDelivery, Error> {
+        let held_delivery = self
+            .entity_sharing_core
+            .get_held_delivery(entity_sharing_id, id)
+            .await?;
+        if held_delivery.status != HeldDeliveryStatus::Held {
+            return Err(Error::ConflictError(format!(
+                "Delivery {} is no longer held",
+                id
+            )));
+        }
+        let results = self
+            .notify_all_subscriptions_of_new_entity_list(entity_sharing_id, &held_delivery.data)
+            .await?;
+        let subscriptions_notified = results.iter().filter(|result| result.is_ok()).count();
+        let subscriptions_failed = results.len() - subscriptions_notified;
+        if subscriptions_notified == 0
+            && let Some(e) = results.into_iter().find_map(Result::err)
+        {
+            return Err(Error::ScriptError(format!(
+                "No subscription took held delivery {}, it stays held: {:?}",
+                id, e
+            )));
+        }
+
+        let held_delivery = self
+            .entity_sharing_core
+            .resolve_held_delivery(entity_sharing_id, id, HeldDeliveryStatus::Approved)
+            .await?;
+        // Held deliveries recorded before their cursor was kept already saved it.
+        if held_delivery.cursor.is_some() {
+            self.entity_sharing_core
+                .update_entity_sharing_polling_cursor(entity_sharing_id, &held_delivery.cursor)
+                .await?;
+        }
+        Ok(ApprovedHeldDelivery {
+            held_delivery,
+            subscriptions_notified: subscriptions_notified as i64,
+            subscriptions_failed: subscriptions_failed as i64,
+        })
     }
 
     pub async fn notify_subscription_of_new_entity_list(
@@ -65,10 +305,333 @@ impl<'a> EntitySubscriptionCore<'a> {
         entity_subscription: &EntitySubscription,
         data: &Value,
     ) -> Result<(), Error> {
-        println!("Notifying subscription of new entity: {:?}", data);
+        self.run_delivery(entity_subscription, data, Uuid::now_v7().to_string())
+            .await
+    }
+
+    /// Delivers under the given id, which a replay takes from the pending delivery so that the
+    /// delivery is never saved twice.
+    #[instrument(
+        name = "deliver",
+        skip_all,
+        fields(
+            entity_subscription_id = %entity_subscription.id,
+            entity_sharing_id = %entity_subscription.entity_sharing_id,
+            %delivery_id,
+        )
+    )]
+    async fn run_delivery(
+        &self,
+        entity_subscription: &EntitySubscription,
+        data: &Value,
+        delivery_id: String,
+    ) -> Result<(), Error> {
+        {
+            let mut in_flight_deliveries = self.in_flight_deliveries.lock().unwrap();
+            in_flight_deliveries.insert(
+                delivery_id.clone(),
+                PendingDelivery {
+                    id: delivery_id.clone(),
+                    entity_subscription_id: entity_subscription.id.clone(),
+                    data: data.clone(),
+                    created_at: Utc::now().timestamp(),
+                },
+            );
+            set_in_flight_deliveries(in_flight_deliveries.len());
+        }
+        let started = Instant::now();
+        let result = self.deliver_entity_list(entity_subscription, data).await;
+        record_delivery(&entity_subscription.id, result.is_ok(), started.elapsed());
+        let saved = {
+            let mut in_flight_deliveries = self.in_flight_deliveries.lock().unwrap();
+            in_flight_deliveries.remove(&delivery_id);
+            set_in_flight_deliveries(in_flight_deliveries.len());
+            self.saved_deliveries.lock().unwrap().remove(&delivery_id)
+        };
+        if saved {
+            // Ended after the grace period, it must not be replayed on the next start.
+            if let Err(e) = self
+                .entity_subscription_repository
+                .delete_pending_delivery(&delivery_id)
+                .await
+            {
+                error!(error = ?e, "Error deleting the pending delivery of an ended delivery");
+            }
+        }
+        match &result {
+            Ok(()) => info!("Entity list delivered"),
+            Err(e) => error!(error = ?e, "Error delivering entity list"),
+        }
+        (self.publish)(
+            Commands::DeliveryCompleted {
+                delivery: DeliveryResult {
+                    id: delivery_id,
+                    entity_subscription_id: entity_subscription.id.clone(),
+                    entity_sharing_id: entity_subscription.entity_sharing_id.clone(),
+                    connected_app_id: entity_subscription.connected_app_id.clone(),
+                    delivered_at: Utc::now().timestamp(),
+                    error: result.as_ref().err().map(|e| format!("{:?}", e)),
+                },
+            },
+            Some(TopicIds::DeliveryCompleted),
+        );
+        return result;
+    }
+
+    async fn deliver_entity_list(
+        &self,
+        entity_subscription: &EntitySubscription,
+        data: &Value,
+    ) -> Result<(), Error> {
         if let Some(python_script) = &entity_subscription.python_script {
-            run_python_script_output_json(python_script, data)?;
+            let _permit = self
+                .entity_sharing_core
+                .connected_app_core
+                .acquire_call_permit(&entity_subscription.connected_app_id)
+                .await?;
+            spawn_script_output_json(
+                entity_subscription.script_runtime,
+                python_script,
+                data,
+                &None,
+                &entity_subscription.allowed_modules,
+            )
+            .instrument(info_span!(
+                "transform",
+                runtime = ?entity_subscription.script_runtime
+            ))
+            .await?;
         }
         Ok(())
     }
+
+    /// Saves the deliveries still running so that they are replayed on the next start. Meant to
+    /// be called once the shutdown grace period is over. A saved delivery that ends afterwards
+    /// deletes its pending delivery.
+    pub async fn save_in_flight_deliveries(&self) -> Result<usize, Error> {
+        let in_flight_deliveries: Vec<PendingDelivery> = {
+            let in_flight_deliveries = self.in_flight_deliveries.lock().unwrap();
+            self.saved_deliveries
+                .lock()
+                .unwrap()
+                .extend(in_flight_deliveries.keys().cloned());
+            in_flight_deliveries.values().cloned().collect()
+        };
+        for pending_delivery in &in_flight_deliveries {
+            self.entity_subscription_repository
+                .create_pending_delivery(pending_delivery)
+                .await?;
+            // It may have ended, and found nothing to delete, while it was being saved.
+            let ended = !self
+                .in_flight_deliveries
+                .lock()
+                .unwrap()
+                .contains_key(&pending_delivery.id);
+            if ended {
+                self.entity_subscription_repository
+                    .delete_pending_delivery(&pending_delivery.id)
+                    .await?;
+            }
+        }
+        Ok(in_flight_deliveries.len())
+    }
+
+    /// Delivers again what a previous shutdown interrupted. A pending delivery is deleted once
+    /// delivered, or once its subscription is gone, and is otherwise tried again on next start.
+    pub async fn replay_pending_deliveries(&self) -> Result<(), Error> {
+        let pending_deliveries = self
+            .entity_subscription_repository
+            .get_pending_deliveries()
+            .await?;
+        for pending_delivery in pending_deliveries {
+            record_delivery_retry(&pending_delivery.entity_subscription_id);
+            let result = match self
+                .get_entity_subscription(&pending_delivery.entity_subscription_id)
+                .await
+            {
+                Ok(entity_subscription) => {
+                    self.run_delivery(
+                        &entity_subscription,
+                        &pending_delivery.data,
+                        pending_delivery.id.clone(),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) | Err(Error::NotFoundError(_)) => {
+                    self.entity_subscription_repository
+                        .delete_pending_delivery(&pending_delivery.id)
+                        .await?;
+                }
+                Err(e) => error!(
+                    pending_delivery_id = %pending_delivery.id,
+                    error = ?e,
+                    "Error replaying pending delivery"
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connected_app::connected_app_core::ConnectedAppCore;
+    use crate::connected_app::connected_app_limiter::ConnectedAppLimiter;
+    use crate::connected_app::connected_app_repository::connected_app_sqlite_repository::ConnectedAppSQLiteRepository;
+    use crate::entity_sharing::entity_sharing_model::HeldDelivery;
+    use crate::entity_sharing::entity_sharing_repository::CreateEntitySharingParams;
+    use crate::entity_sharing::entity_sharing_repository::entity_sharing_sqlite_repository::EntitySharingSQLiteRepository;
+    use crate::entity_subscription::entity_subscription_repository::CreateEntitySubscriptionParams;
+    use crate::entity_subscription::entity_subscription_repository::entity_subscription_sqlite_repository::EntitySubscriptionSQLiteRepository;
+    use crate::shared::db::get_db;
+    use crate::shared::script_runtime::ScriptRuntimeKind;
+
+    async fn open_core() -> EntitySubscriptionCore<'static> {
+        let path = std::env::temp_dir().join(format!("heutl-{}.db", Uuid::now_v7()));
+        let pool = Box::leak(Box::new(
+            get_db(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        ));
+        let connected_app_core = Arc::new(ConnectedAppCore {
+            connected_app_repository: Box::new(ConnectedAppSQLiteRepository { pool }),
+            connected_app_limiter: ConnectedAppLimiter::default(),
+            publish: Box::new(|_, _| {}),
+        });
+        let entity_sharing_core = Arc::new(EntitySharingCore::new(
+            connected_app_core,
+            Box::new(EntitySharingSQLiteRepository { pool }),
+            Box::new(|_, _| {}),
+        ));
+        entity_sharing_core
+            .entity_sharing_repository
+            .create_entity_sharing(
+                "entity-sharing",
+                &CreateEntitySharingParams {
+                    id: None,
+                    name: "Assets".to_string(),
+                    connected_app_id: "connected-app".to_string(),
+                    json_schema: json!({}),
+                    python_script: None,
+                    is_array: true,
+                    script_runtime: ScriptRuntimeKind::Python,
+                    allowed_modules: vec![],
+                    polling_infos: None,
+                },
+                &None,
+            )
+            .await
+            .unwrap();
+        EntitySubscriptionCore {
+            entity_subscription_repository: Box::new(EntitySubscriptionSQLiteRepository { pool }),
+            entity_sharing_core,
+            in_flight_deliveries: Mutex::new(HashMap::new()),
+            saved_deliveries: Mutex::new(HashSet::new()),
+            publish: Box::new(|_, _| {}),
+        }
+    }
+
+    async fn hold(core: &EntitySubscriptionCore<'_>, id: &str, cursor: Option<Value>) {
+        core.entity_sharing_core
+            .entity_sharing_repository
+            .create_held_delivery(&HeldDelivery {
+                id: id.to_string(),
+                entity_sharing_id: "entity-sharing".to_string(),
+                run_id: "run".to_string(),
+                data: json!([]),
+                entity_count: 0,
+                previous_entity_count: 10,
+                cursor,
+                status: HeldDeliveryStatus::Held,
+                created_at: Utc::now().timestamp(),
+                resolved_at: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn polling_cursor(core: &EntitySubscriptionCore<'_>) -> Option<Value> {
+        core.entity_sharing_core
+            .get_entity_sharing("entity-sharing")
+            .await
+            .unwrap()
+            .polling_cursor
+    }
+
+    #[tokio::test]
+    async fn approving_a_held_delivery_saves_its_cursor() {
+        let core = open_core().await;
+        hold(&core, "held-delivery", Some(json!({"page": 3}))).await;
+        assert_eq!(polling_cursor(&core).await, None);
+
+        let approved = core
+            .approve_held_delivery("entity-sharing", "held-delivery")
+            .await
+            .unwrap();
+
+        assert_eq!(approved.held_delivery.status, HeldDeliveryStatus::Approved);
+        assert_eq!(polling_cursor(&core).await, Some(json!({"page": 3})));
+    }
+
+    #[tokio::test]
+    async fn held_deliveries_no_subscription_took_stay_held() {
+        let core = open_core().await;
+        core.entity_subscription_repository
+            .create_entity_subscription(
+                "entity-subscription",
+                &CreateEntitySubscriptionParams {
+                    id: None,
+                    entity_sharing_id: "entity-sharing".to_string(),
+                    connected_app_id: "connected-app".to_string(),
+                    jdm_transform: None,
+                    // Exports nothing to run.
+                    python_script: Some("(module)".to_string()),
+                    script_runtime: ScriptRuntimeKind::Wasm,
+                    allowed_modules: vec![],
+                },
+                &None,
+            )
+            .await
+            .unwrap();
+        hold(&core, "held-delivery", Some(json!({"page": 3}))).await;
+
+        assert!(
+            core.approve_held_delivery("entity-sharing", "held-delivery")
+                .await
+                .is_err()
+        );
+
+        let held_delivery = core
+            .entity_sharing_core
+            .get_held_delivery("entity-sharing", "held-delivery")
+            .await
+            .unwrap();
+        assert_eq!(held_delivery.status, HeldDeliveryStatus::Held);
+        assert_eq!(polling_cursor(&core).await, None);
+    }
+
+    #[tokio::test]
+    async fn rejecting_a_held_delivery_leaves_the_cursor() {
+        let core = open_core().await;
+        core.entity_sharing_core
+            .update_entity_sharing_polling_cursor("entity-sharing", &Some(json!({"page": 2})))
+            .await
+            .unwrap();
+        hold(&core, "held-delivery", Some(json!({"page": 3}))).await;
+
+        core.entity_sharing_core
+            .resolve_held_delivery(
+                "entity-sharing",
+                "held-delivery",
+                HeldDeliveryStatus::Rejected,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(polling_cursor(&core).await, Some(json!({"page": 2})));
+    }
 }
@@ -1,7 +1,15 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+use crate::entity_subscription::entity_subscription_repository::UpdateEntitySubscriptionParams;
+use crate::shared::json_diff::JsonChange;
+use crate::shared::merge_struct::Merge;
+use crate::shared::pagination::{Paginated, SortField, SortValue};
+use crate::shared::script_runtime::ScriptRuntimeKind;
+use chrono::Utc;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq, Clone, ToSchema)]
 pub struct EntitySubscription {
     pub id: String,
     pub entity_sharing_id: String,
@@ -10,5 +18,84 @@ pub struct EntitySubscription {
     pub connected_app_id: String,
     pub jdm_transform: Option<Value>,
     pub python_script: Option<String>,
-    
+    /// Runtime `python_script` is written for.
+    pub script_runtime: ScriptRuntimeKind,
+    /// Modules `python_script` may import, submodules included.
+    #[sqlx(json)]
+    pub allowed_modules: Vec<String>,
+}
+
+impl Paginated for EntitySubscription {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Subscriptions have no name, their repository only sorts them by date.
+    fn sort_value(&self, sort: SortField) -> SortValue {
+        match sort {
+            SortField::UpdatedAt => SortValue::Integer(self.updated_at),
+            SortField::CreatedAt | SortField::Name => SortValue::Integer(self.created_at),
+        }
+    }
+}
+
+/// Immutable snapshot of a subscription definition, recorded on every change.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySubscriptionRevision {
+    pub id: String,
+    pub entity_subscription_id: String,
+    pub revision: i64,
+    pub author: Option<String>,
+    pub created_at: i64,
+    pub snapshot: EntitySubscription,
+}
+
+/// Entity list handed to a subscription, saved if shutdown interrupts its delivery.
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone)]
+pub struct PendingDelivery {
+    pub id: String,
+    pub entity_subscription_id: String,
+    #[sqlx(json)]
+    pub data: Value,
+    pub created_at: i64,
+}
+
+/// Outcome of handing an entity list to a subscription.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct DeliveryResult {
+    pub id: String,
+    pub entity_subscription_id: String,
+    pub entity_sharing_id: String,
+    pub connected_app_id: String,
+    pub delivered_at: i64,
+    /// Why the delivery failed, `None` when it succeeded.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EntitySubscriptionRevisionDiff {
+    pub entity_subscription_id: String,
+    pub from_revision: Option<i64>,
+    pub to_revision: i64,
+    pub changes: Vec<JsonChange>,
+}
+
+impl Merge<UpdateEntitySubscriptionParams> for EntitySubscription {
+    fn merge(self, other: UpdateEntitySubscriptionParams) -> Self {
+        let mut merged = self.clone();
+        if let Some(jdm_transform) = other.jdm_transform {
+            merged.jdm_transform = Some(jdm_transform);
+        }
+        if let Some(python_script) = other.python_script {
+            merged.python_script = Some(python_script);
+        }
+        if let Some(script_runtime) = other.script_runtime {
+            merged.script_runtime = script_runtime;
+        }
+        if let Some(allowed_modules) = other.allowed_modules {
+            merged.allowed_modules = allowed_modules;
+        }
+        merged.updated_at = Utc::now().timestamp();
+        merged
+    }
 }
@@ -1,25 +1,84 @@
-use crate::entity_subscription::entity_subscription_model::EntitySubscription;
+use crate::entity_subscription::entity_subscription_model::{
+    EntitySubscription, EntitySubscriptionRevision, PendingDelivery,
+};
 use crate::shared::errors::Error;
+use crate::shared::pagination::{Page, PageRequest};
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use async_trait::async_trait;
 use serde_json::Value;
 pub mod entity_subscription_sqlite_repository;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
 pub struct CreateEntitySubscriptionParams {
-    pub id: String,
+    /// Generated as a UUIDv7 when left out.
+    #[serde(default)]
+    pub id: Option<String>,
     pub entity_sharing_id: String,
     pub connected_app_id: String,
     pub jdm_transform: Option<Value>,
     pub python_script: Option<String>,
+    #[serde(default)]
+    pub script_runtime: ScriptRuntimeKind,
+    #[serde(default)]
+    pub allowed_modules: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
+pub struct UpdateEntitySubscriptionParams {
+    pub jdm_transform: Option<Value>,
+    pub python_script: Option<String>,
+    pub script_runtime: Option<ScriptRuntimeKind>,
+    pub allowed_modules: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EntitySubscriptionFilter {
+    /// App the subscriptions deliver to.
+    pub connected_app_id: Option<String>,
+    /// Unix timestamp the subscriptions were last updated at or after.
+    pub updated_since: Option<i64>,
 }
 
 #[async_trait]
 pub trait EntitySubscriptionRepository: Send + Sync {
+    /// Records the first revision of the subscription along with it.
     async fn create_entity_subscription(
         &self,
+        id: &str,
         params: &CreateEntitySubscriptionParams,
+        author: &Option<String>,
     ) -> Result<EntitySubscription, Error>;
-    async fn get_entity_subscription_by_id(&self, id: &String) -> Result<EntitySubscription, Error>;
-    async fn get_all_entity_subscriptions_for_entity_sharing(&self, entity_sharing_id: &String) -> Result<Vec<EntitySubscription>, Error>;
+    async fn get_entity_subscription_by_id(&self, id: &str) -> Result<EntitySubscription, Error>;
+    async fn get_all_entity_subscriptions_for_entity_sharing(&self, entity_sharing_id: &str) -> Result<Vec<EntitySubscription>, Error>;
+    async fn get_entity_subscriptions_for_entity_sharing(
+        &self,
+        entity_sharing_id: &str,
+        filter: &EntitySubscriptionFilter,
+        page: &PageRequest,
+    ) -> Result<Page<EntitySubscription>, Error>;
+    /// Records the next revision of the subscription along with the change.
+    async fn update_entity_subscription(
+        &self,
+        entity_subscription: &EntitySubscription,
+        author: &Option<String>,
+    ) -> Result<u64, Error>;
+    async fn get_entity_subscription_revisions(
+        &self,
+        entity_subscription_id: &str,
+    ) -> Result<Vec<EntitySubscriptionRevision>, Error>;
+    async fn get_entity_subscription_revision(
+        &self,
+        entity_subscription_id: &str,
+        revision: i64,
+    ) -> Result<EntitySubscriptionRevision, Error>;
+    /// Does nothing if the delivery is already pending.
+    async fn create_pending_delivery(
+        &self,
+        pending_delivery: &PendingDelivery,
+    ) -> Result<(), Error>;
+    async fn get_pending_deliveries(&self) -> Result<Vec<PendingDelivery>, Error>;
+    async fn delete_pending_delivery(&self, id: &str) -> Result<(), Error>;
 }
@@ -1,11 +1,61 @@
-use crate::entity_subscription::entity_subscription_model::EntitySubscription;
+use crate::entity_subscription::entity_subscription_model::{
+    EntitySubscription, EntitySubscriptionRevision, PendingDelivery,
+};
 use crate::entity_subscription::entity_subscription_repository::{
-    CreateEntitySubscriptionParams, EntitySubscriptionRepository,
+    CreateEntitySubscriptionParams, EntitySubscriptionFilter, EntitySubscriptionRepository,
 };
 use crate::shared::errors::Error;
+use crate::shared::pagination::{Page, PageRequest};
 use async_trait::async_trait;
 use chrono::Utc;
-use sqlx::sqlite::SqlitePool;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{Sqlite, SqlitePool};
+use sqlx::{QueryBuilder, Transaction};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, PartialEq, Eq)]
+pub struct EntitySubscriptionRevisionDTO {
+    pub id: String,
+    pub entity_subscription_id: String,
+    pub revision: i64,
+    pub snapshot: String,
+    pub author: Option<String>,
+    pub created_at: i64,
+}
+
+fn entity_subscription_revision_dto_to_entity_subscription_revision(
+    entity_subscription_revision_dto: EntitySubscriptionRevisionDTO,
+) -> Result<EntitySubscriptionRevision, Error> {
+    Ok(EntitySubscriptionRevision {
+        id: entity_subscription_revision_dto.id,
+        entity_subscription_id: entity_subscription_revision_dto.entity_subscription_id,
+        revision: entity_subscription_revision_dto.revision,
+        author: entity_subscription_revision_dto.author,
+        created_at: entity_subscription_revision_dto.created_at,
+        snapshot: serde_json::from_str(&entity_subscription_revision_dto.snapshot)?,
+    })
+}
+
+/// Records the next revision of a subscription, in the transaction changing it.
+async fn insert_entity_subscription_revision(
+    transaction: &mut Transaction<'_, Sqlite>,
+    entity_subscription: &EntitySubscription,
+    author: &Option<String>,
+) -> Result<EntitySubscriptionRevision, Error> {
+    let result: EntitySubscriptionRevisionDTO = sqlx::query_as(
+        "INSERT INTO entity_subscription_revisions (id, entity_subscription_id, revision, snapshot, author, created_at)
+    VALUES ($1, $2, (SELECT COALESCE(MAX(revision), 0) + 1 FROM entity_subscription_revisions WHERE entity_subscription_id = $2), $3, $4, $5)
+    RETURNING *",
+    )
+    .bind(Uuid::now_v7().to_string())
+    .bind(&entity_subscription.id)
+    .bind(serde_json::to_string(entity_subscription)?)
+    .bind(author)
+    .bind(Utc::now().timestamp())
+    .fetch_one(&mut **transaction)
+    .await?;
+    entity_subscription_revision_dto_to_entity_subscription_revision(result)
+}
 
 pub struct EntitySubscriptionSQLiteRepository<'a> {
     pub pool: &'a SqlitePool,
@@ -15,45 +65,54 @@ pub struct EntitySubscriptionSQLiteRepository<'a> {
 impl<'a> EntitySubscriptionRepository for EntitySubscriptionSQLiteRepository<'a> {
     async fn create_entity_subscription(
         &self,
+        id: &str,
         params: &CreateEntitySubscriptionParams,
+        author: &Option<String>,
     ) -> Result<EntitySubscription, Error> {
         let entity_subscription = EntitySubscription {
-            id: params.id.clone(),
+            id: id.to_string(),
             entity_sharing_id: params.entity_sharing_id.clone(),
             created_at: Utc::now().timestamp(),
             updated_at: Utc::now().timestamp(),
             connected_app_id: params.connected_app_id.clone(),
             jdm_transform: params.jdm_transform.clone(),
             python_script: params.python_script.clone(),
+            script_runtime: params.script_runtime,
+            allowed_modules: params.allowed_modules.clone(),
         };
 
-        sqlx::query("INSERT INTO entity_subscriptions (id, entity_sharing_id, created_at, updated_at, connected_app_id, jdm_transform, python_script) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("INSERT INTO entity_subscriptions (id, entity_sharing_id, created_at, updated_at, connected_app_id, jdm_transform, python_script, script_runtime, allowed_modules) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, json($9))")
         .bind(&entity_subscription.id)
         .bind(&entity_subscription.entity_sharing_id)
-        .bind(&entity_subscription.created_at)
-        .bind(&entity_subscription.updated_at)
+        .bind(entity_subscription.created_at)
+        .bind(entity_subscription.updated_at)
         .bind(&entity_subscription.connected_app_id)
         .bind(serde_json::to_string(&entity_subscription.jdm_transform).unwrap_or_else(|_| "".to_string()))
         .bind(&entity_subscription.python_script)
-        .execute(self.pool)
+        .bind(entity_subscription.script_runtime)
+        .bind(serde_json::to_string(&entity_subscription.allowed_modules)?)
+        .execute(&mut *transaction)
         .await?;
+        insert_entity_subscription_revision(&mut transaction, &entity_subscription, author).await?;
+        transaction.commit().await?;
 
         return Ok(entity_subscription);
     }
 
     async fn get_entity_subscription_by_id(
         &self,
-        id: &String,
+        id: &str,
     ) -> Result<EntitySubscription, Error> {
         let result: EntitySubscription =
-            sqlx::query_as("SELECT * FROM entity_subscriptions WHERE id = :id LIMIT 1")
+            sqlx::query_as("SELECT * FROM entity_subscriptions WHERE id = $1 LIMIT 1")
                 .bind(id)
                 .fetch_one(self.pool)
                 .await?;
         return Ok(result);
     }
 
-    async fn get_all_entity_subscriptions_for_entity_sharing(&self, entity_sharing_id: &String) -> Result<Vec<EntitySubscription>, Error> {
+    async fn get_all_entity_subscriptions_for_entity_sharing(&self, entity_sharing_id: &str) -> Result<Vec<EntitySubscription>, Error> {
         let result: Vec<EntitySubscription> =
             sqlx::query_as("SELECT * FROM entity_subscriptions WHERE entity_sharing_id = $1")
                 .bind(entity_sharing_id)
@@ -61,4 +120,115 @@ impl<'a> EntitySubscriptionRepository for EntitySubscriptionSQLiteRepository<'a>
                 .await?;
         return Ok(result);
     }
+
+    async fn get_entity_subscriptions_for_entity_sharing(
+        &self,
+        entity_sharing_id: &str,
+        filter: &EntitySubscriptionFilter,
+        page: &PageRequest,
+    ) -> Result<Page<EntitySubscription>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT * FROM entity_subscriptions WHERE entity_sharing_id = ",
+        );
+        query.push_bind(entity_sharing_id);
+        if let Some(connected_app_id) = &filter.connected_app_id {
+            query
+                .push(" AND connected_app_id = ")
+                .push_bind(connected_app_id);
+        }
+        if let Some(updated_since) = filter.updated_since {
+            query.push(" AND updated_at >= ").push_bind(updated_since);
+        }
+        page.push_to(&mut query);
+        let entity_subscriptions: Vec<EntitySubscription> =
+            query.build_query_as().fetch_all(self.pool).await?;
+        Ok(page.page_of(entity_subscriptions))
+    }
+
+    async fn update_entity_subscription(
+        &self,
+        entity_subscription: &EntitySubscription,
+        author: &Option<String>,
+    ) -> Result<u64, Error> {
+        let mut transaction = self.pool.begin().await?;
+        let result = sqlx::query("UPDATE entity_subscriptions SET updated_at = $1, jdm_transform = $2, python_script = $3, script_runtime = $4, allowed_modules = json($5) WHERE id = $6")
+        .bind(entity_subscription.updated_at)
+        .bind(serde_json::to_string(&entity_subscription.jdm_transform).unwrap_or_else(|_| "".to_string()))
+        .bind(&entity_subscription.python_script)
+        .bind(entity_subscription.script_runtime)
+        .bind(serde_json::to_string(&entity_subscription.allowed_modules)?)
+        .bind(&entity_subscription.id)
+        .execute(&mut *transaction)
+        .await?;
+        if result.rows_affected() > 0 {
+            insert_entity_subscription_revision(&mut transaction, entity_subscription, author)
+                .await?;
+        }
+        transaction.commit().await?;
+        return Ok(result.rows_affected());
+    }
+
+    async fn get_entity_subscription_revisions(
+        &self,
+        entity_subscription_id: &str,
+    ) -> Result<Vec<EntitySubscriptionRevision>, Error> {
+        let result: Vec<EntitySubscriptionRevisionDTO> = sqlx::query_as(
+            "SELECT * FROM entity_subscription_revisions WHERE entity_subscription_id = $1 ORDER BY revision DESC",
+        )
+        .bind(entity_subscription_id)
+        .fetch_all(self.pool)
+        .await?;
+        return result
+            .into_iter()
+            .map(entity_subscription_revision_dto_to_entity_subscription_revision)
+            .collect::<Result<Vec<EntitySubscriptionRevision>, Error>>();
+    }
+
+    async fn get_entity_subscription_revision(
+        &self,
+        entity_subscription_id: &str,
+        revision: i64,
+    ) -> Result<EntitySubscriptionRevision, Error> {
+        let result: EntitySubscriptionRevisionDTO = sqlx::query_as(
+            "SELECT * FROM entity_subscription_revisions WHERE entity_subscription_id = $1 AND revision = $2 LIMIT 1",
+        )
+        .bind(entity_subscription_id)
+        .bind(revision)
+        .fetch_one(self.pool)
+        .await?;
+        return entity_subscription_revision_dto_to_entity_subscription_revision(result);
+    }
+
+    async fn create_pending_delivery(
+        &self,
+        pending_delivery: &PendingDelivery,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO pending_deliveries (id, entity_subscription_id, data, created_at) VALUES ($1, $2, json($3), $4)
+        ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(&pending_delivery.id)
+        .bind(&pending_delivery.entity_subscription_id)
+        .bind(serde_json::to_string(&pending_delivery.data)?)
+        .bind(pending_delivery.created_at)
+        .execute(self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_pending_deliveries(&self) -> Result<Vec<PendingDelivery>, Error> {
+        let result: Vec<PendingDelivery> =
+            sqlx::query_as("SELECT * FROM pending_deliveries ORDER BY created_at")
+                .fetch_all(self.pool)
+                .await?;
+        Ok(result)
+    }
+
+    async fn delete_pending_delivery(&self, id: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM pending_deliveries WHERE id = $1")
+            .bind(id)
+            .execute(self.pool)
+            .await?;
+        Ok(())
+    }
 }
@@ -1,35 +1,272 @@
-use crate::entity_subscription::entity_subscription_repository::CreateEntitySubscriptionParams;
-use crate::services::web_api::WebAppCores;
+use crate::entity_subscription::entity_subscription_model::{
+    EntitySubscription, EntitySubscriptionRevision, EntitySubscriptionRevisionDiff,
+};
+use crate::entity_subscription::entity_subscription_repository::{
+    CreateEntitySubscriptionParams, EntitySubscriptionFilter, UpdateEntitySubscriptionParams,
+};
+use crate::services::auth::Caller;
+use crate::services::policy::{
+    require_connected_app_owner, require_entity_subscription_owner,
+    require_script_sandbox_unchanged,
+};
+use crate::services::web_api::{Author, RevisionDiffQuery, WebAppCores};
+use crate::shared::errors::{Error, ErrorBody};
+use crate::shared::pagination::{Page, PageQuery};
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use axum::{
     Json, debug_handler,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
 };
 use reqwest::StatusCode;
 
 
+#[utoipa::path(
+    get,
+    path = "/entity-sharings/{entity_sharing_id}/subscriptions",
+    tag = "entity-subscriptions",
+    params(
+        ("entity_sharing_id" = String, Path, description = "Id of the entity sharing"),
+        EntitySubscriptionFilter,
+        PageQuery,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Page of the subscriptions to the entity sharing",
+            body = Page<EntitySubscription>,
+        ),
+        (status = 400, description = "Invalid page or sort by name", body = ErrorBody),
+    )
+)]
 #[debug_handler]
 pub async fn get_entity_subscriptions(
     State(web_app_cores): State<WebAppCores>,
     Path(entity_sharing_id): Path<String>,
-) -> impl IntoResponse {
+    Query(filter): Query<EntitySubscriptionFilter>,
+    Query(page): Query<PageQuery>,
+) -> Result<impl IntoResponse, Error> {
     let entity_subscriptions = web_app_cores
         .entity_subscription_core
-        .get_all_entity_subscriptions_for_entity_sharing(&entity_sharing_id)
-        .await
-        .unwrap();
-    return (StatusCode::OK, Json(entity_subscriptions));
+        .get_entity_subscriptions_for_entity_sharing(
+            &entity_sharing_id,
+            &filter,
+            &page.page_request()?,
+        )
+        .await?;
+    Ok((StatusCode::OK, Json(entity_subscriptions)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/entity-subscriptions",
+    tag = "entity-subscriptions",
+    params(
+        ("X-Author" = Option<String>, Header, description = "Author recorded on the revision"),
+    ),
+    request_body = CreateEntitySubscriptionParams,
+    responses(
+        (status = 201, description = "Created entity subscription", body = EntitySubscription),
+        (status = 409, description = "Id already taken", body = ErrorBody),
+        (status = 422, description = "Invalid fields, listed in `fields`", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
 #[debug_handler]
 pub async fn create_entity_subscription(
     State(web_app_cores): State<WebAppCores>,
+    Author(author): Author,
+    caller: Caller,
     Json(data): Json<CreateEntitySubscriptionParams>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, Error> {
+    require_connected_app_owner(
+        &caller,
+        &data.connected_app_id,
+        "create entity subscriptions for this connected app",
+    )?;
+    require_script_sandbox_unchanged(
+        &caller,
+        (ScriptRuntimeKind::default(), &[]),
+        (Some(data.script_runtime), Some(&data.allowed_modules)),
+    )?;
+    let entity_subscription = web_app_cores
+        .entity_subscription_core
+        .create_entity_subscription(&data, &author)
+        .await?;
+    Ok((StatusCode::CREATED, Json(entity_subscription)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-subscriptions/{entity_subscription_id}",
+    tag = "entity-subscriptions",
+    params(
+        ("entity_subscription_id" = String, Path, description = "Id of the entity subscription"),
+    ),
+    responses(
+        (status = 200, description = "Entity subscription", body = EntitySubscription),
+        (status = 404, description = "Unknown entity subscription", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_subscription(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_subscription_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let entity_subscription = web_app_cores
+        .entity_subscription_core
+        .get_entity_subscription(&entity_subscription_id)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_subscription)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/entity-subscriptions/{entity_subscription_id}",
+    tag = "entity-subscriptions",
+    params(
+        ("entity_subscription_id" = String, Path, description = "Id of the entity subscription"),
+        ("X-Author" = Option<String>, Header, description = "Author recorded on the revision"),
+    ),
+    request_body = UpdateEntitySubscriptionParams,
+    responses(
+        (status = 200, description = "Updated entity subscription", body = EntitySubscription),
+        (status = 404, description = "Unknown entity subscription", body = ErrorBody),
+        (status = 422, description = "Invalid script", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn update_entity_subscription(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_subscription_id): Path<String>,
+    Author(author): Author,
+    caller: Caller,
+    Json(data): Json<UpdateEntitySubscriptionParams>,
+) -> Result<impl IntoResponse, Error> {
+    let entity_subscription = require_entity_subscription_owner(
+        &caller,
+        &web_app_cores,
+        &entity_subscription_id,
+        "update this entity subscription",
+    )
+    .await?;
+    require_script_sandbox_unchanged(
+        &caller,
+        (entity_subscription.script_runtime, &entity_subscription.allowed_modules),
+        (data.script_runtime, data.allowed_modules.as_deref()),
+    )?;
+    let entity_subscription = web_app_cores
+        .entity_subscription_core
+        .update_entity_subscription(&entity_subscription_id, &data, &author)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_subscription)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-subscriptions/{entity_subscription_id}/revisions",
+    tag = "entity-subscriptions",
+    params(
+        ("entity_subscription_id" = String, Path, description = "Id of the entity subscription"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Revisions of the entity subscription",
+            body = Vec<EntitySubscriptionRevision>,
+        ),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_subscription_revisions(
+    State(web_app_cores): State<WebAppCores>,
+    Path(entity_subscription_id): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let revisions = web_app_cores
+        .entity_subscription_core
+        .get_entity_subscription_revisions(&entity_subscription_id)
+        .await?;
+    Ok((StatusCode::OK, Json(revisions)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/entity-subscriptions/{entity_subscription_id}/revisions/{revision}/diff",
+    tag = "entity-subscriptions",
+    params(
+        ("entity_subscription_id" = String, Path, description = "Id of the entity subscription"),
+        ("revision" = i64, Path, description = "Revision number"),
+        RevisionDiffQuery,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Changes made by the revision",
+            body = EntitySubscriptionRevisionDiff,
+        ),
+        (status = 404, description = "Unknown revision", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_entity_subscription_revision_diff(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_subscription_id, revision)): Path<(String, i64)>,
+    Query(query): Query<RevisionDiffQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let diff = web_app_cores
+        .entity_subscription_core
+        .get_entity_subscription_revision_diff(&entity_subscription_id, revision, query.against)
+        .await?;
+    Ok((StatusCode::OK, Json(diff)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/entity-subscriptions/{entity_subscription_id}/revisions/{revision}/rollback",
+    tag = "entity-subscriptions",
+    params(
+        ("entity_subscription_id" = String, Path, description = "Id of the entity subscription"),
+        ("revision" = i64, Path, description = "Revision number"),
+        ("X-Author" = Option<String>, Header, description = "Author recorded on the revision"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Entity subscription restored as a new revision",
+            body = EntitySubscription,
+        ),
+        (status = 404, description = "Unknown revision", body = ErrorBody),
+        (status = 403, description = "Denied to the role of the caller", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn rollback_entity_subscription(
+    State(web_app_cores): State<WebAppCores>,
+    Path((entity_subscription_id, revision)): Path<(String, i64)>,
+    Author(author): Author,
+    caller: Caller,
+) -> Result<impl IntoResponse, Error> {
+    let entity_subscription = require_entity_subscription_owner(
+        &caller,
+        &web_app_cores,
+        &entity_subscription_id,
+        "roll back this entity subscription",
+    )
+    .await?;
+    let snapshot = web_app_cores
+        .entity_subscription_core
+        .get_entity_subscription_revision(&entity_subscription_id, revision)
+        .await?
+        .snapshot;
+    require_script_sandbox_unchanged(
+        &caller,
+        (entity_subscription.script_runtime, &entity_subscription.allowed_modules),
+        (Some(snapshot.script_runtime), Some(&snapshot.allowed_modules)),
+    )?;
     let entity_subscription = web_app_cores
         .entity_subscription_core
-        .create_entity_subscription(&data)
-        .await
-        .unwrap();
-    return (StatusCode::CREATED, Json(entity_subscription));
+        .rollback_entity_subscription(&entity_subscription_id, revision, &author)
+        .await?;
+    Ok((StatusCode::OK, Json(entity_subscription)))
 }
@@ -1,6 +1,10 @@
-use crate::connected_app::connected_app_core::ConnectedAppCore;
+use crate::connected_app::connected_app_core::{ConnectedAppCore, hash_api_key};
+use crate::connected_app::connected_app_limiter::ConnectedAppLimiter;
 use crate::connected_app::connected_app_repository::connected_app_sqlite_repository::ConnectedAppSQLiteRepository;
 use crate::entity_sharing::entity_sharing_core::{ EntitySharingCore};
+use crate::entity_sharing::entity_polling_schedule::{
+    IntervalMode, PollingRetryPolicy, PollingSchedule,
+};
 use crate::entity_sharing::entity_sharing_model::EntitySharingPollingInfos;
 use crate::entity_sharing::entity_sharing_repository::{CreateEntitySharingParams};
 use crate::entity_sharing::entity_sharing_repository::entity_sharing_sqlite_repository::EntitySharingSQLiteRepository;
@@ -8,16 +12,25 @@ use crate::entity_subscription::entity_subscription_core::EntitySubscriptionCore
 use crate::entity_subscription::entity_subscription_repository::entity_subscription_sqlite_repository::EntitySubscriptionSQLiteRepository;
 use crate::entity_subscription::entity_subscription_repository::CreateEntitySubscriptionParams;
 use crate::connected_app::connected_app_repository::CreateConnectedAppParams;
-use crate::services::web_api::run_web_api;
+use crate::services::alerts::Alerter;
+use crate::services::event_stream::EventStream;
+use crate::services::web_api::{WebAppCores, run_web_api};
+use crate::shared::config::Config;
 use crate::shared::db::get_db;
+use crate::shared::metrics::init_metrics;
+use crate::shared::python_runner::check_python_interpreter;
+use crate::shared::shutdown::ShutdownCoordinator;
+use crate::shared::telemetry::init_telemetry;
+use crate::shared::script_runtime::ScriptRuntimeKind;
 use crate::entity_sharing::entity_polling_handler::EntityPollingHandler;
-use crate::shared::bus::{Commands, TopicIds};
+use crate::entity_sharing::entity_polling_scheduler::EntityPollingScheduler;
+use crate::shared::bus::{Commands, Publish, TopicIds};
 use pubsub_bus::{EventBus};
-use serde_json::{json, Value};
-use uuid::{Uuid, Timestamp, NoContext};
-use chrono::{Timelike, Utc};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
 
 mod connected_app;
 mod entity_sharing;
@@ -30,133 +43,223 @@ async fn test_scenario<'a>(
     entity_sharing_core: Arc<EntitySharingCore<'a>>,
     entity_subscription_core: Arc<EntitySubscriptionCore<'a>>,
 ) {
-    let ts = Timestamp::from_unix(
-        NoContext,
-        (Utc::now().timestamp() * 1000).try_into().unwrap(),
-        0,
-    );
+    // Instances sharing a database only seed it once.
+    if entity_sharing_core
+        .get_entity_sharing("423f9ce6-acc0-7fe9-9ef6-270b1e7acb78")
+        .await
+        .is_ok()
+    {
+        return;
+    }
     let aptimize_app = app_core
         .create_connected_app(&CreateConnectedAppParams {
-            id: Uuid::new_v7(ts).to_string(),
+            id: None,
             name: "Aptimize".to_string(),
+            rate_limit_per_minute: None,
+            max_concurrent_calls: None,
         })
         .await
         .unwrap();
     let arcfm_app = app_core
         .create_connected_app(&CreateConnectedAppParams {
-            id: Uuid::new_v7(ts).to_string(),
+            id: None,
             name: "ArcFM".to_string(),
+            rate_limit_per_minute: None,
+            max_concurrent_calls: None,
         })
         .await
         .unwrap();
     let aptimize_asset = entity_sharing_core
-        .create_entity_sharing(&CreateEntitySharingParams {
-            id: "423f9ce6-acc0-7fe9-9ef6-270b1e7acb78".to_string(),
-            name: "Aptimize asset".to_string(),
-            connected_app_id: aptimize_app.id.clone(),
-            json_schema: json!({}),
-            python_script: Some("result = [{\"name\": \"aptimize_asset1\"}]".to_string()),
-            is_array: true,
-            polling_infos: Some(EntitySharingPollingInfos {
-                polling_interval: 10000,
-            }),
-        })
+        .create_entity_sharing(
+            &CreateEntitySharingParams {
+                id: Some("423f9ce6-acc0-7fe9-9ef6-270b1e7acb78".to_string()),
+                name: "Aptimize asset".to_string(),
+                connected_app_id: aptimize_app.id.clone(),
+                json_schema: json!({}),
+                python_script: Some("result = [{\"name\": \"aptimize_asset1\"}]".to_string()),
+                is_array: true,
+                script_runtime: ScriptRuntimeKind::Python,
+                allowed_modules: vec![],
+                polling_infos: Some(EntitySharingPollingInfos {
+                    schedule: PollingSchedule::Interval {
+                        polling_interval: 10000,
+                        interval_mode: IntervalMode::FixedDelay,
+                    },
+                    input: None,
+                    retry_policy: PollingRetryPolicy::default(),
+                    guardrail: None,
+                }),
+            },
+            &None,
+        )
         .await
         .unwrap();
 
     let arcfm_asset = entity_sharing_core
-        .create_entity_sharing(&CreateEntitySharingParams {
-            id: "423f9ce6-acc0-7a23-a8d4-8d8ab7a1ad39".to_string(),
-            name: "ArcFM asset".to_string(),
-            connected_app_id: arcfm_app.id.clone(),
-            json_schema: json!({}),
-            python_script: Some("result = [{\"name\": \"arcfm_asset1\"}]".to_string()),
-            is_array: true,
-            polling_infos: Some(EntitySharingPollingInfos {
-                polling_interval: 1000,
-            }),
-        })
+        .create_entity_sharing(
+            &CreateEntitySharingParams {
+                id: Some("423f9ce6-acc0-7a23-a8d4-8d8ab7a1ad39".to_string()),
+                name: "ArcFM asset".to_string(),
+                connected_app_id: arcfm_app.id.clone(),
+                json_schema: json!({}),
+                python_script: Some("result = [{\"name\": \"arcfm_asset1\"}]".to_string()),
+                is_array: true,
+                script_runtime: ScriptRuntimeKind::Python,
+                allowed_modules: vec![],
+                polling_infos: Some(EntitySharingPollingInfos {
+                    schedule: PollingSchedule::Interval {
+                        polling_interval: 1000,
+                        interval_mode: IntervalMode::FixedDelay,
+                    },
+                    input: None,
+                    retry_policy: PollingRetryPolicy::default(),
+                    guardrail: None,
+                }),
+            },
+            &None,
+        )
         .await
         .unwrap();
 
     entity_subscription_core
-        .create_entity_subscription(&CreateEntitySubscriptionParams {
-            id: Uuid::new_v7(ts).to_string(),
-            entity_sharing_id: arcfm_asset.id.clone(),
-            connected_app_id: aptimize_app.id.clone(),
-            jdm_transform: None,
-            python_script: None,
-        })
+        .create_entity_subscription(
+            &CreateEntitySubscriptionParams {
+                id: None,
+                entity_sharing_id: arcfm_asset.id.clone(),
+                connected_app_id: aptimize_app.id.clone(),
+                jdm_transform: None,
+                python_script: None,
+                script_runtime: ScriptRuntimeKind::Python,
+                allowed_modules: vec![],
+            },
+            &None,
+        )
         .await
         .unwrap();
     entity_subscription_core
-        .create_entity_subscription(&CreateEntitySubscriptionParams {
-            id: Uuid::new_v7(ts).to_string(),
-            entity_sharing_id: aptimize_asset.id.clone(),
-            connected_app_id: arcfm_app.id.clone(),
-            jdm_transform: None,
-            python_script: None,
-        })
+        .create_entity_subscription(
+            &CreateEntitySubscriptionParams {
+                id: None,
+                entity_sharing_id: aptimize_asset.id.clone(),
+                connected_app_id: arcfm_app.id.clone(),
+                jdm_transform: None,
+                python_script: None,
+                script_runtime: ScriptRuntimeKind::Python,
+                allowed_modules: vec![],
+            },
+            &None,
+        )
         .await
         .unwrap();
 }
 
-async fn init_app() -> (
+async fn init_app(
+    shutdown: &ShutdownCoordinator,
+    config: &Config,
+) -> (
     Arc<ConnectedAppCore<'static>>,
     Arc<EntitySharingCore<'static>>,
     Arc<EntitySubscriptionCore<'static>>,
-    Arc<AtomicBool>,
+    EntityPollingScheduler,
+    EventStream,
+    &'static SqlitePool,
 ) {
     let bus: EventBus<Commands, TopicIds> = EventBus::new();
-    let bus_static = Box::leak(Box::new(bus));
-    let publish = Box::new(|command: Commands, topic_id: Option<TopicIds>| {
-        bus_static.publish(command, topic_id, 0)
-    });
-    let should_stop = Arc::new(AtomicBool::new(false));
-    let should_stop_clone = Arc::clone(&should_stop);
-    ctrlc::set_handler(move || {
-        println!("Received SIGINT/SIGTERM, shutting down...");
-        should_stop_clone.store(true, Ordering::Relaxed);
-    })
-    .expect("Error setting Ctrl-C handler");
-    let pool = Box::leak(Box::new(get_db().await.expect("Failed to create database")));
-
-    let connected_app_repository = Box::new(ConnectedAppSQLiteRepository { pool: pool });
-    let entity_sharing_repository = Box::new(EntitySharingSQLiteRepository { pool: pool });
+    let bus_static: &'static EventBus<Commands, TopicIds> = Box::leak(Box::new(bus));
+    let publish = || -> Publish {
+        Box::new(|command: Commands, topic_id: Option<TopicIds>| {
+            bus_static.publish(command, topic_id, 0)
+        })
+    };
+    let pool = Box::leak(Box::new(get_db(&config.database_url).await.expect("Failed to create database")));
+
+    let connected_app_repository = Box::new(ConnectedAppSQLiteRepository { pool });
+    let entity_sharing_repository = Box::new(EntitySharingSQLiteRepository { pool });
     let entity_subscription_repository =
-        Box::new(EntitySubscriptionSQLiteRepository { pool: pool });
+        Box::new(EntitySubscriptionSQLiteRepository { pool });
 
     let app_core = Arc::new(ConnectedAppCore {
-        connected_app_repository: connected_app_repository,
+        connected_app_repository,
+        connected_app_limiter: ConnectedAppLimiter::default(),
+        publish: publish(),
     });
     let entity_sharing_core = Arc::new(EntitySharingCore::new(
         Arc::clone(&app_core),
         entity_sharing_repository,
-        publish,
+        publish(),
     ));
 
     let entity_subscription_core = Arc::new(EntitySubscriptionCore {
-        entity_subscription_repository: entity_subscription_repository,
+        entity_subscription_repository,
         entity_sharing_core: Arc::clone(&entity_sharing_core),
+        in_flight_deliveries: Mutex::new(HashMap::new()),
+        saved_deliveries: Mutex::new(HashSet::new()),
+        publish: publish(),
     });
 
-    let entity_polling_handler = EntityPollingHandler::new(
+    let entity_polling_scheduler = EntityPollingScheduler::start(
         Arc::clone(&entity_subscription_core),
-        Arc::clone(&should_stop),
+        shutdown.clone(),
+        config,
     );
+    let entity_polling_handler = EntityPollingHandler::new(entity_polling_scheduler.clone());
 
     bus_static.add_subscriber(entity_polling_handler);
+    bus_static.add_subscriber(Alerter::new(
+        config.alert_webhook_url.clone(),
+        shutdown.clone(),
+    ));
+    let event_stream = EventStream::default();
+    bus_static.add_subscriber(event_stream.clone());
 
     (
         app_core,
         entity_sharing_core,
         entity_subscription_core,
-        should_stop,
+        entity_polling_scheduler,
+        event_stream,
+        pool,
     )
 }
 
 async fn run_app() {
-    let (app_core, entity_sharing_core, entity_subscription_core, should_stop) = init_app().await;
+    let config = Config::from_env();
+    let telemetry = init_telemetry(&config);
+    for (name, value) in &config.invalid_variables {
+        error!(
+            variable = %name,
+            ?value,
+            "Invalid value for environment variable, using the default"
+        );
+    }
+    if config.admin_api_key.is_none() {
+        warn!("HEUTL_ADMIN_API_KEY is not set, only connected app keys are accepted");
+    }
+    if let Err(e) = check_python_interpreter() {
+        warn!(error = ?e, "Python scripts can't run, the instance won't be ready");
+    }
+    let shutdown = ShutdownCoordinator::new();
+    shutdown.listen_for_signals();
+    let metrics_handle = init_metrics(&shutdown);
+
+    let (
+        app_core,
+        entity_sharing_core,
+        entity_subscription_core,
+        entity_polling_scheduler,
+        event_stream,
+        pool,
+    ) = init_app(&shutdown, &config).await;
+
+    let replay_entity_subscription_core = Arc::clone(&entity_subscription_core);
+    shutdown.spawn(async move {
+        if let Err(e) = replay_entity_subscription_core
+            .replay_pending_deliveries()
+            .await
+        {
+            error!(error = ?e, "Error replaying pending deliveries");
+        }
+    });
 
     test_scenario(
         Arc::clone(&app_core),
@@ -165,12 +268,47 @@ async fn run_app() {
     )
     .await;
 
-    run_web_api(app_core, entity_sharing_core, entity_subscription_core)
+    let web_api_shutdown = shutdown.clone();
+    let web_app_cores = WebAppCores {
+        app_core,
+        entity_sharing_core: Arc::clone(&entity_sharing_core),
+        entity_subscription_core: Arc::clone(&entity_subscription_core),
+        entity_polling_scheduler,
+        admin_api_key_hash: config.admin_api_key.as_ref().map(|key| hash_api_key(key)),
+        event_stream,
+        shutdown: shutdown.clone(),
+        metrics_handle,
+        pool,
+    };
+    let bind_address = config.bind_address.clone();
+    shutdown.spawn(async move {
+        if let Err(e) = run_web_api(&bind_address, web_app_cores).await {
+            error!(error = ?e, "Failed to run web api");
+            web_api_shutdown.request_shutdown();
+        }
+    });
+
+    shutdown.cancelled().await;
+    if !shutdown.wait(config.shutdown_grace_period).await {
+        warn!("Shutdown grace period elapsed before all polls and deliveries ended");
+    }
+    match entity_subscription_core.save_in_flight_deliveries().await {
+        Ok(0) => {}
+        Ok(count) => info!(count, "Saved pending deliveries"),
+        Err(e) => error!(error = ?e, "Error saving pending deliveries"),
+    }
+    if let Err(e) = entity_sharing_core
+        .release_polling_instance(&config.instance_id)
         .await
-        .expect("Failed to run web api");
+    {
+        error!(error = ?e, "Error releasing polling leases");
+    }
+    telemetry.shutdown();
 }
 
-#[tokio::main]
-async fn main() {
-    run_app().await;
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create tokio runtime");
+    runtime.block_on(run_app());
+    // Scripts still running past the grace period are left behind, their deliveries were saved.
+    runtime.shutdown_background();
 }
@@ -1 +1,8 @@
+pub mod alerts;
+pub mod auth;
+pub mod event_stream;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
+pub mod policy;
 pub mod web_api;
\ No newline at end of file
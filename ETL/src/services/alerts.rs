@@ -0,0 +1,176 @@
+use crate::entity_sharing::entity_sharing_model::HeldDelivery;
+use crate::shared::bus::{Commands, TopicIds};
+use crate::shared::metrics::record_delivery_held;
+use crate::shared::shutdown::ShutdownCoordinator;
+use pubsub_bus::{BusEvent, Subscriber};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::error;
+
+/// How long the webhook gets to take an alert.
+const ALERT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body posted to the alert webhook, its kind in the `alert` field.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    /// A polled list waits for approval, the entity count guardrail kept it from the
+    /// subscriptions. The list itself is left out, it is read from the held deliveries route.
+    DeliveryHeld {
+        entity_sharing_id: String,
+        held_delivery_id: String,
+        run_id: String,
+        entity_count: i64,
+        previous_entity_count: i64,
+        created_at: i64,
+    },
+}
+
+impl From<&HeldDelivery> for Alert {
+    fn from(held_delivery: &HeldDelivery) -> Self {
+        Alert::DeliveryHeld {
+            entity_sharing_id: held_delivery.entity_sharing_id.clone(),
+            held_delivery_id: held_delivery.id.clone(),
+            run_id: held_delivery.run_id.clone(),
+            entity_count: held_delivery.entity_count,
+            previous_entity_count: held_delivery.previous_entity_count,
+            created_at: held_delivery.created_at,
+        }
+    }
+}
+
+/// Raises an alert for the events published on the bus that need someone to act. Alerts are
+/// counted, and posted to the webhook when one is configured.
+#[derive(Clone)]
+pub struct Alerter {
+    webhook_url: Option<String>,
+    client: reqwest::Client,
+    shutdown: ShutdownCoordinator,
+}
+
+impl Alerter {
+    pub fn new(webhook_url: Option<String>, shutdown: ShutdownCoordinator) -> Self {
+        Self {
+            webhook_url,
+            client: reqwest::Client::new(),
+            shutdown,
+        }
+    }
+
+    /// Posts the alert to the webhook, if any.
+    pub async fn send(&self, alert: &Alert) -> Result<(), reqwest::Error> {
+        let Some(webhook_url) = &self.webhook_url else {
+            return Ok(());
+        };
+        self.client
+            .post(webhook_url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(alert).expect("Alerts serialize to JSON"))
+            .timeout(ALERT_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Subscriber<Commands, TopicIds> for Alerter {
+    fn on_event(&mut self, event: &BusEvent<Commands, TopicIds>) {
+        let Commands::DeliveryHeld { held_delivery } = event.get_content() else {
+            return;
+        };
+        record_delivery_held(&held_delivery.entity_sharing_id);
+        let alerter = self.clone();
+        let alert = Alert::from(held_delivery);
+        self.shutdown.spawn(async move {
+            if let Err(e) = alerter.send(&alert).await {
+                error!(error = ?e, ?alert, "Error posting alert to the webhook");
+            }
+        });
+    }
+
+    fn is_subscribed_to(&self, topic_id: &TopicIds) -> bool {
+        matches!(topic_id, TopicIds::DeliveryHeld)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_sharing::entity_sharing_model::HeldDeliveryStatus;
+    use axum::{Router, body::Bytes, extract::State, http::StatusCode, routing::post};
+    use serde_json::{Value, json};
+    use tokio::sync::mpsc;
+
+    fn held_delivery() -> HeldDelivery {
+        HeldDelivery {
+            id: "held-delivery".to_string(),
+            entity_sharing_id: "entity-sharing".to_string(),
+            run_id: "run".to_string(),
+            data: json!([{"name": "asset1"}]),
+            entity_count: 1,
+            previous_entity_count: 10,
+            cursor: Some(json!({"page": 2})),
+            status: HeldDeliveryStatus::Held,
+            created_at: 1760870000,
+            resolved_at: None,
+        }
+    }
+
+    /// Webhook answering with `status`, forwarding the bodies it receives.
+    async fn start_webhook(status: StatusCode) -> (String, mpsc::UnboundedReceiver<Value>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let router = Router::new()
+            .route(
+                "/alerts",
+                post(
+                    async move |State(sender): State<mpsc::UnboundedSender<Value>>, body: Bytes| {
+                        sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                        status
+                    },
+                ),
+            )
+            .with_state(sender);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (format!("http://{}/alerts", address), receiver)
+    }
+
+    #[tokio::test]
+    async fn held_deliveries_are_posted_to_the_webhook() {
+        let (webhook_url, mut alerts) = start_webhook(StatusCode::NO_CONTENT).await;
+        let alerter = Alerter::new(Some(webhook_url), ShutdownCoordinator::new());
+
+        alerter.send(&Alert::from(&held_delivery())).await.unwrap();
+
+        assert_eq!(
+            alerts.recv().await.unwrap(),
+            json!({
+                "alert": "delivery_held",
+                "entity_sharing_id": "entity-sharing",
+                "held_delivery_id": "held-delivery",
+                "run_id": "run",
+                "entity_count": 1,
+                "previous_entity_count": 10,
+                "created_at": 1760870000,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn webhook_failures_are_reported() {
+        let (webhook_url, _alerts) = start_webhook(StatusCode::SERVICE_UNAVAILABLE).await;
+        let alerter = Alerter::new(Some(webhook_url), ShutdownCoordinator::new());
+
+        assert!(alerter.send(&Alert::from(&held_delivery())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn nothing_is_posted_without_a_webhook() {
+        let alerter = Alerter::new(None, ShutdownCoordinator::new());
+
+        alerter.send(&Alert::from(&held_delivery())).await.unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use crate::connected_app::connected_app_core::hash_api_key;
+use crate::connected_app::connected_app_model::Role;
+use crate::services::web_api::WebAppCores;
+use crate::shared::errors::Error;
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+
+/// Who sent a request, resolved from its `Authorization: Bearer <key>` header.
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub role: Role,
+    /// App the key was issued to, `None` for `HEUTL_ADMIN_API_KEY`.
+    pub connected_app_id: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Caller>()
+            .cloned()
+            .ok_or_else(|| Error::UnauthorizedError("Missing API key".to_string()))
+    }
+}
+
+/// Rejects the requests without a valid key and attaches their `Caller` to the others.
+pub async fn auth_middleware(
+    State(web_app_cores): State<WebAppCores>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let key = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|key| key.trim().to_string())
+        .ok_or_else(|| Error::UnauthorizedError("Missing API key".to_string()))?;
+
+    let caller = if web_app_cores.admin_api_key_hash.as_ref() == Some(&hash_api_key(&key)) {
+        Caller {
+            role: Role::Admin,
+            connected_app_id: None,
+        }
+    } else {
+        let api_key = web_app_cores.app_core.authenticate(&key).await?;
+        Caller {
+            role: api_key.role,
+            connected_app_id: Some(api_key.connected_app_id),
+        }
+    };
+    req.extensions_mut().insert(caller);
+    Ok(next.run(req).await)
+}
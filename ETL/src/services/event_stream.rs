@@ -0,0 +1,307 @@
+use crate::services::web_api::WebAppCores;
+use crate::shared::bus::{Commands, TopicIds};
+use crate::shared::errors::{Error, ErrorBody};
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    response::{
+        IntoResponse,
+        sse::{Event as SseEvent, KeepAlive, Sse},
+    },
+};
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use pubsub_bus::{BusEvent, Subscriber};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::error;
+use utoipa::{IntoParams, ToSchema};
+
+/// Events kept for the clients lagging behind, older ones are dropped.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceType {
+    ConnectedApp,
+    ConnectedAppApiKey,
+    EntitySharing,
+    /// Poll cycle of a sharing.
+    EntitySharingRun,
+    EntitySubscription,
+    HeldDelivery,
+    /// Entity list handed to a subscription.
+    Delivery,
+}
+
+impl ResourceType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ResourceType::ConnectedApp => "connected_app",
+            ResourceType::ConnectedAppApiKey => "connected_app_api_key",
+            ResourceType::EntitySharing => "entity_sharing",
+            ResourceType::EntitySharingRun => "entity_sharing_run",
+            ResourceType::EntitySubscription => "entity_subscription",
+            ResourceType::HeldDelivery => "held_delivery",
+            ResourceType::Delivery => "delivery",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventAction {
+    Created,
+    Updated,
+    Deleted,
+    Succeeded,
+    Failed,
+}
+
+impl EventAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventAction::Created => "created",
+            EventAction::Updated => "updated",
+            EventAction::Deleted => "deleted",
+            EventAction::Succeeded => "succeeded",
+            EventAction::Failed => "failed",
+        }
+    }
+}
+
+/// Change streamed on `/events`, sent as a server-sent event named `<resource_type>.<action>`.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Event {
+    pub resource_type: ResourceType,
+    pub action: EventAction,
+    pub resource_id: String,
+    /// Sharing the resource belongs to, if any.
+    pub entity_sharing_id: Option<String>,
+    /// Connected app the resource belongs to, if known.
+    pub connected_app_id: Option<String>,
+    pub occurred_at: i64,
+    /// Resource after the change, `null` once deleted.
+    pub data: Value,
+}
+
+impl Event {
+    fn new(
+        resource_type: ResourceType,
+        action: EventAction,
+        resource_id: &str,
+        entity_sharing_id: Option<&str>,
+        connected_app_id: Option<&str>,
+        data: Value,
+    ) -> Self {
+        Event {
+            resource_type,
+            action,
+            resource_id: resource_id.to_string(),
+            entity_sharing_id: entity_sharing_id.map(str::to_string),
+            connected_app_id: connected_app_id.map(str::to_string),
+            occurred_at: Utc::now().timestamp(),
+            data,
+        }
+    }
+
+    fn from_command(command: &Commands) -> Result<Self, Error> {
+        let event = match command {
+            Commands::ConnectedAppCreated { connected_app }
+            | Commands::ConnectedAppUpdated { connected_app } => Event::new(
+                ResourceType::ConnectedApp,
+                match command {
+                    Commands::ConnectedAppCreated { .. } => EventAction::Created,
+                    _ => EventAction::Updated,
+                },
+                &connected_app.id,
+                None,
+                Some(&connected_app.id),
+                serde_json::to_value(connected_app)?,
+            ),
+            Commands::ConnectedAppApiKeyCreated { api_key } => Event::new(
+                ResourceType::ConnectedAppApiKey,
+                EventAction::Created,
+                &api_key.id,
+                None,
+                Some(&api_key.connected_app_id),
+                serde_json::to_value(api_key)?,
+            ),
+            Commands::ConnectedAppApiKeyRevoked {
+                connected_app_id,
+                api_key_id,
+            } => Event::new(
+                ResourceType::ConnectedAppApiKey,
+                EventAction::Deleted,
+                api_key_id,
+                None,
+                Some(connected_app_id),
+                Value::Null,
+            ),
+            Commands::EntitySharingCreated { entity_sharing }
+            | Commands::EntitySharingUpdated { entity_sharing } => Event::new(
+                ResourceType::EntitySharing,
+                match command {
+                    Commands::EntitySharingCreated { .. } => EventAction::Created,
+                    _ => EventAction::Updated,
+                },
+                &entity_sharing.id,
+                Some(&entity_sharing.id),
+                Some(&entity_sharing.connected_app_id),
+                serde_json::to_value(entity_sharing)?,
+            ),
+            Commands::EntitySharingRunRecorded { run } => Event::new(
+                ResourceType::EntitySharingRun,
+                EventAction::Created,
+                &run.id,
+                Some(&run.entity_sharing_id),
+                None,
+                serde_json::to_value(run)?,
+            ),
+            Commands::EntitySubscriptionCreated {
+                entity_subscription,
+            }
+            | Commands::EntitySubscriptionUpdated {
+                entity_subscription,
+            } => Event::new(
+                ResourceType::EntitySubscription,
+                match command {
+                    Commands::EntitySubscriptionCreated { .. } => EventAction::Created,
+                    _ => EventAction::Updated,
+                },
+                &entity_subscription.id,
+                Some(&entity_subscription.entity_sharing_id),
+                Some(&entity_subscription.connected_app_id),
+                serde_json::to_value(entity_subscription)?,
+            ),
+            Commands::DeliveryHeld { held_delivery }
+            | Commands::HeldDeliveryResolved { held_delivery } => Event::new(
+                ResourceType::HeldDelivery,
+                match command {
+                    Commands::DeliveryHeld { .. } => EventAction::Created,
+                    _ => EventAction::Updated,
+                },
+                &held_delivery.id,
+                Some(&held_delivery.entity_sharing_id),
+                None,
+                serde_json::to_value(held_delivery)?,
+            ),
+            Commands::DeliveryCompleted { delivery } => Event::new(
+                ResourceType::Delivery,
+                match delivery.error {
+                    None => EventAction::Succeeded,
+                    Some(_) => EventAction::Failed,
+                },
+                &delivery.id,
+                Some(&delivery.entity_sharing_id),
+                Some(&delivery.connected_app_id),
+                serde_json::to_value(delivery)?,
+            ),
+        };
+        Ok(event)
+    }
+
+    fn name(&self) -> String {
+        format!("{}.{}", self.resource_type.as_str(), self.action.as_str())
+    }
+}
+
+/// Forwards every command published on the bus to the clients of `/events`.
+#[derive(Clone)]
+pub struct EventStream {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+        Self { sender }
+    }
+}
+
+impl EventStream {
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+impl Subscriber<Commands, TopicIds> for EventStream {
+    fn on_event(&mut self, event: &BusEvent<Commands, TopicIds>) {
+        match Event::from_command(event.get_content()) {
+            // Sending only fails while no client is connected.
+            Ok(event) => _ = self.sender.send(event),
+            Err(e) => error!(error = ?e, "Error streaming event"),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams, Debug, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct EventFilter {
+    pub resource_type: Option<ResourceType>,
+    /// Id of a resource, whose own events are streamed along with those of the resources
+    /// belonging to it, such as the runs and deliveries of a sharing.
+    pub resource_id: Option<String>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &Event) -> bool {
+        if self
+            .resource_type
+            .is_some_and(|resource_type| resource_type != event.resource_type)
+        {
+            return false;
+        }
+        match &self.resource_id {
+            Some(resource_id) => {
+                &event.resource_id == resource_id
+                    || event.entity_sharing_id.as_ref() == Some(resource_id)
+                    || event.connected_app_id.as_ref() == Some(resource_id)
+            }
+            None => true,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(EventFilter),
+    responses(
+        (
+            status = 200,
+            description = "Server-sent events, a `lagged` event tells how many were missed",
+            content_type = "text/event-stream",
+            body = Event,
+        ),
+        (status = 400, description = "Unknown resource type", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub async fn get_events(
+    State(web_app_cores): State<WebAppCores>,
+    Query(filter): Query<EventFilter>,
+) -> Result<impl IntoResponse, Error> {
+    let receiver = web_app_cores.event_stream.subscribe();
+    let events = stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+        loop {
+            let sse_event = match receiver.recv().await {
+                Ok(event) if filter.matches(&event) => {
+                    SseEvent::default().event(event.name()).json_data(&event)
+                }
+                Ok(_) => continue,
+                // Clients missing events are told so and can fetch the resources again.
+                Err(RecvError::Lagged(missed)) => {
+                    Ok(SseEvent::default().event("lagged").data(missed.to_string()))
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((sse_event, (receiver, filter)));
+        }
+    })
+    // Open streams would otherwise hold the graceful shutdown of the server.
+    .take_until(web_app_cores.shutdown.cancelled());
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
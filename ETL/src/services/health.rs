@@ -0,0 +1,127 @@
+use crate::services::web_api::WebAppCores;
+use crate::shared::db::{check_database, check_migrations};
+use crate::shared::errors::Error;
+use crate::shared::python_runner::check_python_interpreter;
+use crate::shared::shutdown::ShutdownCoordinator;
+use axum::{Json, debug_handler, extract::State, http::StatusCode, response::IntoResponse};
+use futures::join;
+use serde::Serialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// Time a check gets before it is reported as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ReadinessCheck {
+    /// `shutdown`, `database`, `migrations`, `python` or `scheduler`.
+    pub name: String,
+    pub ok: bool,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Readiness {
+    /// Whether every check passed.
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+async fn run_check(name: &str, check: impl Future<Output = Result<(), Error>>) -> ReadinessCheck {
+    let started = Instant::now();
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{:?}", e)),
+        Err(_) => Some(format!("Timed out after {:?}", CHECK_TIMEOUT)),
+    };
+    ReadinessCheck {
+        name: name.to_string(),
+        ok: error.is_none(),
+        error,
+        duration_ms: started.elapsed().as_millis() as i64,
+    }
+}
+
+/// Fails once the shutdown started, so that no new traffic is routed to the instance while it
+/// drains.
+fn check_shutdown(shutdown: &ShutdownCoordinator) -> ReadinessCheck {
+    ReadinessCheck {
+        name: "shutdown".to_string(),
+        ok: !shutdown.is_shutting_down(),
+        error: shutdown
+            .is_shutting_down()
+            .then(|| "The instance is shutting down".to_string()),
+        duration_ms: 0,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    security(()),
+    responses(
+        (status = 200, description = "The process is alive", body = String),
+    )
+)]
+#[debug_handler]
+pub async fn get_healthz() -> Result<impl IntoResponse, Error> {
+    Ok((StatusCode::OK, Json("ok")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    security(()),
+    responses(
+        (status = 200, description = "The instance can take traffic", body = Readiness),
+        (status = 503, description = "A check failed or the instance shuts down", body = Readiness),
+    )
+)]
+#[debug_handler]
+pub async fn get_readyz(
+    State(web_app_cores): State<WebAppCores>,
+) -> Result<impl IntoResponse, Error> {
+    let (database, migrations, python, scheduler) = join!(
+        run_check("database", check_database(web_app_cores.pool)),
+        run_check("migrations", check_migrations(web_app_cores.pool)),
+        run_check("python", async {
+            // Starting the interpreter blocks.
+            tokio::task::spawn_blocking(check_python_interpreter)
+                .await
+                .map_err(|e| Error::ScriptError(e.to_string()))?
+        }),
+        run_check(
+            "scheduler",
+            web_app_cores.entity_polling_scheduler.check_alive()
+        ),
+    );
+    let shutdown = check_shutdown(&web_app_cores.shutdown);
+    let checks = vec![shutdown, database, migrations, python, scheduler];
+    let ready = checks.iter().all(|check| check.ok);
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok((status, Json(Readiness { ready, checks })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_shutting_down_are_not_ready() {
+        let shutdown = ShutdownCoordinator::new();
+        assert!(check_shutdown(&shutdown).ok);
+
+        shutdown.request_shutdown();
+
+        let check = check_shutdown(&shutdown);
+        assert!(!check.ok);
+        assert!(check.error.is_some());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::services::web_api::WebAppCores;
+use crate::shared::errors::Error;
+use crate::shared::metrics::record_http_request;
+use axum::{
+    debug_handler,
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Instant;
+
+/// Counts and times every request by the route it matched.
+pub async fn http_metrics_middleware(req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let response = next.run(req).await;
+    record_http_request(method, route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    security(()),
+    responses(
+        (
+            status = 200,
+            description = "Metrics in the Prometheus text format",
+            content_type = "text/plain",
+            body = String,
+        ),
+    )
+)]
+#[debug_handler]
+pub async fn get_metrics(
+    State(web_app_cores): State<WebAppCores>,
+) -> Result<impl IntoResponse, Error> {
+    Ok((
+        StatusCode::OK,
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        web_app_cores.metrics_handle.render(),
+    ))
+}
@@ -0,0 +1,106 @@
+use crate::connected_app::connected_app_web_api::{
+    __path_create_connected_app, __path_create_connected_app_api_key, __path_get_connected_app,
+    __path_get_connected_app_api_keys, __path_get_connected_app_usages, __path_get_connected_apps,
+    __path_revoke_connected_app_api_key, __path_update_connected_app,
+};
+use crate::entity_sharing::entity_sharing_web_api::{
+    __path_approve_held_delivery, __path_cancel_entity_sharing_poller,
+    __path_create_entity_sharing, __path_get_entity_sharing, __path_get_entity_sharing_poller,
+    __path_get_entity_sharing_pollers, __path_get_entity_sharing_revision_diff,
+    __path_get_entity_sharing_revisions, __path_get_entity_sharing_run,
+    __path_get_entity_sharing_run_stats, __path_get_entity_sharing_runs,
+    __path_get_entity_sharings, __path_get_held_deliveries, __path_notify_new_entity_list,
+    __path_pause_entity_sharing_poller, __path_reject_held_delivery,
+    __path_resume_entity_sharing_poller, __path_rollback_entity_sharing,
+    __path_test_entity_sharing, __path_test_entity_sharing_draft,
+    __path_trigger_entity_sharing_poll, __path_update_entity_sharing,
+};
+use crate::entity_subscription::entity_subscription_web_api::{
+    __path_create_entity_subscription, __path_get_entity_subscription,
+    __path_get_entity_subscription_revision_diff, __path_get_entity_subscription_revisions,
+    __path_get_entity_subscriptions, __path_rollback_entity_subscription,
+    __path_update_entity_subscription,
+};
+use crate::services::event_stream::__path_get_events;
+use crate::services::health::{__path_get_healthz, __path_get_readyz};
+use crate::services::metrics::__path_get_metrics;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// Declares the `Authorization: Bearer <key>` scheme the routes require.
+struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// OpenAPI document of the web API, served at `/openapi.json` and browsable at `/docs`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "HEUTL",
+        description = "Shares entity lists polled from connected apps with their subscribers."
+    ),
+    paths(
+        get_connected_apps,
+        get_connected_app,
+        create_connected_app,
+        update_connected_app,
+        get_connected_app_usages,
+        get_connected_app_api_keys,
+        create_connected_app_api_key,
+        revoke_connected_app_api_key,
+        get_entity_sharings,
+        get_entity_sharing,
+        create_entity_sharing,
+        update_entity_sharing,
+        notify_new_entity_list,
+        test_entity_sharing,
+        test_entity_sharing_draft,
+        get_entity_sharing_revisions,
+        get_entity_sharing_revision_diff,
+        rollback_entity_sharing,
+        get_entity_sharing_pollers,
+        get_entity_sharing_poller,
+        pause_entity_sharing_poller,
+        resume_entity_sharing_poller,
+        cancel_entity_sharing_poller,
+        trigger_entity_sharing_poll,
+        get_entity_sharing_runs,
+        get_entity_sharing_run,
+        get_entity_sharing_run_stats,
+        get_held_deliveries,
+        approve_held_delivery,
+        reject_held_delivery,
+        get_entity_subscriptions,
+        get_entity_subscription,
+        create_entity_subscription,
+        update_entity_subscription,
+        get_entity_subscription_revisions,
+        get_entity_subscription_revision_diff,
+        rollback_entity_subscription,
+        get_events,
+        get_metrics,
+        get_healthz,
+        get_readyz,
+    ),
+    modifiers(&ApiKeySecurity),
+    security(("api_key" = [])),
+    tags(
+        (name = "connected-apps", description = "Apps the entities are polled from"),
+        (name = "entity-sharings", description = "Entity lists shared by connected apps"),
+        (name = "pollers", description = "Pollers of the entity sharings on this instance"),
+        (name = "runs", description = "History of the poll cycles"),
+        (name = "held-deliveries", description = "Entity lists held by the guardrail"),
+        (name = "entity-subscriptions", description = "Subscribers of the entity sharings"),
+        (name = "events", description = "Live stream of the changes"),
+        (name = "operations", description = "Monitoring of the instance, reachable without a key"),
+    )
+)]
+pub struct ApiDoc;
@@ -0,0 +1,253 @@
+use crate::connected_app::connected_app_model::Role;
+use crate::entity_sharing::entity_sharing_model::EntitySharing;
+use crate::entity_subscription::entity_subscription_model::EntitySubscription;
+use crate::services::auth::Caller;
+use crate::services::web_api::WebAppCores;
+use crate::shared::errors::Error;
+use crate::shared::script_runtime::ScriptRuntimeKind;
+
+// Checks the handlers make before calling the cores. Every role reads everything, admins change
+// everything, owners change what belongs to their connected app and auditors change nothing.
+// Owners manage only the owner keys of their app and can't loosen the sandbox of the scripts.
+
+fn auditor_denied(operation: &str) -> Error {
+    Error::ForbiddenError(format!("Auditors can't {}", operation))
+}
+
+pub fn require_admin(caller: &Caller, operation: &str) -> Result<(), Error> {
+    match caller.role {
+        Role::Admin => Ok(()),
+        Role::Auditor => Err(auditor_denied(operation)),
+        Role::Owner => Err(Error::ForbiddenError(format!(
+            "Only admins can {}",
+            operation
+        ))),
+    }
+}
+
+/// Lets through admins and owners, for changes not tied to a connected app.
+pub fn require_not_auditor(caller: &Caller, operation: &str) -> Result<(), Error> {
+    match caller.role {
+        Role::Auditor => Err(auditor_denied(operation)),
+        Role::Admin | Role::Owner => Ok(()),
+    }
+}
+
+/// Lets through admins and the owners of the connected app.
+pub fn require_connected_app_owner(
+    caller: &Caller,
+    connected_app_id: &str,
+    operation: &str,
+) -> Result<(), Error> {
+    match caller.role {
+        Role::Admin => Ok(()),
+        Role::Auditor => Err(auditor_denied(operation)),
+        Role::Owner if caller.connected_app_id.as_deref() == Some(connected_app_id) => Ok(()),
+        Role::Owner => Err(Error::ForbiddenError(format!(
+            "Only admins and owners of connected app {} can {}",
+            connected_app_id, operation
+        ))),
+    }
+}
+
+/// Lets through admins, auditors and the owners of the connected app.
+pub fn require_connected_app_reader(
+    caller: &Caller,
+    connected_app_id: &str,
+    operation: &str,
+) -> Result<(), Error> {
+    match caller.role {
+        Role::Auditor => Ok(()),
+        Role::Admin | Role::Owner => {
+            require_connected_app_owner(caller, connected_app_id, operation)
+        }
+    }
+}
+
+/// Lets through the owners of the connected app only, no one may act on behalf of an app.
+pub fn require_connected_app(
+    caller: &Caller,
+    connected_app_id: &str,
+    operation: &str,
+) -> Result<(), Error> {
+    if caller.role == Role::Owner && caller.connected_app_id.as_deref() == Some(connected_app_id) {
+        return Ok(());
+    }
+    Err(Error::ForbiddenError(format!(
+        "Only connected app {} can {}",
+        connected_app_id, operation
+    )))
+}
+
+/// Lets through admins for keys of any role and, for owner keys, the owners of the connected app.
+pub fn require_api_key_manager(
+    caller: &Caller,
+    connected_app_id: &str,
+    api_key_role: Role,
+    operation: &str,
+) -> Result<(), Error> {
+    require_connected_app_owner(caller, connected_app_id, operation)?;
+    if api_key_role != Role::Owner {
+        require_admin(caller, operation)?;
+    }
+    Ok(())
+}
+
+/// Lets through admins, and the others as long as the runtime and the allowed modules of the
+/// script stay as they are: an allowed module such as `os` runs anything on the host. `None`
+/// leaves the setting unchanged.
+pub fn require_script_sandbox_unchanged(
+    caller: &Caller,
+    (current_script_runtime, current_allowed_modules): (ScriptRuntimeKind, &[String]),
+    (script_runtime, allowed_modules): (Option<ScriptRuntimeKind>, Option<&[String]>),
+) -> Result<(), Error> {
+    let runtime_changed = script_runtime.is_some_and(|kind| kind != current_script_runtime);
+    let modules_changed = allowed_modules.is_some_and(|modules| modules != current_allowed_modules);
+    if runtime_changed || modules_changed {
+        require_admin(caller, "change the script runtime or the allowed modules")?;
+    }
+    Ok(())
+}
+
+pub async fn require_entity_sharing_owner(
+    caller: &Caller,
+    web_app_cores: &WebAppCores,
+    entity_sharing_id: &str,
+    operation: &str,
+) -> Result<EntitySharing, Error> {
+    let entity_sharing = web_app_cores
+        .entity_sharing_core
+        .get_entity_sharing(entity_sharing_id)
+        .await?;
+    require_connected_app_owner(caller, &entity_sharing.connected_app_id, operation)?;
+    Ok(entity_sharing)
+}
+
+pub async fn require_entity_subscription_owner(
+    caller: &Caller,
+    web_app_cores: &WebAppCores,
+    entity_subscription_id: &str,
+    operation: &str,
+) -> Result<EntitySubscription, Error> {
+    let entity_subscription = web_app_cores
+        .entity_subscription_core
+        .get_entity_subscription(entity_subscription_id)
+        .await?;
+    require_connected_app_owner(caller, &entity_subscription.connected_app_id, operation)?;
+    Ok(entity_subscription)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP: &str = "app";
+    const OTHER_APP: &str = "other-app";
+
+    fn admin() -> Caller {
+        Caller {
+            role: Role::Admin,
+            connected_app_id: None,
+        }
+    }
+
+    fn caller(role: Role, connected_app_id: &str) -> Caller {
+        Caller {
+            role,
+            connected_app_id: Some(connected_app_id.to_string()),
+        }
+    }
+
+    /// Callers of each role, of the app and of another app, with whether they pass.
+    fn matrix(expected: [bool; 6], check: impl Fn(&Caller) -> Result<(), Error>) {
+        let callers = [
+            admin(),
+            caller(Role::Admin, OTHER_APP),
+            caller(Role::Owner, APP),
+            caller(Role::Owner, OTHER_APP),
+            caller(Role::Auditor, APP),
+            caller(Role::Auditor, OTHER_APP),
+        ];
+        for (caller, expected) in callers.iter().zip(expected) {
+            let result = check(caller);
+            assert_eq!(result.is_ok(), expected, "{:?}: {:?}", caller, result);
+            if let Err(e) = result {
+                assert!(matches!(e, Error::ForbiddenError(_)), "{:?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn admin_is_required() {
+        matrix([true, true, false, false, false, false], |caller| {
+            require_admin(caller, "test")
+        });
+    }
+
+    #[test]
+    fn auditors_are_denied_changes() {
+        matrix([true, true, true, true, false, false], |caller| {
+            require_not_auditor(caller, "test")
+        });
+    }
+
+    #[test]
+    fn owners_change_their_own_app() {
+        matrix([true, true, true, false, false, false], |caller| {
+            require_connected_app_owner(caller, APP, "test")
+        });
+    }
+
+    #[test]
+    fn everyone_but_other_owners_reads_an_app() {
+        matrix([true, true, true, false, true, true], |caller| {
+            require_connected_app_reader(caller, APP, "test")
+        });
+    }
+
+    #[test]
+    fn only_the_app_acts_on_its_behalf() {
+        matrix([false, false, true, false, false, false], |caller| {
+            require_connected_app(caller, APP, "test")
+        });
+    }
+
+    #[test]
+    fn owners_manage_owner_keys_only() {
+        matrix([true, true, true, false, false, false], |caller| {
+            require_api_key_manager(caller, APP, Role::Owner, "test")
+        });
+        for role in [Role::Admin, Role::Auditor] {
+            matrix([true, true, false, false, false, false], |caller| {
+                require_api_key_manager(caller, APP, role, "test")
+            });
+        }
+    }
+
+    #[test]
+    fn only_admins_loosen_the_script_sandbox() {
+        let modules = vec!["math".to_string()];
+        let current = (ScriptRuntimeKind::Python, modules.as_slice());
+        let more_modules = vec!["math".to_string(), "os".to_string()];
+
+        matrix([true; 6], |caller| {
+            require_script_sandbox_unchanged(caller, current, (None, None))
+        });
+        matrix([true; 6], |caller| {
+            require_script_sandbox_unchanged(
+                caller,
+                current,
+                (Some(ScriptRuntimeKind::Python), Some(&modules)),
+            )
+        });
+        matrix([true, true, false, false, false, false], |caller| {
+            require_script_sandbox_unchanged(caller, current, (None, Some(&more_modules)))
+        });
+        matrix([true, true, false, false, false, false], |caller| {
+            require_script_sandbox_unchanged(caller, current, (None, Some(&[])))
+        });
+        matrix([true, true, false, false, false, false], |caller| {
+            require_script_sandbox_unchanged(caller, current, (Some(ScriptRuntimeKind::Wasm), None))
+        });
+    }
+}
@@ -1,30 +1,95 @@
 use crate::connected_app::connected_app_core::ConnectedAppCore;
-use crate::connected_app::connected_app_web_api::{create_connected_app, get_connected_apps};
+use crate::connected_app::connected_app_web_api::{
+    create_connected_app, create_connected_app_api_key, get_connected_app,
+    get_connected_app_api_keys, get_connected_app_usages, get_connected_apps,
+    revoke_connected_app_api_key, update_connected_app,
+};
+use crate::entity_sharing::entity_polling_scheduler::EntityPollingScheduler;
 use crate::entity_sharing::entity_sharing_core::EntitySharingCore;
 use crate::entity_sharing::entity_sharing_web_api::{
-    create_entity_sharing, get_entity_sharings, notify_new_entity_list, update_entity_sharing,  
+    approve_held_delivery, cancel_entity_sharing_poller, create_entity_sharing, get_entity_sharing,
+    get_entity_sharing_poller, get_entity_sharing_pollers, get_entity_sharing_revision_diff,
+    get_entity_sharing_revisions, get_entity_sharing_run, get_entity_sharing_run_stats,
+    get_entity_sharing_runs, get_entity_sharings, get_held_deliveries, notify_new_entity_list,
+    pause_entity_sharing_poller, reject_held_delivery, resume_entity_sharing_poller,
+    rollback_entity_sharing, test_entity_sharing, test_entity_sharing_draft,
+    trigger_entity_sharing_poll, update_entity_sharing,
 };
 use crate::entity_subscription::entity_subscription_core::EntitySubscriptionCore;
 use crate::entity_subscription::entity_subscription_web_api::{
-    create_entity_subscription, get_entity_subscriptions,
+    create_entity_subscription, get_entity_subscription, get_entity_subscription_revision_diff,
+    get_entity_subscription_revisions, get_entity_subscriptions, rollback_entity_subscription,
+    update_entity_subscription,
 };
+use crate::services::auth::auth_middleware;
+use crate::services::event_stream::{EventStream, get_events};
+use crate::services::health::{get_healthz, get_readyz};
+use crate::services::metrics::{get_metrics, http_metrics_middleware};
+use crate::services::openapi::ApiDoc;
+use crate::shared::shutdown::ShutdownCoordinator;
 use axum::{
     Router,
-    extract::Request,
-    middleware::{self, Next},
-    response::Response,
-    routing::{get, post, put},
+    extract::{FromRequestParts, Request},
+    http::request::Parts,
+    middleware,
+    routing::{delete, get, post, put},
 };
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::convert::Infallible;
 use std::io::Error;
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tokio::signal;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{Level, Span, info_span};
+use utoipa::{IntoParams, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+/// Span of an HTTP request, the parent of the script runs and deliveries it triggers.
+fn make_request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    info_span!(
+        "http_request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+    )
+}
+
+/// Author of a change, taken from the `X-Author` header and stored on revisions.
+pub struct Author(pub Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for Author {
+    type Rejection = Infallible;
 
-async fn logging_middleware(req: Request, next: Next) -> Response {
-    println!("Request {:?}::{:?}", req.method(), req.uri());
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let author = parts
+            .headers
+            .get("x-author")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        Ok(Author(author))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RevisionDiffQuery {
+    /// Revision to compare with, defaults to the previous one.
+    pub against: Option<i64>,
+}
 
-    let response = next.run(req).await;
-    return response;
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RunsQuery {
+    /// Number of runs returned, most recent first. Defaults to 100.
+    pub limit: Option<i64>,
 }
 
 #[derive(Clone)]
@@ -32,39 +97,20 @@ pub struct WebAppCores {
     pub app_core: Arc<ConnectedAppCore<'static>>,
     pub entity_sharing_core: Arc<EntitySharingCore<'static>>,
     pub entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
+    pub entity_polling_scheduler: EntityPollingScheduler,
+    /// Hash of `HEUTL_ADMIN_API_KEY`, see `hash_api_key`.
+    pub admin_api_key_hash: Option<String>,
+    pub event_stream: EventStream,
+    pub shutdown: ShutdownCoordinator,
+    pub metrics_handle: PrometheusHandle,
+    pub pool: &'static SqlitePool,
 }
 
-async fn shutdown_signal() {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
-
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
-
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
-
-    tokio::select! {
-        _ = ctrl_c => {},
-        _ = terminate => {},
-    }
-}
-
-pub async fn run_web_api(
-    app_core: Arc<ConnectedAppCore<'static>>,
-    entity_sharing_core: Arc<EntitySharingCore<'static>>,
-    entity_subscription_core: Arc<EntitySubscriptionCore<'static>>,
-) -> Result<(), Error> {
+pub async fn run_web_api(bind_address: &str, web_app_cores: WebAppCores) -> Result<(), Error> {
+    let shutdown = web_app_cores.shutdown.clone();
     let app = Router::new()
         .route("/connected-apps", get(get_connected_apps))
+        .route("/connected-app-usages", get(get_connected_app_usages))
         .route("/entity-sharings", get(get_entity_sharings))
         .route(
             "/entity-sharings/{entity_sharing_id}/subscriptions",
@@ -72,19 +118,136 @@ pub async fn run_web_api(
         )
         .route("/entity/{entity_sharing_id}", post(notify_new_entity_list))
         .route("/entity-sharings", post(create_entity_sharing))
+        .route("/entity-sharings/{entity_sharing_id}", get(get_entity_sharing))
         .route("/entity-sharings/{entity_sharing_id}", put(update_entity_sharing))
+        .route("/entity-sharings/test", post(test_entity_sharing_draft))
+        .route(
+            "/entity-sharings/{entity_sharing_id}/test",
+            post(test_entity_sharing),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/revisions",
+            get(get_entity_sharing_revisions),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/revisions/{revision}/diff",
+            get(get_entity_sharing_revision_diff),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/revisions/{revision}/rollback",
+            post(rollback_entity_sharing),
+        )
+        .route("/entity-sharing-pollers", get(get_entity_sharing_pollers))
+        .route(
+            "/entity-sharings/{entity_sharing_id}/poller",
+            get(get_entity_sharing_poller),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/poller/pause",
+            post(pause_entity_sharing_poller),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/poller/resume",
+            post(resume_entity_sharing_poller),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/poller/cancel",
+            post(cancel_entity_sharing_poller),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/poll",
+            post(trigger_entity_sharing_poll),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/runs",
+            get(get_entity_sharing_runs),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/runs/{run_id}",
+            get(get_entity_sharing_run),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/runs/stats",
+            get(get_entity_sharing_run_stats),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/held-deliveries",
+            get(get_held_deliveries),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/held-deliveries/{held_delivery_id}/approve",
+            post(approve_held_delivery),
+        )
+        .route(
+            "/entity-sharings/{entity_sharing_id}/held-deliveries/{held_delivery_id}/reject",
+            post(reject_held_delivery),
+        )
         .route("/entity-subscriptions", post(create_entity_subscription))
+        .route(
+            "/entity-subscriptions/{entity_subscription_id}",
+            get(get_entity_subscription),
+        )
+        .route(
+            "/entity-subscriptions/{entity_subscription_id}",
+            put(update_entity_subscription),
+        )
+        .route(
+            "/entity-subscriptions/{entity_subscription_id}/revisions",
+            get(get_entity_subscription_revisions),
+        )
+        .route(
+            "/entity-subscriptions/{entity_subscription_id}/revisions/{revision}/diff",
+            get(get_entity_subscription_revision_diff),
+        )
+        .route(
+            "/entity-subscriptions/{entity_subscription_id}/revisions/{revision}/rollback",
+            post(rollback_entity_subscription),
+        )
         .route("/connected-apps", post(create_connected_app))
-        .layer(middleware::from_fn(logging_middleware))
-        .with_state(WebAppCores {
-            app_core,
-            entity_sharing_core,
-            entity_subscription_core,
-        });
+        .route(
+            "/connected-apps/{connected_app_id}",
+            get(get_connected_app),
+        )
+        .route(
+            "/connected-apps/{connected_app_id}",
+            put(update_connected_app),
+        )
+        .route(
+            "/connected-apps/{connected_app_id}/api-keys",
+            get(get_connected_app_api_keys),
+        )
+        .route(
+            "/connected-apps/{connected_app_id}/api-keys",
+            post(create_connected_app_api_key),
+        )
+        .route(
+            "/connected-apps/{connected_app_id}/api-keys/{api_key_id}",
+            delete(revoke_connected_app_api_key),
+        )
+        .route("/events", get(get_events))
+        .route_layer(middleware::from_fn_with_state(
+            web_app_cores.clone(),
+            auth_middleware,
+        ))
+        // The docs, the metrics and the probes stay reachable without a key.
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+        .layer(middleware::from_fn(http_metrics_middleware))
+        // Requests get an `X-Request-Id`, unless the caller sent one, echoed in the response.
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(web_app_cores);
 
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
+    let listener = TcpListener::bind(bind_address).await?;
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(shutdown.cancelled())
         .await?;
     Ok(())
 }
@@ -1,6 +1,15 @@
 pub mod bus;
+pub mod config;
 pub mod db;
 pub mod errors;
 pub mod rule_engine;
+pub mod shutdown;
+pub mod telemetry;
 pub mod python_runner;
-pub mod merge_struct;
\ No newline at end of file
+pub mod script_runtime;
+pub mod wasm_runner;
+pub mod merge_struct;
+pub mod json_schema_validator;
+pub mod json_diff;
+pub mod metrics;
+pub mod pagination;
@@ -1,13 +1,38 @@
-use crate::entity_sharing::entity_sharing_model::EntitySharing;
+use crate::connected_app::connected_app_model::{ConnectedApp, ConnectedAppApiKey};
+use crate::entity_sharing::entity_sharing_model::{EntitySharing, EntitySharingRun, HeldDelivery};
+use crate::entity_subscription::entity_subscription_model::{DeliveryResult, EntitySubscription};
 
 #[derive(Debug)]
 pub enum Commands {
+    ConnectedAppCreated { connected_app: ConnectedApp },
+    ConnectedAppUpdated { connected_app: ConnectedApp },
+    ConnectedAppApiKeyCreated { api_key: ConnectedAppApiKey },
+    ConnectedAppApiKeyRevoked { connected_app_id: String, api_key_id: String },
     EntitySharingCreated { entity_sharing: EntitySharing },
     EntitySharingUpdated { entity_sharing: EntitySharing },
+    EntitySharingRunRecorded { run: EntitySharingRun },
+    EntitySubscriptionCreated { entity_subscription: EntitySubscription },
+    EntitySubscriptionUpdated { entity_subscription: EntitySubscription },
+    DeliveryHeld { held_delivery: HeldDelivery },
+    HeldDeliveryResolved { held_delivery: HeldDelivery },
+    DeliveryCompleted { delivery: DeliveryResult },
 }
 
 #[derive(PartialEq, Clone)]
 pub enum TopicIds {
+    ConnectedAppCreated,
+    ConnectedAppUpdated,
+    ConnectedAppApiKeyCreated,
+    ConnectedAppApiKeyRevoked,
     EntitySharingCreated,
     EntitySharingUpdated,
+    EntitySharingRunRecorded,
+    EntitySubscriptionCreated,
+    EntitySubscriptionUpdated,
+    DeliveryHeld,
+    HeldDeliveryResolved,
+    DeliveryCompleted,
 }
+
+/// Publishes a command on the bus, given to the cores.
+pub type Publish = Box<dyn Fn(Commands, Option<TopicIds>) + Send + Sync>;
@@ -0,0 +1,119 @@
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Settings read from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long running polls and deliveries get to finish once shutdown is requested, from
+    /// `HEUTL_SHUTDOWN_GRACE_PERIOD_MS`.
+    pub shutdown_grace_period: Duration,
+    /// sqlx SQLite URL from `HEUTL_DATABASE_URL`. Instances sharing the polling work must point
+    /// to the same database file.
+    pub database_url: String,
+    /// Address the web API listens on, from `HEUTL_BIND_ADDRESS`.
+    pub bind_address: String,
+    /// Name of this instance in the polling leases, from `HEUTL_INSTANCE_ID`. Defaults to a new
+    /// id on every start.
+    pub instance_id: String,
+    /// How long a sharing stays leased to an instance that stopped heartbeating, from
+    /// `HEUTL_POLLER_LEASE_DURATION_MS`. Leases are renewed three times per duration.
+    pub poller_lease_duration: Duration,
+    /// Key from `HEUTL_ADMIN_API_KEY` granting access to every route, used to register the
+    /// connected apps and issue their keys. Without it, only connected app keys are accepted.
+    pub admin_api_key: Option<String>,
+    /// Format of the logs written to stdout, `text` or `json`, from `HEUTL_LOG_FORMAT`. Levels
+    /// are filtered with `RUST_LOG`.
+    pub log_format: LogFormat,
+    /// OTLP gRPC endpoint spans are exported to, from `HEUTL_OTLP_ENDPOINT`. Spans are only
+    /// logged when unset.
+    pub otlp_endpoint: Option<String>,
+    /// URL alerts, such as a delivery held by the guardrail, are posted to as JSON, from
+    /// `HEUTL_ALERT_WEBHOOK_URL`. Alerts are only logged and counted when unset.
+    pub alert_webhook_url: Option<String>,
+    /// Variables whose value couldn't be parsed, with that value, the default being used instead.
+    /// They are read before the logs are set up, so they are logged by the caller afterwards.
+    pub invalid_variables: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", value)),
+        }
+    }
+}
+
+fn get_env_or<T: FromStr>(
+    name: &str,
+    default: T,
+    invalid_variables: &mut Vec<(String, String)>,
+) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            invalid_variables.push((name.to_string(), value));
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let mut invalid_variables = vec![];
+        Self {
+            shutdown_grace_period: Duration::from_millis(get_env_or(
+                "HEUTL_SHUTDOWN_GRACE_PERIOD_MS",
+                30000,
+                &mut invalid_variables,
+            )),
+            database_url: get_env_or(
+                "HEUTL_DATABASE_URL",
+                "sqlite:file:in-memory-db?mode=memory&cache=shared".to_string(),
+                &mut invalid_variables,
+            ),
+            bind_address: get_env_or(
+                "HEUTL_BIND_ADDRESS",
+                "127.0.0.1:8080".to_string(),
+                &mut invalid_variables,
+            ),
+            instance_id: get_env_or(
+                "HEUTL_INSTANCE_ID",
+                Uuid::now_v7().to_string(),
+                &mut invalid_variables,
+            ),
+            poller_lease_duration: Duration::from_millis(get_env_or(
+                "HEUTL_POLLER_LEASE_DURATION_MS",
+                30000,
+                &mut invalid_variables,
+            )),
+            admin_api_key: env::var("HEUTL_ADMIN_API_KEY")
+                .ok()
+                .filter(|admin_api_key| !admin_api_key.is_empty()),
+            log_format: get_env_or(
+                "HEUTL_LOG_FORMAT",
+                LogFormat::default(),
+                &mut invalid_variables,
+            ),
+            otlp_endpoint: env::var("HEUTL_OTLP_ENDPOINT")
+                .ok()
+                .filter(|otlp_endpoint| !otlp_endpoint.is_empty()),
+            alert_webhook_url: env::var("HEUTL_ALERT_WEBHOOK_URL")
+                .ok()
+                .filter(|alert_webhook_url| !alert_webhook_url.is_empty()),
+            invalid_variables,
+        }
+    }
+}
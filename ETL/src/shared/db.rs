@@ -1,11 +1,59 @@
 use crate::shared::errors::Error;
-use sqlx::sqlite::SqlitePool;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool};
+use std::str::FromStr;
 
-pub async fn get_db() -> Result<SqlitePool, Error> {
-    let pool = SqlitePool::connect("sqlite:file:in-memory-db?mode=memory&cache=shared").await?;
-    sqlx::migrate!("./src/shared/migrations")
+static MIGRATOR: Migrator = sqlx::migrate!("./src/shared/migrations");
+
+pub async fn get_db(database_url: &str) -> Result<SqlitePool, Error> {
+    let mut options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+    // WAL lets an instance read while another one writes to the same database file.
+    if !database_url.contains("mode=memory") && !database_url.contains(":memory:") {
+        options = options.journal_mode(SqliteJournalMode::Wal);
+    }
+    let pool = SqlitePool::connect_with(options).await?;
+    MIGRATOR
         .run(&pool)
         .await
         .map_err(|e| Error::DatabaseError(e.to_string()))?;
     Ok(pool)
 }
+
+/// Checks the database still answers queries.
+pub async fn check_database(pool: &SqlitePool) -> Result<(), Error> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+/// Checks every migration of this build was applied, which another instance running an older
+/// build against the same database doesn't guarantee.
+pub async fn check_migrations(pool: &SqlitePool) -> Result<(), Error> {
+    let applied_migrations: Vec<(i64, bool)> =
+        sqlx::query_as("SELECT version, success FROM _sqlx_migrations")
+            .fetch_all(pool)
+            .await?;
+    for migration in MIGRATOR.iter() {
+        if migration.migration_type.is_down_migration() {
+            continue;
+        }
+        let applied = applied_migrations
+            .iter()
+            .find(|(version, _)| *version == migration.version);
+        match applied {
+            Some((_, true)) => {}
+            Some((_, false)) => {
+                return Err(Error::DatabaseError(format!(
+                    "Migration {} failed",
+                    migration.version
+                )));
+            }
+            None => {
+                return Err(Error::DatabaseError(format!(
+                    "Migration {} is not applied",
+                    migration.version
+                )));
+            }
+        }
+    }
+    Ok(())
+}
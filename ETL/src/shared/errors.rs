@@ -1,18 +1,111 @@
-use sqlx::Error as SQLXError;
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
 use serde_json::Error as SerializeError;
+use sqlx::Error as SQLXError;
+use utoipa::ToSchema;
 use zen_engine::EvaluationError as ZenEngineError;
 
+/// Body of every error response.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    /// Problems found in the fields of the request, each one reported separately.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Collects the problems found in the fields of a request, to report them all at once.
+#[derive(Debug, Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &str, message: String) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            message,
+        });
+    }
+
+    /// Records the error of a check of the field. Errors not caused by the request, such as a
+    /// database failure, are returned instead.
+    pub fn check(&mut self, field: &str, result: Result<(), Error>) -> Result<(), Error> {
+        match result {
+            Ok(()) => {}
+            Err(
+                Error::BadRequestError(message)
+                | Error::JsonSchemaError(message)
+                | Error::ScriptValidationError(message),
+            ) => self.add(field, message),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Records that the field refers to a resource that doesn't exist.
+    pub fn check_reference<T>(
+        &mut self,
+        field: &str,
+        result: Result<T, Error>,
+        message: String,
+    ) -> Result<(), Error> {
+        match result {
+            Ok(_) => {}
+            Err(Error::NotFoundError(_)) => self.add(field, message),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Records an empty id, ids left out are generated instead.
+    pub fn check_id(&mut self, id: &Option<String>) {
+        if id.as_ref().is_some_and(|id| id.trim().is_empty()) {
+            self.add("id", "Can't be empty".to_string());
+        }
+    }
+
+    pub fn result(self) -> Result<(), Error> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        Err(Error::ValidationError(self.0))
+    }
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     DatabaseError(String),
     JsonError(String),
     NotFoundError(String),
     RuleEngineError(String),
+    ScriptError(String),
+    JsonSchemaError(String),
+    BadRequestError(String),
+    ScriptValidationError(String),
+    SchedulerError(String),
+    ConflictError(String),
+    UnauthorizedError(String),
+    ForbiddenError(String),
+    ValidationError(Vec<FieldError>),
 }
 
 impl From<SQLXError> for Error {
     fn from(error: SQLXError) -> Self {
-        Error::DatabaseError(error.to_string())
+        match error {
+            SQLXError::RowNotFound => Error::NotFoundError(error.to_string()),
+            SQLXError::Database(ref e) if e.is_unique_violation() => {
+                Error::ConflictError(error.to_string())
+            }
+            _ => Error::DatabaseError(error.to_string()),
+        }
     }
 }
 
@@ -26,4 +119,38 @@ impl From<ZenEngineError> for Error {
     fn from(error: ZenEngineError) -> Self {
         Error::RuleEngineError(error.to_string())
     }
-}
\ No newline at end of file
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let mut fields = vec![];
+        let (status, message) = match self {
+            Error::NotFoundError(message) => (StatusCode::NOT_FOUND, message),
+            Error::ConflictError(message) => (StatusCode::CONFLICT, message),
+            Error::UnauthorizedError(message) => (StatusCode::UNAUTHORIZED, message),
+            Error::ForbiddenError(message) => (StatusCode::FORBIDDEN, message),
+            Error::JsonError(message) | Error::BadRequestError(message) => {
+                (StatusCode::BAD_REQUEST, message)
+            }
+            Error::JsonSchemaError(message) | Error::ScriptValidationError(message) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, message)
+            }
+            Error::DatabaseError(message)
+            | Error::RuleEngineError(message)
+            | Error::ScriptError(message)
+            | Error::SchedulerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
+            Error::ValidationError(field_errors) => {
+                fields = field_errors;
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Invalid request".to_string(),
+                )
+            }
+        };
+        let error = ErrorBody {
+            error: message,
+            fields,
+        };
+        (status, Json(error)).into_response()
+    }
+}
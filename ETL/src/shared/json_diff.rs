@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct JsonChange {
+    /// JSON pointer of the changed value.
+    pub path: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+fn diff_json_at(path: String, before: Option<&Value>, after: Option<&Value>) -> Vec<JsonChange> {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            keys
+                .into_iter()
+                .flat_map(|key| {
+                    diff_json_at(format!("{}/{}", path, key), before.get(key), after.get(key))
+                })
+                .collect()
+        }
+        _ if before == after => vec![],
+        _ => vec![JsonChange {
+            path: if path.is_empty() { "/".to_string() } else { path },
+            before: before.cloned(),
+            after: after.cloned(),
+        }],
+    }
+}
+
+/// Lists the values that differ between two JSON documents, descending into objects.
+pub fn diff_json(before: &Value, after: &Value) -> Vec<JsonChange> {
+    diff_json_at(String::new(), Some(before), Some(after))
+}
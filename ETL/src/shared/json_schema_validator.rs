@@ -0,0 +1,45 @@
+use crate::shared::errors::Error;
+use jsonschema::ValidationError;
+use serde_json::Value;
+
+fn format_validation_error(prefix: &str, error: &ValidationError) -> String {
+    let path = format!("{}{}", prefix, error.instance_path.as_str());
+    let path = if path.is_empty() { "/".to_string() } else { path };
+    format!("{}: {}", path, error)
+}
+
+/// Checks that a schema is valid and compiles, so that entity lists can be validated against it.
+pub fn validate_json_schema(json_schema: &Value) -> Result<(), Error> {
+    jsonschema::validator_for(json_schema).map_err(|e| Error::JsonSchemaError(e.to_string()))?;
+    Ok(())
+}
+
+/// Validates an entity list against the schema of a single entity and returns every violation
+/// found, prefixed with the path of the offending value.
+pub fn validate_entity_list(
+    json_schema: &Value,
+    is_array: bool,
+    entities: &Value,
+) -> Result<Vec<String>, Error> {
+    let validator =
+        jsonschema::validator_for(json_schema).map_err(|e| Error::JsonSchemaError(e.to_string()))?;
+    if !is_array {
+        return Ok(validator
+            .iter_errors(entities)
+            .map(|e| format_validation_error("", &e))
+            .collect());
+    }
+    let Some(entities) = entities.as_array() else {
+        return Ok(vec!["/: Expected an array of entities".to_string()]);
+    };
+    Ok(entities
+        .iter()
+        .enumerate()
+        .flat_map(|(index, entity)| {
+            validator
+                .iter_errors(entity)
+                .map(|e| format_validation_error(&format!("/{}", index), &e))
+                .collect::<Vec<String>>()
+        })
+        .collect())
+}
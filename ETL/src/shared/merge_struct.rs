@@ -1,3 +1,15 @@
+use serde::{Deserialize, Deserializer};
+
 pub trait Merge<T> {
   fn merge(self, other: T) -> Self;
 }
+
+/// Deserializes a field of a partial update so that a missing field (`None`, with
+/// `#[serde(default)]`) can be told apart from an explicit `null` (`Some(None)`) clearing it.
+pub fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,162 @@
+use crate::shared::script_runtime::ScriptRuntimeKind;
+use crate::shared::shutdown::ShutdownCoordinator;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::time::Duration;
+
+/// Bounds of the duration histograms, in seconds. Scripts are killed after 30 seconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+/// How often the histograms are compacted between two scrapes.
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+fn outcome(success: bool) -> &'static str {
+    match success {
+        true => "success",
+        false => "failure",
+    }
+}
+
+fn runtime_label(runtime: ScriptRuntimeKind) -> &'static str {
+    match runtime {
+        ScriptRuntimeKind::Python => "python",
+        ScriptRuntimeKind::Wasm => "wasm",
+    }
+}
+
+/// Installs the global recorder the metrics below are written to and keeps it compacted until
+/// shutdown. The handle renders them in the Prometheus text format.
+pub fn init_metrics(shutdown: &ShutdownCoordinator) -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets(DURATION_BUCKETS)
+        .expect("Invalid metrics buckets")
+        .install_recorder()
+        .expect("Failed to install metrics recorder");
+
+    let upkeep_handle = handle.clone();
+    let upkeep_shutdown = shutdown.clone();
+    shutdown.spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = upkeep_shutdown.cancelled() => break,
+                _ = interval.tick() => upkeep_handle.run_upkeep(),
+            }
+        }
+    });
+    handle
+}
+
+/// `route` is the path the request matched, such as `/entity-sharings/{entity_sharing_id}`, so
+/// that ids don't end up in the labels.
+pub fn record_http_request(method: String, route: String, status: u16, duration: Duration) {
+    counter!(
+        "heutl_http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => status.to_string(),
+    )
+    .increment(1);
+    histogram!(
+        "heutl_http_request_duration_seconds",
+        "method" => method,
+        "route" => route,
+    )
+    .record(duration.as_secs_f64());
+}
+
+pub fn record_poll_run(
+    entity_sharing_id: &str,
+    success: bool,
+    duration: Duration,
+    entity_count: Option<i64>,
+) {
+    counter!(
+        "heutl_poll_runs_total",
+        "entity_sharing_id" => entity_sharing_id.to_string(),
+        "outcome" => outcome(success),
+    )
+    .increment(1);
+    histogram!(
+        "heutl_poll_run_duration_seconds",
+        "entity_sharing_id" => entity_sharing_id.to_string(),
+    )
+    .record(duration.as_secs_f64());
+    if let Some(entity_count) = entity_count {
+        gauge!(
+            "heutl_poll_run_entity_count",
+            "entity_sharing_id" => entity_sharing_id.to_string(),
+        )
+        .set(entity_count as f64);
+    }
+}
+
+pub fn record_script_run(runtime: ScriptRuntimeKind, success: bool, duration: Duration) {
+    histogram!(
+        "heutl_script_duration_seconds",
+        "runtime" => runtime_label(runtime),
+        "outcome" => outcome(success),
+    )
+    .record(duration.as_secs_f64());
+}
+
+/// `limit` is the limit the script went over: `memory`, `time` or `fuel`.
+pub fn record_script_kill(runtime: ScriptRuntimeKind, limit: &'static str) {
+    counter!(
+        "heutl_script_kills_total",
+        "runtime" => runtime_label(runtime),
+        "limit" => limit,
+    )
+    .increment(1);
+}
+
+pub fn record_delivery(entity_subscription_id: &str, success: bool, duration: Duration) {
+    counter!(
+        "heutl_deliveries_total",
+        "entity_subscription_id" => entity_subscription_id.to_string(),
+        "outcome" => outcome(success),
+    )
+    .increment(1);
+    histogram!(
+        "heutl_delivery_duration_seconds",
+        "entity_subscription_id" => entity_subscription_id.to_string(),
+    )
+    .record(duration.as_secs_f64());
+}
+
+/// Counts the deliveries made again after a shutdown interrupted them.
+pub fn record_delivery_retry(entity_subscription_id: &str) {
+    counter!(
+        "heutl_delivery_retries_total",
+        "entity_subscription_id" => entity_subscription_id.to_string(),
+    )
+    .increment(1);
+}
+
+/// Counts the polled lists the entity count guardrail kept from the subscriptions.
+pub fn record_delivery_held(entity_sharing_id: &str) {
+    counter!(
+        "heutl_deliveries_held_total",
+        "entity_sharing_id" => entity_sharing_id.to_string(),
+    )
+    .increment(1);
+}
+
+pub fn set_in_flight_deliveries(count: usize) {
+    gauge!("heutl_deliveries_in_flight").set(count as f64);
+}
+
+/// Polls and deliveries of a connected app waiting for a permit, and those holding one.
+pub fn set_connected_app_calls(connected_app_id: &str, queued: usize, in_flight: usize) {
+    gauge!(
+        "heutl_connected_app_queued_calls",
+        "connected_app_id" => connected_app_id.to_string(),
+    )
+    .set(queued as f64);
+    gauge!(
+        "heutl_connected_app_in_flight_calls",
+        "connected_app_id" => connected_app_id.to_string(),
+    )
+    .set(in_flight as f64);
+}
@@ -0,0 +1,2 @@
+-- Persist the cursor returned by polling scripts between two polls
+ALTER TABLE entity_sharings ADD COLUMN polling_cursor TEXT;
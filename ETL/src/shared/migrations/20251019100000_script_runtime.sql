@@ -0,0 +1,3 @@
+-- Runtime targeted by sharing and subscription scripts
+ALTER TABLE entity_sharings ADD COLUMN script_runtime TEXT NOT NULL DEFAULT 'python';
+ALTER TABLE entity_subscriptions ADD COLUMN script_runtime TEXT NOT NULL DEFAULT 'python';
@@ -0,0 +1,5 @@
+-- Immutable history of sharing and subscription definitions
+CREATE TABLE IF NOT EXISTS entity_sharing_revisions (id TEXT PRIMARY KEY, entity_sharing_id TEXT, revision INTEGER, snapshot TEXT, author TEXT,
+ created_at INTEGER, UNIQUE (entity_sharing_id, revision));
+CREATE TABLE IF NOT EXISTS entity_subscription_revisions (id TEXT PRIMARY KEY, entity_subscription_id TEXT, revision INTEGER, snapshot TEXT, author TEXT,
+ created_at INTEGER, UNIQUE (entity_subscription_id, revision));
@@ -0,0 +1,3 @@
+-- Modules scripts are allowed to import
+ALTER TABLE entity_sharings ADD COLUMN allowed_modules TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE entity_subscriptions ADD COLUMN allowed_modules TEXT NOT NULL DEFAULT '[]';
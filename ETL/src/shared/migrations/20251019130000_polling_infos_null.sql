@@ -0,0 +1,2 @@
+-- Sharings without polling were stored with a JSON 'null' instead of NULL
+UPDATE entity_sharings SET polling_infos = NULL WHERE polling_infos = 'null';
@@ -0,0 +1,6 @@
+-- Health of the sharing pollers
+ALTER TABLE entity_sharings ADD COLUMN health_status TEXT NOT NULL DEFAULT 'healthy';
+ALTER TABLE entity_sharings ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE entity_sharings ADD COLUMN last_error TEXT;
+ALTER TABLE entity_sharings ADD COLUMN last_success_at INTEGER;
+ALTER TABLE entity_sharings ADD COLUMN last_failure_at INTEGER;
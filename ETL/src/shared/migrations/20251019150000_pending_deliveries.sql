@@ -0,0 +1,2 @@
+-- Deliveries cut off by a shutdown, replayed on the next start
+CREATE TABLE IF NOT EXISTS pending_deliveries (id TEXT PRIMARY KEY, entity_subscription_id TEXT, data TEXT, created_at INTEGER);
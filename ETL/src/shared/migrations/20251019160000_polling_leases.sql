@@ -0,0 +1,5 @@
+-- Instances taking part in polling, kept alive by their heartbeats
+CREATE TABLE IF NOT EXISTS polling_instances (id TEXT PRIMARY KEY, started_at INTEGER, expires_at INTEGER);
+-- Sharing polled by each instance, a lease past its expiry can be taken over by any instance
+CREATE TABLE IF NOT EXISTS entity_sharing_leases (entity_sharing_id TEXT PRIMARY KEY, instance_id TEXT, acquired_at INTEGER, expires_at INTEGER);
+CREATE INDEX IF NOT EXISTS entity_sharing_leases_instance_id ON entity_sharing_leases (instance_id);
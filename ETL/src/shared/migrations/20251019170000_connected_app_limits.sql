@@ -0,0 +1,3 @@
+-- Throttling of the polls and deliveries targeting a connected app, NULL for no limit
+ALTER TABLE connected_apps ADD COLUMN rate_limit_per_minute INTEGER;
+ALTER TABLE connected_apps ADD COLUMN max_concurrent_calls INTEGER;
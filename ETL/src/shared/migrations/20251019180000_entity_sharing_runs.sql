@@ -0,0 +1,4 @@
+-- One row per poll cycle of a sharing, scheduled or triggered through the API
+CREATE TABLE IF NOT EXISTS entity_sharing_runs (id TEXT PRIMARY KEY, entity_sharing_id TEXT, trigger TEXT, started_at INTEGER, ended_at INTEGER,
+ duration_ms INTEGER, entity_count INTEGER, valid BOOLEAN, validation_errors TEXT, subscriptions_notified INTEGER, subscriptions_failed INTEGER, error TEXT);
+CREATE INDEX IF NOT EXISTS entity_sharing_runs_entity_sharing_id ON entity_sharing_runs (entity_sharing_id, started_at);
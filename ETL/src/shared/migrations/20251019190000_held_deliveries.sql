@@ -0,0 +1,5 @@
+-- Polled entity lists held back by the entity count guardrail of their sharing
+CREATE TABLE IF NOT EXISTS held_deliveries (id TEXT PRIMARY KEY, entity_sharing_id TEXT, run_id TEXT, data TEXT, entity_count INTEGER,
+ previous_entity_count INTEGER, status TEXT, created_at INTEGER, resolved_at INTEGER);
+CREATE INDEX IF NOT EXISTS held_deliveries_entity_sharing_id ON held_deliveries (entity_sharing_id, created_at);
+ALTER TABLE entity_sharing_runs ADD COLUMN held_delivery_id TEXT;
@@ -0,0 +1,4 @@
+-- Keys connected apps authenticate with, only their SHA-256 is stored
+CREATE TABLE IF NOT EXISTS connected_app_api_keys (id TEXT PRIMARY KEY, connected_app_id TEXT, key_hash TEXT UNIQUE, prefix TEXT,
+ created_at INTEGER, revoked_at INTEGER);
+CREATE INDEX IF NOT EXISTS connected_app_api_keys_connected_app_id ON connected_app_api_keys (connected_app_id);
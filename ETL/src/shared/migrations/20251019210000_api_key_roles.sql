@@ -0,0 +1,2 @@
+-- Role granted by an API key, the existing keys act as owners of their connected app
+ALTER TABLE connected_app_api_keys ADD COLUMN role TEXT NOT NULL DEFAULT 'owner';
@@ -0,0 +1,15 @@
+-- First revision of the sharings and subscriptions created before revisions were recorded
+INSERT INTO entity_sharing_revisions (id, entity_sharing_id, revision, snapshot, author, created_at)
+SELECT lower(hex(randomblob(16))), id, 1, json_object('id', id, 'name', name, 'connected_app_id', connected_app_id,
+ 'created_at', created_at, 'updated_at', updated_at,
+ 'polling_infos', CASE WHEN json_valid(polling_infos) THEN json(polling_infos) END,
+ 'json_schema', CASE WHEN json_valid(json_schema) THEN json(json_schema) ELSE json('{}') END,
+ 'is_array', json(CASE WHEN is_array THEN 'true' ELSE 'false' END), 'python_script', python_script,
+ 'script_runtime', script_runtime, 'allowed_modules', json(allowed_modules)), NULL, updated_at
+FROM entity_sharings WHERE id NOT IN (SELECT entity_sharing_id FROM entity_sharing_revisions);
+INSERT INTO entity_subscription_revisions (id, entity_subscription_id, revision, snapshot, author, created_at)
+SELECT lower(hex(randomblob(16))), id, 1, json_object('id', id, 'entity_sharing_id', entity_sharing_id,
+ 'created_at', created_at, 'updated_at', updated_at, 'connected_app_id', connected_app_id,
+ 'jdm_transform', CASE WHEN json_valid(jdm_transform) THEN json(jdm_transform) END, 'python_script', python_script,
+ 'script_runtime', script_runtime, 'allowed_modules', json(allowed_modules)), NULL, updated_at
+FROM entity_subscriptions WHERE id NOT IN (SELECT entity_subscription_id FROM entity_subscription_revisions);
@@ -0,0 +1,2 @@
+-- Cursor of the held poll, saved once the held list is approved
+ALTER TABLE held_deliveries ADD COLUMN cursor TEXT;
@@ -0,0 +1,308 @@
+use crate::shared::errors::Error;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite};
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Name,
+}
+
+impl SortField {
+    fn column(&self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::Name => "name",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SortValue {
+    Integer(i64),
+    Text(String),
+}
+
+/// Items listed page by page, ordered by one of their `SortField` then by id.
+pub trait Paginated {
+    fn id(&self) -> &str;
+    fn sort_value(&self, sort: SortField) -> SortValue;
+}
+
+/// Position after the last item of a page, handed to clients as an opaque string.
+#[derive(Serialize, Deserialize, Debug)]
+struct Cursor {
+    sort: SortField,
+    order: SortOrder,
+    value: SortValue,
+    id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    fn decode(cursor: &str) -> Result<Self, Error> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| Error::BadRequestError("Invalid cursor".to_string()))
+    }
+}
+
+#[derive(Deserialize, IntoParams, Debug, Default)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Items per page, 100 by default and 500 at most.
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page, which keeps its sort and order.
+    pub cursor: Option<String>,
+    /// Defaults to `created_at`.
+    pub sort: Option<SortField>,
+    /// Defaults to `asc`.
+    pub order: Option<SortOrder>,
+}
+
+impl PageQuery {
+    pub fn page_request(&self) -> Result<PageRequest, Error> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(Error::BadRequestError(format!(
+                "limit must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+        let Some(cursor) = &self.cursor else {
+            return Ok(PageRequest {
+                limit,
+                sort: self.sort.unwrap_or_default(),
+                order: self.order.unwrap_or_default(),
+                after: None,
+            });
+        };
+        let cursor = Cursor::decode(cursor)?;
+        if self.sort.is_some_and(|sort| sort != cursor.sort)
+            || self.order.is_some_and(|order| order != cursor.order)
+        {
+            return Err(Error::BadRequestError(
+                "cursor was issued for another sort or order".to_string(),
+            ));
+        }
+        Ok(PageRequest {
+            limit,
+            sort: cursor.sort,
+            order: cursor.order,
+            after: Some((cursor.value, cursor.id)),
+        })
+    }
+}
+
+pub struct PageRequest {
+    pub limit: i64,
+    pub sort: SortField,
+    pub order: SortOrder,
+    after: Option<(SortValue, String)>,
+}
+
+impl PageRequest {
+    pub fn require_sort(&self, sortable: &[SortField]) -> Result<(), Error> {
+        if !sortable.contains(&self.sort) {
+            return Err(Error::BadRequestError(format!(
+                "Can't sort by {}",
+                self.sort.column()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Appends the position, order and limit of the page to a query ending with a `WHERE`
+    /// clause. One more item than the limit is fetched to tell whether another page follows.
+    pub fn push_to(&self, query: &mut QueryBuilder<Sqlite>) {
+        let column = self.sort.column();
+        let (comparison, direction) = match self.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+        if let Some((value, id)) = &self.after {
+            query.push(format!(" AND ({}, id) {} (", column, comparison));
+            match value {
+                SortValue::Integer(value) => query.push_bind(*value),
+                SortValue::Text(value) => query.push_bind(value.clone()),
+            };
+            query.push(", ").push_bind(id.clone()).push(")");
+        }
+        query.push(format!(
+            " ORDER BY {} {}, id {} LIMIT ",
+            column, direction, direction
+        ));
+        query.push_bind(self.limit + 1);
+    }
+
+    pub fn page_of<T: Paginated>(&self, mut items: Vec<T>) -> Page<T> {
+        let mut next_cursor = None;
+        if items.len() as i64 > self.limit {
+            items.truncate(self.limit as usize);
+            next_cursor = items.last().map(|item| {
+                Cursor {
+                    sort: self.sort,
+                    order: self.order,
+                    value: item.sort_value(self.sort),
+                    id: item.id().to_string(),
+                }
+                .encode()
+            });
+        }
+        Page { items, next_cursor }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item {
+        id: String,
+        name: String,
+    }
+
+    impl Paginated for Item {
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn sort_value(&self, _sort: SortField) -> SortValue {
+            SortValue::Text(self.name.clone())
+        }
+    }
+
+    fn items(count: usize) -> Vec<Item> {
+        (0..count)
+            .map(|index| Item {
+                id: format!("item-{}", index),
+                name: format!("name-{}", index),
+            })
+            .collect()
+    }
+
+    fn page_request(query: PageQuery) -> PageRequest {
+        query.page_request().unwrap()
+    }
+
+    fn next_cursor(sort: SortField, order: SortOrder) -> String {
+        let page_request = page_request(PageQuery {
+            limit: Some(2),
+            sort: Some(sort),
+            order: Some(order),
+            ..Default::default()
+        });
+        page_request.page_of(items(3)).next_cursor.unwrap()
+    }
+
+    #[test]
+    fn cursors_resume_after_the_last_item_with_the_same_sort_and_order() {
+        let cursor = next_cursor(SortField::Name, SortOrder::Desc);
+
+        let next_page = page_request(PageQuery {
+            limit: Some(2),
+            cursor: Some(cursor),
+            ..Default::default()
+        });
+
+        assert_eq!(next_page.limit, 2);
+        assert_eq!(next_page.sort, SortField::Name);
+        assert_eq!(next_page.order, SortOrder::Desc);
+        assert_eq!(
+            next_page.after,
+            Some((SortValue::Text("name-1".to_string()), "item-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn cursors_of_another_sort_or_order_are_rejected() {
+        let cursor = next_cursor(SortField::Name, SortOrder::Desc);
+
+        for (sort, order) in [
+            (Some(SortField::CreatedAt), None),
+            (None, Some(SortOrder::Asc)),
+        ] {
+            let result = PageQuery {
+                cursor: Some(cursor.clone()),
+                sort,
+                order,
+                ..Default::default()
+            }
+            .page_request();
+            assert!(matches!(result, Err(Error::BadRequestError(_))));
+        }
+        // Restating the sort and order of the cursor is fine.
+        PageQuery {
+            cursor: Some(cursor),
+            sort: Some(SortField::Name),
+            order: Some(SortOrder::Desc),
+            ..Default::default()
+        }
+        .page_request()
+        .unwrap();
+    }
+
+    #[test]
+    fn forged_cursors_are_rejected() {
+        for cursor in [
+            "not a cursor".to_string(),
+            URL_SAFE_NO_PAD.encode("not json"),
+            URL_SAFE_NO_PAD.encode(r#"{"sort": "name", "order": "asc"}"#),
+            URL_SAFE_NO_PAD.encode(r#"{"sort": "id", "order": "asc", "value": 1, "id": "a"}"#),
+        ] {
+            let result = PageQuery {
+                cursor: Some(cursor),
+                ..Default::default()
+            }
+            .page_request();
+            assert!(matches!(result, Err(Error::BadRequestError(_))));
+        }
+    }
+
+    #[test]
+    fn last_pages_have_no_next_cursor() {
+        let page_request = page_request(PageQuery {
+            limit: Some(3),
+            ..Default::default()
+        });
+
+        // The query fetches one more item than the limit, a page without it is the last.
+        assert!(page_request.page_of(items(3)).next_cursor.is_none());
+        assert!(page_request.page_of(items(1)).next_cursor.is_none());
+        assert!(page_request.page_of(items(0)).next_cursor.is_none());
+        let page = page_request.page_of(items(4));
+        assert_eq!(page.items.len(), 3);
+        assert!(page.next_cursor.is_some());
+    }
+}
@@ -1,11 +1,16 @@
 use crate::shared::errors::Error;
+use crate::shared::metrics::record_script_kill;
+use crate::shared::script_runtime::{ScriptRun, ScriptRuntime, ScriptRuntimeKind};
 use serde_json::Value;
+use std::path::Path;
+use std::thread;
 use std::time::SystemTime;
 use std::{
     process::{Command, Stdio},
     time::Duration,
 };
 use sysinfo::{Pid, System};
+use tracing::warn;
 
 fn limit_process_memory_and_time(pid: Pid, memory_limit: u64, time_limit: u64) {
     let mut sys = System::new_all();
@@ -14,44 +19,127 @@ fn limit_process_memory_and_time(pid: Pid, memory_limit: u64, time_limit: u64) {
 
     while sys.process(pid).is_some() {
         if sys.process(pid).unwrap().memory() > memory_limit {
-            println!("Process memory limit exceeded");
-            sys.process(pid).unwrap().kill();
+            warn!(%pid, memory_limit, "Script memory limit exceeded, killing it");
+            if sys.process(pid).unwrap().kill() {
+                record_script_kill(ScriptRuntimeKind::Python, "memory");
+                return;
+            }
         }
         if start_time.elapsed().unwrap() > Duration::from_secs(time_limit) {
-            println!("Process time limit exceeded");
-            sys.process(pid).unwrap().kill();
+            warn!(%pid, time_limit, "Script time limit exceeded, killing it");
+            if sys.process(pid).unwrap().kill() {
+                record_script_kill(ScriptRuntimeKind::Python, "time");
+                return;
+            }
         }
         sys.refresh_all();
     }
 }
 
-// TODO: Get result using memmap file / memory buffer instead of stdout
-pub fn run_python_script(script: &String, input: &Value) -> Result<String, Error> {
-    let handle = Command::new("python/.venv/bin/python")
-        .arg("python/container.py")
+const PYTHON_INTERPRETER: &str = "python/.venv/bin/python";
+const CONTAINER_SCRIPT: &str = "python/container.py";
+
+fn container_command(script: &str, allowed_modules: &[String]) -> Result<Command, Error> {
+    let mut command = Command::new(PYTHON_INTERPRETER);
+    command
+        .arg(CONTAINER_SCRIPT)
         .arg(format!("--script={}", script))
-        .arg(format!("--input={}", input.to_string()))
+        .arg(format!(
+            "--allowed-modules={}",
+            serde_json::to_string(allowed_modules)?
+        ));
+    Ok(command)
+}
+
+// TODO: Get result using memmap file / memory buffer instead of stdout
+pub fn run_python_script(
+    script: &str,
+    input: &Value,
+    cursor: &Option<Value>,
+    allowed_modules: &[String],
+) -> Result<ScriptRun, Error> {
+    let handle = container_command(script, allowed_modules)?
+        .arg(format!("--input={}", input))
+        .arg(format!("--cursor={}", serde_json::to_string(cursor)?))
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
-        .unwrap();
-    let mut sys = System::new_all();
-    sys.refresh_all();
+        .map_err(|e| Error::ScriptError(e.to_string()))?;
 
     let pid = Pid::from_u32(handle.id());
 
-    limit_process_memory_and_time(pid, 1024 * 1024 * 1024, 30);
+    // Pipes are drained while the limits are enforced so a chatty script cannot block on a
+    // full pipe until it gets killed.
+    let limiter = thread::spawn(move || limit_process_memory_and_time(pid, 1024 * 1024 * 1024, 30));
 
-    let output = handle.wait_with_output().unwrap();
+    let output = handle
+        .wait_with_output()
+        .map_err(|e| Error::ScriptError(e.to_string()))?;
+    let _ = limiter.join();
 
     let stdout = String::from_utf8_lossy(&output.stdout)
         .to_string()
         .trim()
         .to_string();
-    Ok(stdout)
+    let logs = String::from_utf8_lossy(&output.stderr).to_string();
+    Ok(ScriptRun {
+        stdout,
+        logs,
+        success: output.status.success(),
+    })
+}
+
+/// Compiles the script and checks its imports against the allow-list without running it.
+pub fn validate_python_script(script: &str, allowed_modules: &[String]) -> Result<(), Error> {
+    let output = container_command(script, allowed_modules)?
+        .arg("--validate")
+        .output()
+        .map_err(|e| Error::ScriptError(e.to_string()))?;
+    if !output.status.success() {
+        return Err(Error::ScriptValidationError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the venv interpreter starts and the sandbox script is in place, without which every
+/// Python script fails.
+pub fn check_python_interpreter() -> Result<(), Error> {
+    if !Path::new(CONTAINER_SCRIPT).is_file() {
+        return Err(Error::ScriptError(format!(
+            "{} is missing",
+            CONTAINER_SCRIPT
+        )));
+    }
+    let output = Command::new(PYTHON_INTERPRETER)
+        .arg("--version")
+        .output()
+        .map_err(|e| Error::ScriptError(format!("{}: {}", PYTHON_INTERPRETER, e)))?;
+    if !output.status.success() {
+        return Err(Error::ScriptError(format!(
+            "{} --version failed: {}",
+            PYTHON_INTERPRETER,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
 }
 
-pub fn run_python_script_output_json(script: &String, input: &Value) -> Result<Value, Error> {
-    let result = run_python_script(script, input)?;
-    let result = serde_json::from_str::<Value>(&result)?;
-    Ok(result)
+pub struct PythonScriptRuntime;
+
+impl ScriptRuntime for PythonScriptRuntime {
+    fn run(
+        &self,
+        script: &str,
+        input: &Value,
+        cursor: &Option<Value>,
+        allowed_modules: &[String],
+    ) -> Result<ScriptRun, Error> {
+        run_python_script(script, input, cursor, allowed_modules)
+    }
+
+    fn validate(&self, script: &str, allowed_modules: &[String]) -> Result<(), Error> {
+        validate_python_script(script, allowed_modules)
+    }
 }
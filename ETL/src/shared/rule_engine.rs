@@ -2,6 +2,8 @@ use crate::shared::errors::Error;
 use serde_json::Value;
 use zen_engine::{DecisionEngine, Variable, model::DecisionContent};
 
+// Not wired to the `jdm_transform` of the subscriptions yet.
+#[allow(dead_code)]
 pub async fn evaluate(content: &Value) -> Result<Value, Error> {
     let content: DecisionContent = serde_json::from_value(content.clone())?;
     let engine = DecisionEngine::default();
@@ -12,5 +14,5 @@ pub async fn evaluate(content: &Value) -> Result<Value, Error> {
         .await
         .map_err(|e| Error::RuleEngineError(e.to_string()))?;
 
-    return Ok(result.result.to_value());
+    Ok(result.result.to_value())
 }
@@ -0,0 +1,127 @@
+use crate::shared::errors::Error;
+use crate::shared::metrics::record_script_run;
+use crate::shared::python_runner::PythonScriptRuntime;
+use crate::shared::wasm_runner::WasmScriptRuntime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+/// Runtime a sharing or subscription script targets.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ScriptRuntimeKind {
+    /// Python source run by `python/container.py` under RestrictedPython.
+    #[default]
+    Python,
+    /// WebAssembly module, as WAT text or base64-encoded binary, run in-process.
+    Wasm,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScriptOutput {
+    pub result: Value,
+    /// Value the script assigned to `cursor`, to be handed back on its next run.
+    #[serde(default)]
+    pub cursor: Option<Value>,
+}
+
+/// Raw outcome of a script run: the JSON it produced and the logs it emitted along the way.
+#[derive(Debug, Clone)]
+pub struct ScriptRun {
+    pub stdout: String,
+    pub logs: String,
+    pub success: bool,
+}
+
+impl ScriptRun {
+    pub fn output_json(&self) -> Result<ScriptOutput, Error> {
+        if !self.success {
+            return Err(Error::ScriptError(self.logs.clone()));
+        }
+        let output = serde_json::from_str::<ScriptOutput>(&self.stdout)?;
+        Ok(output)
+    }
+}
+
+pub trait ScriptRuntime: Send + Sync {
+    fn run(
+        &self,
+        script: &str,
+        input: &Value,
+        cursor: &Option<Value>,
+        allowed_modules: &[String],
+    ) -> Result<ScriptRun, Error>;
+    /// Checks that a script loads and only imports allowed modules, without running it.
+    fn validate(&self, script: &str, allowed_modules: &[String]) -> Result<(), Error>;
+}
+
+pub fn get_script_runtime(kind: ScriptRuntimeKind) -> &'static dyn ScriptRuntime {
+    match kind {
+        ScriptRuntimeKind::Python => &PythonScriptRuntime,
+        ScriptRuntimeKind::Wasm => &WasmScriptRuntime,
+    }
+}
+
+pub fn run_script(
+    kind: ScriptRuntimeKind,
+    script: &str,
+    input: &Value,
+    cursor: &Option<Value>,
+    allowed_modules: &[String],
+) -> Result<ScriptRun, Error> {
+    let started = Instant::now();
+    let script_run = get_script_runtime(kind).run(script, input, cursor, allowed_modules);
+    let success = script_run
+        .as_ref()
+        .is_ok_and(|script_run| script_run.success);
+    record_script_run(kind, success, started.elapsed());
+    script_run
+}
+
+pub fn run_script_output_json(
+    kind: ScriptRuntimeKind,
+    script: &str,
+    input: &Value,
+    cursor: &Option<Value>,
+    allowed_modules: &[String],
+) -> Result<ScriptOutput, Error> {
+    run_script(kind, script, input, cursor, allowed_modules)?.output_json()
+}
+
+/// Runs a script on the blocking pool so that long runs don't stall the runtime workers.
+pub async fn spawn_script_output_json(
+    kind: ScriptRuntimeKind,
+    script: &str,
+    input: &Value,
+    cursor: &Option<Value>,
+    allowed_modules: &[String],
+) -> Result<ScriptOutput, Error> {
+    let script = script.to_string();
+    let input = input.clone();
+    let cursor = cursor.clone();
+    let allowed_modules = allowed_modules.to_vec();
+    tokio::task::spawn_blocking(move || {
+        run_script_output_json(kind, &script, &input, &cursor, &allowed_modules)
+    })
+    .await
+    .map_err(|e| Error::ScriptError(e.to_string()))?
+}
+
+/// Validates a script on the blocking pool, as the Python runtime spawns an interpreter to do so.
+pub async fn validate_script(
+    kind: ScriptRuntimeKind,
+    script: &str,
+    allowed_modules: &[String],
+) -> Result<(), Error> {
+    let script = script.to_string();
+    let allowed_modules = allowed_modules.to_vec();
+    tokio::task::spawn_blocking(move || {
+        get_script_runtime(kind).validate(&script, &allowed_modules)
+    })
+    .await
+    .map_err(|e| Error::ScriptError(e.to_string()))?
+}
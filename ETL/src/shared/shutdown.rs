@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::signal;
+use tokio::task::JoinHandle;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tokio_util::task::TaskTracker;
+use tracing::info;
+
+/// Single place shutdown is requested from and waited on. Long running work is spawned through
+/// it so that shutdown can wait for it to finish.
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    token: CancellationToken,
+    tracker: TaskTracker,
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+        }
+    }
+
+    /// Requests shutdown on SIGINT or SIGTERM.
+    pub fn listen_for_signals(&self) {
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            info!("Received SIGINT/SIGTERM, shutting down...");
+            token.cancel();
+        });
+    }
+
+    pub fn request_shutdown(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once shutdown is requested.
+    pub fn cancelled(&self) -> WaitForCancellationFutureOwned {
+        self.token.clone().cancelled_owned()
+    }
+
+    /// Spawns a task shutdown waits for.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tracker.spawn(task)
+    }
+
+    /// Waits for the spawned tasks to finish, returning false if some are still running after
+    /// `grace_period`.
+    pub async fn wait(&self, grace_period: Duration) -> bool {
+        self.tracker.close();
+        return tokio::time::timeout(grace_period, self.tracker.wait())
+            .await
+            .is_ok();
+    }
+}
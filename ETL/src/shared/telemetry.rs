@@ -0,0 +1,63 @@
+use crate::shared::config::{Config, LogFormat};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::error;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, fmt};
+
+const SERVICE_NAME: &str = "heutl";
+
+/// Keeps the OTLP exporter, if any, alive. Spans still buffered are flushed by `shutdown`.
+pub struct TelemetryGuard {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl TelemetryGuard {
+    pub fn shutdown(self) {
+        if let Some(tracer_provider) = self.tracer_provider
+            && let Err(e) = tracer_provider.shutdown()
+        {
+            error!(error = ?e, "Error flushing spans");
+        }
+    }
+}
+
+/// Installs the global tracing subscriber: logs filtered by `RUST_LOG`, `info` by default, in
+/// the configured format, and spans exported over OTLP when an endpoint is configured. Must be
+/// called from within the tokio runtime.
+pub fn init_telemetry(config: &Config) -> TelemetryGuard {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt_layer = match config.log_format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let tracer_provider = config.otlp_endpoint.as_ref().map(|otlp_endpoint| {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(otlp_endpoint)
+            .build()
+            .expect("Failed to create OTLP exporter");
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build()
+    });
+    let otel_layer = tracer_provider.as_ref().map(|tracer_provider| {
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(SERVICE_NAME))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+    TelemetryGuard { tracer_provider }
+}
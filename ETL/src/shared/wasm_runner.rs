@@ -0,0 +1,316 @@
+use crate::shared::errors::Error;
+use crate::shared::metrics::record_script_kill;
+use crate::shared::script_runtime::{ScriptRun, ScriptRuntime, ScriptRuntimeKind};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use wasmtime::{
+    AsContext, AsContextMut, Caller, Config, Engine, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Trap,
+};
+
+const FUEL_LIMIT: u64 = 10_000_000_000;
+const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+const MODULE_CACHE_SIZE: usize = 256;
+/// Bytes a run may log through `env.log`, the logs being kept in the memory of HEUTL itself.
+const LOG_LIMIT: usize = 1024 * 1024;
+
+struct WasmScriptState {
+    limits: StoreLimits,
+    logs: String,
+}
+
+fn get_engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config).expect("Failed to create WebAssembly engine")
+    })
+}
+
+/// Compiled modules of the most recently used scripts, keyed by the script text itself so that
+/// two scripts never share a module. The least recently used one goes when the cache is full.
+struct ModuleCache {
+    capacity: usize,
+    modules: HashMap<String, (Module, u64)>,
+    last_use: u64,
+}
+
+impl ModuleCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            modules: HashMap::new(),
+            last_use: 0,
+        }
+    }
+
+    fn get(&mut self, script: &str) -> Option<Module> {
+        let (module, last_use) = self.modules.get_mut(script)?;
+        self.last_use += 1;
+        *last_use = self.last_use;
+        Some(module.clone())
+    }
+
+    fn insert(&mut self, script: &str, module: Module) {
+        if self.modules.len() >= self.capacity && !self.modules.contains_key(script) {
+            let least_recently_used = self
+                .modules
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(script, _)| script.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                self.modules.remove(&least_recently_used);
+            }
+        }
+        self.last_use += 1;
+        self.modules
+            .insert(script.to_string(), (module, self.last_use));
+    }
+}
+
+/// Compiled modules are cached by script so hot transforms skip compilation.
+fn get_module(script: &str) -> Result<Module, Error> {
+    static MODULES: OnceLock<Mutex<ModuleCache>> = OnceLock::new();
+    let modules = MODULES.get_or_init(|| Mutex::new(ModuleCache::new(MODULE_CACHE_SIZE)));
+    if let Some(module) = modules.lock().unwrap().get(script) {
+        return Ok(module);
+    }
+
+    let module = compile_module(script)?;
+    modules.lock().unwrap().insert(script, module.clone());
+    Ok(module)
+}
+
+fn compile_module(script: &str) -> Result<Module, Error> {
+    let bytes = if script.trim_start().starts_with('(') {
+        script.as_bytes().to_vec()
+    } else {
+        BASE64
+            .decode(script.trim())
+            .map_err(|e| Error::ScriptError(e.to_string()))?
+    };
+    Module::new(get_engine(), bytes).map_err(|e| Error::ScriptError(format!("{:#}", e)))
+}
+
+/// Trap of a module logging more than `LOG_LIMIT`.
+#[derive(Debug)]
+struct LogLimitExceeded;
+
+impl fmt::Display for LogLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Logs exceeded {} bytes", LOG_LIMIT)
+    }
+}
+
+impl std::error::Error for LogLimitExceeded {}
+
+/// Bytes of the guest memory `data` at `ptr..ptr + len`. Both come from the guest, so the range
+/// is checked against the memory rather than trusted to size a host buffer.
+fn read_memory(data: &[u8], ptr: i32, len: i32) -> Result<&[u8], wasmtime::Error> {
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+    data.get(start..end)
+        .ok_or(wasmtime::Error::msg("Out of bounds memory access"))
+}
+
+fn get_memory(caller: &mut Caller<'_, WasmScriptState>) -> Result<Memory, wasmtime::Error> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(wasmtime::Error::msg("Module does not export a memory"))
+}
+
+/// Runs a module following this ABI:
+/// - it exports `memory`, `alloc(len: i32) -> i32` and `run(ptr: i32, len: i32) -> i64`,
+/// - `run` receives `{"input": ..., "cursor": ...}` as JSON and returns the location of
+///   `{"result": ..., "cursor": ...}` packed as `(ptr << 32) | len`,
+/// - it may import `env.log(ptr: i32, len: i32)` to emit log lines.
+fn run_wasm_module(
+    module: &Module,
+    payload: &[u8],
+    store: &mut Store<WasmScriptState>,
+) -> Result<String, wasmtime::Error> {
+    let mut linker = Linker::new(get_engine());
+    linker.func_wrap(
+        "env",
+        "log",
+        |mut caller: Caller<'_, WasmScriptState>, ptr: i32, len: i32| {
+            let memory = get_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let message = read_memory(data, ptr, len)?;
+            if state.logs.len() + message.len() + 1 > LOG_LIMIT {
+                return Err(wasmtime::Error::new(LogLimitExceeded));
+            }
+            state.logs.push_str(&String::from_utf8_lossy(message));
+            state.logs.push('\n');
+            Ok(())
+        },
+    )?;
+    let instance = linker.instantiate(store.as_context_mut(), module)?;
+    let memory = instance
+        .get_memory(store.as_context_mut(), "memory")
+        .ok_or(wasmtime::Error::msg("Module does not export a memory"))?;
+    let alloc = instance.get_typed_func::<i32, i32>(store.as_context_mut(), "alloc")?;
+    let run = instance.get_typed_func::<(i32, i32), i64>(store.as_context_mut(), "run")?;
+
+    let input_ptr = alloc.call(store.as_context_mut(), payload.len() as i32)?;
+    memory.write(store.as_context_mut(), input_ptr as u32 as usize, payload)?;
+    let packed = run.call(store.as_context_mut(), (input_ptr, payload.len() as i32))?;
+
+    let output = read_memory(
+        memory.data(store.as_context()),
+        (packed >> 32) as i32,
+        packed as i32,
+    )?;
+    Ok(String::from_utf8_lossy(output).to_string())
+}
+
+pub fn run_wasm_script(
+    script: &str,
+    input: &Value,
+    cursor: &Option<Value>,
+) -> Result<ScriptRun, Error> {
+    let module = get_module(script)?;
+    let payload = serde_json::to_vec(&json!({ "input": input, "cursor": cursor }))?;
+
+    let mut store = Store::new(
+        get_engine(),
+        WasmScriptState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build(),
+            logs: String::new(),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store
+        .set_fuel(FUEL_LIMIT)
+        .map_err(|e| Error::ScriptError(e.to_string()))?;
+
+    let result = run_wasm_module(&module, &payload, &mut store);
+    let mut logs = std::mem::take(&mut store.data_mut().logs);
+    match result {
+        Ok(stdout) => Ok(ScriptRun {
+            stdout: stdout.trim().to_string(),
+            logs,
+            success: true,
+        }),
+        Err(e) => {
+            if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+                record_script_kill(ScriptRuntimeKind::Wasm, "fuel");
+            } else if e.downcast_ref::<LogLimitExceeded>().is_some() {
+                record_script_kill(ScriptRuntimeKind::Wasm, "memory");
+            }
+            logs.push_str(&format!("{:#}\n", e));
+            Ok(ScriptRun {
+                stdout: String::new(),
+                logs,
+                success: false,
+            })
+        }
+    }
+}
+
+pub struct WasmScriptRuntime;
+
+impl ScriptRuntime for WasmScriptRuntime {
+    fn run(
+        &self,
+        script: &str,
+        input: &Value,
+        cursor: &Option<Value>,
+        _allowed_modules: &[String],
+    ) -> Result<ScriptRun, Error> {
+        run_wasm_script(script, input, cursor)
+    }
+
+    /// Modules can only import the host functions HEUTL provides, so only compilation is checked.
+    fn validate(&self, script: &str, _allowed_modules: &[String]) -> Result<(), Error> {
+        get_module(script).map(|_| ()).map_err(|e| match e {
+            Error::ScriptError(message) => Error::ScriptValidationError(message),
+            e => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(script: &str) -> Module {
+        compile_module(script).unwrap()
+    }
+
+    #[test]
+    fn modules_are_cached_by_script_text() {
+        let mut cache = ModuleCache::new(2);
+        let empty = module("(module)");
+        cache.insert("(module)", empty.clone());
+
+        assert!(Module::same(&cache.get("(module)").unwrap(), &empty));
+        assert!(cache.get(" (module)").is_none());
+    }
+
+    #[test]
+    fn least_recently_used_module_is_evicted() {
+        let mut cache = ModuleCache::new(2);
+        cache.insert("(module)", module("(module)"));
+        cache.insert("(module (func))", module("(module (func))"));
+        cache.get("(module)").unwrap();
+
+        cache.insert("(module (memory 1))", module("(module (memory 1))"));
+
+        assert!(cache.get("(module)").is_some());
+        assert!(cache.get("(module (func))").is_none());
+        assert!(cache.get("(module (memory 1))").is_some());
+    }
+
+    #[test]
+    fn inserting_a_cached_script_evicts_nothing() {
+        let mut cache = ModuleCache::new(2);
+        cache.insert("(module)", module("(module)"));
+        cache.insert("(module (func))", module("(module (func))"));
+
+        cache.insert("(module)", module("(module)"));
+
+        assert!(cache.get("(module)").is_some());
+        assert!(cache.get("(module (func))").is_some());
+    }
+
+    /// Module whose `run` executes `body` then returns an empty output.
+    fn run_module(body: &str) -> ScriptRun {
+        let script = format!(
+            r#"(module
+                (import "env" "log" (func $log (param i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "run") (param i32 i32) (result i64) {} i64.const 0))"#,
+            body
+        );
+        run_wasm_script(&script, &json!({}), &None).unwrap()
+    }
+
+    #[test]
+    fn out_of_bounds_logs_fail_the_run() {
+        let run = run_module("(call $log (i32.const 0) (i32.const -1))");
+
+        assert!(!run.success);
+        assert!(run.logs.contains("Out of bounds memory access"));
+    }
+
+    #[test]
+    fn logs_are_capped() {
+        let run =
+            run_module("(loop $again (call $log (i32.const 0) (i32.const 65536)) (br $again))");
+
+        assert!(!run.success);
+        assert!(run.logs.len() <= LOG_LIMIT + 100);
+        assert!(run.logs.contains("Logs exceeded"));
+    }
+}
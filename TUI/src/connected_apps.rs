@@ -1,3 +1,5 @@
+use crate::shared::api_get_all;
+
 #[derive(Debug, serde::Deserialize, Clone)]
 pub struct ConnectedApp {
     pub id: String,
@@ -7,11 +9,5 @@ pub struct ConnectedApp {
 }
 
 pub async fn query_connected_apps() -> Result<Vec<ConnectedApp>, String> {
-    let result = reqwest::get("http://localhost:8080/connected-apps")
-        .await
-        .map_err(|e| e.to_string())?;
-    let body = result.text().await.map_err(|e| e.to_string())?;
-    let connected_apps: Vec<ConnectedApp> =
-        serde_json::from_str(&body).map_err(|e| e.to_string())?;
-    Ok(connected_apps)
+    api_get_all("http://localhost:8080/connected-apps".to_string()).await
 }
\ No newline at end of file
@@ -4,10 +4,9 @@ use ratatui::{
     text::Line,
     widgets::{Block, List, ListItem, ListState, StatefulWidget, Widget},
 };
-use reqwest;
 use std::sync::{Arc, RwLock};
 
-use crate::shared::LoadingState;
+use crate::shared::{LoadingState, api_get_all};
 
 #[derive(Clone, Debug, Default)]
 pub struct EntitySharingsWidget {
@@ -28,16 +27,12 @@ pub struct EntitySharing {
     pub id: String,
     created_at: i64,
     updated_at: i64,
+    /// `polling` or `push`, how the entities of the sharing are ingested.
+    mode: String,
 }
 
 async fn query_entity_sharings() -> Result<Vec<EntitySharing>, String> {
-    let result = reqwest::get("http://localhost:8080/entity-sharings")
-        .await
-        .map_err(|e| e.to_string())?;
-    let body = result.text().await.map_err(|e| e.to_string())?;
-    let entity_sharings: Vec<EntitySharing> =
-        serde_json::from_str(&body).map_err(|e| e.to_string())?;
-    Ok(entity_sharings)
+    api_get_all("http://localhost:8080/entity-sharings".to_string()).await
 }
 
 impl EntitySharingsWidget {
@@ -91,7 +86,7 @@ impl Widget for &EntitySharingsWidget {
             state
                 .entity_sharings
                 .iter()
-                .map(|sharing| ListItem::from(format!("{}", sharing.name))),
+                .map(|sharing| ListItem::from(format!("{} ({})", sharing.name, sharing.mode))),
         )
         .block(block)
         .highlight_symbol(">> ");
@@ -5,13 +5,12 @@ use ratatui::{
     text::Line,
     widgets::{Block, List, ListItem, ListState, StatefulWidget, Widget},
 };
-use reqwest;
 use std::sync::{Arc, RwLock};
 
 use crate::{
     connected_apps::{ConnectedApp, query_connected_apps},
     entity_sharings::EntitySharing,
-    shared::LoadingState,
+    shared::{LoadingState, api_get_all},
 };
 
 #[derive(Clone, Debug, Default)]
@@ -38,16 +37,11 @@ pub struct EntitySubscription {
 async fn query_entity_subscriptions(
     entity_sharing_id: &String,
 ) -> Result<Vec<EntitySubscription>, String> {
-    let result = reqwest::get(format!(
+    api_get_all(format!(
         "http://localhost:8080/entity-sharings/{}/subscriptions",
         entity_sharing_id
     ))
     .await
-    .map_err(|e| e.to_string())?;
-    let body = result.text().await.map_err(|e| e.to_string())?;
-    let entity_subscriptions: Vec<EntitySubscription> =
-        serde_json::from_str(&body).map_err(|e| e.to_string())?;
-    Ok(entity_subscriptions)
 }
 
 impl EntitySubscriptionsWidget {
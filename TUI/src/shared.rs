@@ -7,3 +7,34 @@ pub enum LoadingState {
     Error(String),
 }
 
+
+#[derive(Debug, serde::Deserialize)]
+struct Page<T> {
+    items: Vec<T>,
+    next_cursor: Option<String>,
+}
+
+/// Every item of a HEUTL web API list, page after page, authenticated with the key from
+/// `HEUTL_API_KEY`.
+pub async fn api_get_all<T: serde::de::DeserializeOwned>(url: String) -> Result<Vec<T>, String> {
+    let client = reqwest::Client::new();
+    let mut items = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut request = client.get(&url);
+        if let Ok(api_key) = std::env::var("HEUTL_API_KEY") {
+            request = request.bearer_auth(api_key);
+        }
+        if let Some(cursor) = &cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let result = request.send().await.map_err(|e| e.to_string())?;
+        let body = result.text().await.map_err(|e| e.to_string())?;
+        let page: Page<T> = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+        items.extend(page.items);
+        match page.next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => return Ok(items),
+        }
+    }
+}